@@ -35,8 +35,8 @@ pub fn hacky_as_hell_mcap_to_dataframe(p: &PathBuf) -> Result<DataFrame> {
                 continue;
             }
         }
-        f.write(&message.data)?;
-        f.write(b"\n")?;
+        f.write_all(&message.data)?;
+        f.write_all(b"\n")?;
     }
     f.flush()?;
     let reader = polars::io::ndjson::core::JsonLineReader::from_path("/tmp/foo");
@@ -47,8 +47,6 @@ pub fn hacky_as_hell_mcap_to_dataframe(p: &PathBuf) -> Result<DataFrame> {
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-
     #[test]
     fn it_works() {
         // hacky_as_hell_mcap_to_dataframe().unwrap();
@@ -0,0 +1,92 @@
+//! Times expressions on a large frame with and without `slang::optimize`.
+//! Run with `cargo bench -p slang`, optionally giving the number of rows.
+
+use std::time::{Duration, Instant};
+
+use polars::prelude::*;
+use slang::parser::Program;
+use slang::FunctionRegistry;
+
+const RUNS: usize = 5;
+
+const EXPRESSIONS: &[(&str, &str)] = &[
+    ("constants", "q.w * (180 / 3.141592653589793) + q.x * (2 ^ 10 / 4)"),
+    (
+        "repeated",
+        "sin(q.x * 2 - q.y) * sin(q.x * 2 - q.y) + cos(q.x * 2 - q.y) * cos(q.x * 2 - q.y)",
+    ),
+    (
+        "slang function",
+        "def mag(a, b) = (a * a + b * b) ^ 0.5\nmag(q.x - q.z, q.y - q.w) / mag(q.x - q.z, q.y - q.w)",
+    ),
+    (
+        "euler",
+        "roll(q.w * 2, q.x * 2, q.y * 2, q.z * 2) + pitch(q.w * 2, q.x * 2, q.y * 2, q.z * 2)",
+    ),
+    ("filter", "x - lowpass(x, 5, t) + lowpass(x, 5, t) * 2"),
+];
+
+fn frame(rows: usize) -> LazyFrame {
+    let t: Vec<f64> = (0..rows).map(|i| i as f64 / 1000.0).collect();
+    let wave = |phase: f64| -> Vec<f64> { t.iter().map(|t| (t + phase).sin()).collect() };
+    let q = StructChunked::from_series(
+        "q".into(),
+        rows,
+        [
+            Series::new("w".into(), wave(0.0)),
+            Series::new("x".into(), wave(1.0)),
+            Series::new("y".into(), wave(2.0)),
+            Series::new("z".into(), wave(3.0)),
+        ]
+        .iter(),
+    )
+    .unwrap();
+    DataFrame::new(vec![
+        Series::new("t".into(), t.clone()).into(),
+        Series::new("x".into(), wave(0.5)).into(),
+        q.into_series().into(),
+    ])
+    .unwrap()
+    .lazy()
+}
+
+/// The fastest of [`RUNS`] evaluations of `program` against `df`.
+fn time(df: &LazyFrame, program: &Program, functions: &FunctionRegistry) -> Duration {
+    let program = slang::to_polars_program(program, functions).unwrap();
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            program
+                .with_bindings(df.clone())
+                .select([program.result.clone()])
+                .collect()
+                .unwrap();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    // `cargo bench` passes `--bench`, take the first number given instead
+    let rows = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(2_000_000);
+    let df = frame(rows).collect().unwrap().lazy();
+    let functions = FunctionRegistry::with_builtins();
+
+    println!("{} rows, best of {} runs", rows, RUNS);
+    for (name, source) in EXPRESSIONS {
+        let program = slang::parse_program(source).unwrap();
+        let before = time(&df, &program, &functions);
+        let after = time(&df, &slang::optimize(&program, &functions), &functions);
+        println!(
+            "{:<16} {:>10.2?} -> {:>10.2?} ({:.2}x)",
+            name,
+            before,
+            after,
+            before.as_secs_f64() / after.as_secs_f64()
+        );
+    }
+}
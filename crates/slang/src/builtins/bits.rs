@@ -0,0 +1,190 @@
+//! Bit fields of integer columns, e.g. the flags packed into a status word.
+//! `&`, `|` and `xor` lower to polars directly, shifts are done here.
+
+use anyhow::Result;
+use polars::prelude::{
+    ChunkedArray, Column, DataType, GetOutput, IntoSeries, PolarsError, PolarsNumericType,
+    PolarsResult, Series,
+};
+use polars_lazy::prelude::*;
+
+use crate::functions::{ArgType::*, Arity, CallArgs, Function, FunctionRegistry};
+
+/// Widest integer shifts and bit fields work on.
+const WIDTH: i64 = 64;
+
+pub(crate) fn register(registry: &mut FunctionRegistry) {
+    registry.register(Function::new(
+        "bit",
+        Arity::Exact(2),
+        &[("x", Numeric), ("n", Literal)],
+        "Bit `n` of integer `x`, 0 or 1, counting from the least significant.",
+        |args| {
+            let n = bit_index(args, "bit", 1)?;
+            Ok(shift(args.expr(0), lit(n), Shift::Right).and(lit(1)))
+        },
+    ));
+    registry.register(Function::new(
+        "bits",
+        Arity::Exact(3),
+        &[("x", Numeric), ("lo", Literal), ("hi", Literal)],
+        "Bits `lo` to `hi` of integer `x`, inclusive, shifted down to start at bit 0.",
+        |args| {
+            let (lo, hi) = (bit_index(args, "bits", 1)?, bit_index(args, "bits", 2)?);
+            if lo > hi {
+                return Err(anyhow::anyhow!(
+                    "`bits` lo ({}) must not be above hi ({})",
+                    lo,
+                    hi
+                ));
+            }
+            let shifted = shift(args.expr(0), lit(lo), Shift::Right);
+            Ok(match hi - lo + 1 {
+                WIDTH => shifted,
+                width => shifted.and(lit((1i64 << width) - 1)),
+            })
+        },
+    ));
+}
+
+fn bit_index(args: &CallArgs<'_>, name: &str, index: usize) -> Result<i64> {
+    let n = args.int_literal(index)?;
+    if (0..WIDTH).contains(&n) {
+        Ok(n)
+    } else {
+        Err(anyhow::anyhow!(
+            "argument {} of `{}` must be a bit index from 0 to {}, found {}",
+            index + 1,
+            name,
+            WIDTH - 1,
+            n
+        ))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Shift {
+    Left,
+    Right,
+}
+
+/// `x << n` or `x >> n`. Signed integers shift as `Int64`, keeping their
+/// sign on the way right, and unsigned ones as `UInt64`. Shifts by a
+/// negative amount or by the full width or more are null.
+pub(crate) fn shift(x: Expr, n: Expr, direction: Shift) -> Expr {
+    let output = GetOutput::map_dtype(|dtype| {
+        Ok(match dtype.is_unsigned_integer() {
+            true => DataType::UInt64,
+            false => DataType::Int64,
+        })
+    });
+    x.map_many(
+        move |columns: &mut [Column]| {
+            let (x, n) = (columns[0].as_materialized_series(), &columns[1]);
+            if !x.dtype().is_integer() {
+                return Err(PolarsError::SchemaMismatch(
+                    format!("bit shifts need integers, found {}", x.dtype()).into(),
+                ));
+            }
+            let n = n.as_materialized_series().cast(&DataType::Int64)?;
+            let n = n.i64()?;
+            let shifted =
+                match x.dtype().is_unsigned_integer() {
+                    true => shift_values(x.cast(&DataType::UInt64)?.u64()?, n, |x: u64, n| {
+                        match direction {
+                            Shift::Left => x.checked_shl(n),
+                            Shift::Right => x.checked_shr(n),
+                        }
+                    })?,
+                    false => shift_values(x.cast(&DataType::Int64)?.i64()?, n, |x: i64, n| {
+                        match direction {
+                            Shift::Left => x.checked_shl(n),
+                            Shift::Right => x.checked_shr(n),
+                        }
+                    })?,
+                };
+            Ok(Some(shifted.with_name(x.name().clone()).into()))
+        },
+        &[n],
+        output,
+    )
+}
+
+fn shift_values<T, F>(
+    x: &ChunkedArray<T>,
+    n: &ChunkedArray<polars::prelude::Int64Type>,
+    f: F,
+) -> PolarsResult<Series>
+where
+    T: PolarsNumericType,
+    ChunkedArray<T>: IntoSeries,
+    F: Fn(T::Native, u32) -> Option<T::Native>,
+{
+    // Either side may be a single value, e.g. `1 << n`
+    let len = x.len().max(n.len());
+    if ![x.len(), n.len()]
+        .iter()
+        .all(|side| [1, len].contains(side))
+    {
+        return Err(PolarsError::ShapeMismatch(
+            format!("cannot shift {} rows by {} amounts", x.len(), n.len()).into(),
+        ));
+    }
+    let shifted: ChunkedArray<T> = (0..len)
+        .map(|row| {
+            let x = x.get(row.min(x.len() - 1))?;
+            let n = n.get(row.min(n.len() - 1))?;
+            f(x, u32::try_from(n).ok()?)
+        })
+        .collect();
+    Ok(shifted.into_series())
+}
+
+#[cfg(test)]
+mod tests {
+    use polars::prelude::{DataFrame, IntoLazy, NamedFrom, Series};
+
+    use crate::builtins::testing::{eval, select};
+
+    fn status() -> DataFrame {
+        DataFrame::new(vec![
+            Series::new("status".into(), [0b1010u32, 0b0110, 0xF0]).into(),
+            Series::new("signed".into(), [-8i32, 1, 3]).into(),
+        ])
+        .unwrap()
+    }
+
+    fn eval_dtype(source: &str) -> polars::prelude::DataType {
+        select(&status(), source).dtype().clone()
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(eval(&status(), "status & 6"), [2.0, 6.0, 0.0]);
+        assert_eq!(eval(&status(), "status | 1"), [11.0, 7.0, 241.0]);
+        assert_eq!(eval(&status(), "status xor 15"), [5.0, 9.0, 255.0]);
+        assert_eq!(eval(&status(), "status << 2"), [40.0, 24.0, 960.0]);
+        assert_eq!(eval(&status(), "status >> 1 & 1"), [1.0, 1.0, 0.0]);
+        assert_eq!(eval(&status(), "signed >> 2"), [-2.0, 0.0, 0.0]);
+        assert_eq!(eval(&status(), "1 << signed")[1..], [2.0, 8.0]);
+        assert!(eval(&status(), "1 << signed")[0].is_nan());
+
+        assert!(eval_dtype("status & 6").is_integer());
+        assert_eq!(eval_dtype("status << 1"), polars::prelude::DataType::UInt64);
+        assert_eq!(eval_dtype("signed >> 1"), polars::prelude::DataType::Int64);
+        assert!(crate::eval(&status().lazy(), "1.5 << 1").is_err());
+    }
+
+    #[test]
+    fn test_bit_fields() {
+        assert_eq!(eval(&status(), "bit(status, 1)"), [1.0, 1.0, 0.0]);
+        assert_eq!(eval(&status(), "bit(status, 2)"), [0.0, 1.0, 0.0]);
+        assert_eq!(eval(&status(), "bits(status, 4, 7)"), [0.0, 0.0, 15.0]);
+        assert_eq!(eval(&status(), "bits(status, 1, 2)"), [1.0, 3.0, 0.0]);
+        assert_eq!(eval(&status(), "bits(status, 0, 63)"), [10.0, 6.0, 240.0]);
+        assert!(eval_dtype("bit(status, 1)").is_integer());
+
+        assert!(crate::eval(&status().lazy(), "bit(status, 64)").is_err());
+        assert!(crate::eval(&status().lazy(), "bits(status, 3, 2)").is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use polars::prelude::DataType;
+use polars::series::ops::NullBehavior;
+use polars_lazy::prelude::*;
+
+use crate::functions::{ArgType::*, Arity, Function, FunctionRegistry};
+
+pub(crate) fn register(registry: &mut FunctionRegistry) {
+    registry.register(Function::new(
+        "diff",
+        Arity::Exact(1),
+        &[("x", Numeric)],
+        "Difference between each sample and the previous one, null for the first sample.",
+        |args| Ok(diff(args.expr(0))),
+    ));
+    registry.register(Function::new(
+        "cumsum",
+        Arity::Exact(1),
+        &[("x", Numeric)],
+        "Running total of `x`.",
+        |args| Ok(args.expr(0).cum_sum(false)),
+    ));
+    registry.register(Function::new(
+        "deriv",
+        Arity::Exact(2),
+        &[("y", Numeric), ("t", Numeric)],
+        "Derivative dy/dt, second order accurate for non-uniformly spaced `t`.",
+        |args| Ok(deriv(args.expr(0), args.expr(1))),
+    ));
+    registry.register(Function::new(
+        "integrate",
+        Arity::Exact(2),
+        &[("y", Numeric), ("t", Numeric)],
+        "Cumulative trapezoidal integral of `y` over `t`, starting from zero.",
+        |args| Ok(integrate(args.expr(0), args.expr(1))),
+    ));
+}
+
+fn diff(x: Expr) -> Expr {
+    x.cast(DataType::Float64).diff(1, NullBehavior::Ignore)
+}
+
+/// Central differences weighted by the spacing on each side, the same scheme as
+/// `numpy.gradient`, with one sided differences at the ends.
+fn deriv(y: Expr, t: Expr) -> Expr {
+    let y = y.cast(DataType::Float64);
+    let t = t.cast(DataType::Float64);
+    let (y_prev, y_next) = (y.clone().shift(lit(1)), y.clone().shift(lit(-1)));
+    let (t_prev, t_next) = (t.clone().shift(lit(1)), t.clone().shift(lit(-1)));
+
+    let h_prev = t.clone() - t_prev;
+    let h_next = t_next - t.clone();
+    let central = (h_prev.clone().pow(2) * y_next.clone() - h_next.clone().pow(2) * y_prev.clone()
+        + (h_next.clone().pow(2) - h_prev.clone().pow(2)) * y.clone())
+        / (h_prev.clone() * h_next.clone() * (h_prev.clone() + h_next.clone()));
+
+    let backward = (y.clone() - y_prev) / h_prev;
+    let forward = (y_next - y) / h_next;
+    central.fill_null(backward).fill_null(forward)
+}
+
+fn integrate(y: Expr, t: Expr) -> Expr {
+    let y = y.cast(DataType::Float64);
+    let area = (y.clone() + y.shift(lit(1))) / lit(2.0) * diff(t);
+    area.fill_null(lit(0.0)).cum_sum(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+
+    use crate::builtins::testing::{assert_close, eval};
+
+    /// Unevenly spaced samples of `t`, `t^2` and `sin(t)`.
+    fn signals() -> DataFrame {
+        let t: Vec<f64> = (0..200)
+            .map(|i| i as f64 * 0.01 + if i % 3 == 0 { 0.002 } else { 0.0 })
+            .collect();
+        let square: Vec<f64> = t.iter().map(|t| t * t).collect();
+        let sine: Vec<f64> = t.iter().map(|t| t.sin()).collect();
+        DataFrame::new(vec![
+            Series::new("t".into(), &t).into(),
+            Series::new("square".into(), square).into(),
+            Series::new("sine".into(), sine).into(),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_diff_and_cumsum() {
+        let df = DataFrame::new(vec![Series::new("x".into(), [1i64, 4, 9, 16]).into()]).unwrap();
+
+        let diff = eval(&df, "diff(x)");
+        assert!(diff[0].is_nan());
+        assert_eq!(diff[1..], [3.0, 5.0, 7.0]);
+        assert_eq!(eval(&df, "cumsum(x)"), vec![1.0, 5.0, 14.0, 30.0]);
+    }
+
+    #[test]
+    fn test_deriv() {
+        let df = signals();
+        let ts = eval(&df, "t");
+
+        // The central scheme is exact for quadratics, the ends are first order
+        let expected: Vec<f64> = ts.iter().map(|t| 2.0 * t).collect();
+        let n = ts.len();
+        assert_close(
+            &eval(&df, "deriv(square, t)")[1..n - 1],
+            &expected[1..n - 1],
+            1e-9,
+        );
+
+        let expected: Vec<f64> = ts.iter().map(|t| t.cos()).collect();
+        assert_close(&eval(&df, "deriv(sine, t)"), &expected, 1e-2);
+        assert_close(
+            &eval(&df, "deriv(sine, t)")[1..n - 1],
+            &expected[1..n - 1],
+            1e-4,
+        );
+    }
+
+    #[test]
+    fn test_integrate() {
+        let df = signals();
+        let ts = eval(&df, "t");
+
+        // Trapezoids are exact for linear integrands
+        let expected: Vec<f64> = ts.iter().map(|t| t * t - ts[0] * ts[0]).collect();
+        assert_close(&eval(&df, "integrate(2 * t, t)"), &expected, 1e-9);
+
+        let expected: Vec<f64> = ts.iter().map(|t| ts[0].cos() - t.cos()).collect();
+        assert_close(&eval(&df, "integrate(sine, t)"), &expected, 1e-4);
+    }
+}
@@ -0,0 +1,464 @@
+use anyhow::Result;
+use std::f64::consts::PI;
+
+use super::{map_f64, sample_rate};
+use crate::functions::{ArgType::*, Arity, CallArgs, Function, FunctionRegistry};
+
+const DEFAULT_ORDER: i64 = 2;
+const MAX_ORDER: i64 = 8;
+const DEFAULT_NOTCH_Q: f64 = 30.0;
+
+pub(crate) fn register(registry: &mut FunctionRegistry) {
+    let zero_phase_doc = "With `zero_phase` set to 1 the filter runs forwards and backwards \
+                          (filtfilt), doubling the order and removing the phase lag.";
+
+    registry.register(Function::new(
+        "lowpass",
+        Arity::Range(3, 5),
+        &[
+            ("x", Numeric),
+            ("cutoff_hz", Literal),
+            ("t", Numeric),
+            ("order", Literal),
+            ("zero_phase", Literal),
+        ],
+        &format!(
+            "Butterworth lowpass, `order` defaults to {}. {}",
+            DEFAULT_ORDER, zero_phase_doc
+        ),
+        |args| {
+            let cutoff = args.float_literal(1)?;
+            let order = order(args, 3)?;
+            apply(args, "lowpass", 2, 4, move |fs| {
+                Filter::lowpass(cutoff, fs, order)
+            })
+        },
+    ));
+    registry.register(Function::new(
+        "highpass",
+        Arity::Range(3, 5),
+        &[
+            ("x", Numeric),
+            ("cutoff_hz", Literal),
+            ("t", Numeric),
+            ("order", Literal),
+            ("zero_phase", Literal),
+        ],
+        &format!(
+            "Butterworth highpass, `order` defaults to {}. {}",
+            DEFAULT_ORDER, zero_phase_doc
+        ),
+        |args| {
+            let cutoff = args.float_literal(1)?;
+            let order = order(args, 3)?;
+            apply(args, "highpass", 2, 4, move |fs| {
+                Filter::highpass(cutoff, fs, order)
+            })
+        },
+    ));
+    registry.register(Function::new(
+        "bandpass",
+        Arity::Range(4, 6),
+        &[
+            ("x", Numeric),
+            ("low_hz", Literal),
+            ("high_hz", Literal),
+            ("t", Numeric),
+            ("order", Literal),
+            ("zero_phase", Literal),
+        ],
+        &format!(
+            "Butterworth highpass at `low_hz` followed by a lowpass at `high_hz`, \
+             `order` defaults to {}. {}",
+            DEFAULT_ORDER, zero_phase_doc
+        ),
+        |args| {
+            let (low, high) = (args.float_literal(1)?, args.float_literal(2)?);
+            if low >= high {
+                return Err(anyhow::anyhow!(
+                    "`bandpass` low_hz ({}) must be below high_hz ({})",
+                    low,
+                    high
+                ));
+            }
+            let order = order(args, 4)?;
+            apply(args, "bandpass", 3, 5, move |fs| {
+                let mut filter = Filter::highpass(low, fs, order)?;
+                filter
+                    .sections
+                    .extend(Filter::lowpass(high, fs, order)?.sections);
+                Ok(filter)
+            })
+        },
+    ));
+    registry.register(Function::new(
+        "notch",
+        Arity::Range(3, 5),
+        &[
+            ("x", Numeric),
+            ("freq_hz", Literal),
+            ("t", Numeric),
+            ("q", Literal),
+            ("zero_phase", Literal),
+        ],
+        &format!(
+            "Removes a narrow band around `freq_hz`, whose width is `freq_hz / q`, \
+             `q` defaults to {}. {}",
+            DEFAULT_NOTCH_Q, zero_phase_doc
+        ),
+        |args| {
+            let freq = args.float_literal(1)?;
+            let q = match args.len() > 3 {
+                true => args.float_literal(3)?,
+                false => DEFAULT_NOTCH_Q,
+            };
+            if q <= 0.0 {
+                return Err(anyhow::anyhow!("`notch` q must be positive, found {}", q));
+            }
+            apply(args, "notch", 2, 4, move |fs| Filter::notch(freq, fs, q))
+        },
+    ));
+}
+
+fn order(args: &CallArgs<'_>, index: usize) -> Result<usize> {
+    if args.len() <= index {
+        return Ok(DEFAULT_ORDER as usize);
+    }
+    match args.int_literal(index)? {
+        order @ 1..=MAX_ORDER => Ok(order as usize),
+        order => Err(anyhow::anyhow!(
+            "filter order must be between 1 and {}, found {}",
+            MAX_ORDER,
+            order
+        )),
+    }
+}
+
+/// Designs the filter once the sample rate of the time column at `t_index`
+/// is known, then runs it over the first argument.
+fn apply<F>(
+    args: &CallArgs<'_>,
+    name: &'static str,
+    t_index: usize,
+    zero_phase_index: usize,
+    design: F,
+) -> Result<polars_lazy::dsl::Expr>
+where
+    F: Fn(f64) -> Result<Filter> + Send + Sync + 'static,
+{
+    let zero_phase = match args.len() > zero_phase_index {
+        true => match args.int_literal(zero_phase_index)? {
+            0 => false,
+            1 => true,
+            other => {
+                return Err(anyhow::anyhow!(
+                    "`{}` zero_phase must be 0 or 1, found {}",
+                    name,
+                    other
+                ))
+            }
+        },
+        false => false,
+    };
+
+    Ok(map_f64(
+        name,
+        &[args.expr(0), args.expr(t_index)],
+        move |columns| {
+            let (x, t) = (&columns[0], &columns[1]);
+            if x.is_empty() {
+                return Ok(vec![]);
+            }
+            let filter = design(sample_rate(t)?)?;
+            Ok(filter.apply(x, zero_phase))
+        },
+    ))
+}
+
+/// Second order IIR section in transposed direct form II, `a0` normalized to one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+}
+
+impl Biquad {
+    /// Bilinear transform of `1 / (s^2 + s/q + 1)`, `k` is the prewarped
+    /// cutoff `tan(pi * fc / fs)`.
+    fn lowpass(k: f64, q: f64) -> Self {
+        let norm = 1.0 / (1.0 + k / q + k * k);
+        let b0 = k * k * norm;
+        Self {
+            b: [b0, 2.0 * b0, b0],
+            a: [2.0 * (k * k - 1.0) * norm, (1.0 - k / q + k * k) * norm],
+        }
+    }
+
+    fn highpass(k: f64, q: f64) -> Self {
+        let norm = 1.0 / (1.0 + k / q + k * k);
+        Self {
+            b: [norm, -2.0 * norm, norm],
+            a: [2.0 * (k * k - 1.0) * norm, (1.0 - k / q + k * k) * norm],
+        }
+    }
+
+    fn first_order_lowpass(k: f64) -> Self {
+        let b0 = k / (1.0 + k);
+        Self {
+            b: [b0, b0, 0.0],
+            a: [(k - 1.0) / (k + 1.0), 0.0],
+        }
+    }
+
+    fn first_order_highpass(k: f64) -> Self {
+        let b0 = 1.0 / (1.0 + k);
+        Self {
+            b: [b0, -b0, 0.0],
+            a: [(k - 1.0) / (k + 1.0), 0.0],
+        }
+    }
+
+    fn notch(k: f64, q: f64) -> Self {
+        let norm = 1.0 / (1.0 + k / q + k * k);
+        let b0 = (1.0 + k * k) * norm;
+        let b1 = 2.0 * (k * k - 1.0) * norm;
+        Self {
+            b: [b0, b1, b0],
+            a: [b1, (1.0 - k / q + k * k) * norm],
+        }
+    }
+
+    fn dc_gain(&self) -> f64 {
+        self.b.iter().sum::<f64>() / (1.0 + self.a[0] + self.a[1])
+    }
+
+    /// Filters `x` in place. The state starts as if `x[0]` had been the input
+    /// forever, which avoids a large transient at the start of the signal.
+    fn filter(&self, x: &mut [f64]) {
+        let ([b0, b1, b2], [a1, a2]) = (self.b, self.a);
+        let Some(&first) = x.first() else {
+            return;
+        };
+
+        let settled = self.dc_gain() * first;
+        let mut z2 = b2 * first - a2 * settled;
+        let mut z1 = b1 * first - a1 * settled + z2;
+        for value in x.iter_mut() {
+            let input = *value;
+            let output = b0 * input + z1;
+            z1 = b1 * input - a1 * output + z2;
+            z2 = b2 * input - a2 * output;
+            *value = output;
+        }
+    }
+}
+
+/// A cascade of second order sections.
+#[derive(Clone, Debug, PartialEq)]
+struct Filter {
+    sections: Vec<Biquad>,
+}
+
+impl Filter {
+    fn lowpass(cutoff: f64, fs: f64, order: usize) -> Result<Self> {
+        let k = prewarp(cutoff, fs)?;
+        Ok(Self::butterworth(order, |q| match q {
+            Some(q) => Biquad::lowpass(k, q),
+            None => Biquad::first_order_lowpass(k),
+        }))
+    }
+
+    fn highpass(cutoff: f64, fs: f64, order: usize) -> Result<Self> {
+        let k = prewarp(cutoff, fs)?;
+        Ok(Self::butterworth(order, |q| match q {
+            Some(q) => Biquad::highpass(k, q),
+            None => Biquad::first_order_highpass(k),
+        }))
+    }
+
+    fn notch(freq: f64, fs: f64, q: f64) -> Result<Self> {
+        Ok(Self {
+            sections: vec![Biquad::notch(prewarp(freq, fs)?, q)],
+        })
+    }
+
+    /// Pairs the Butterworth poles into second order sections with quality
+    /// factor `q`, plus a first order section (`None`) for odd orders.
+    fn butterworth(order: usize, section: impl Fn(Option<f64>) -> Biquad) -> Self {
+        let n = order as f64;
+        let mut sections: Vec<Biquad> = (0..order / 2)
+            .map(|k| {
+                let angle = PI * (n - 2.0 * k as f64 - 1.0) / (2.0 * n);
+                section(Some(1.0 / (2.0 * angle.cos())))
+            })
+            .collect();
+        if order % 2 == 1 {
+            sections.push(section(None));
+        }
+        Self { sections }
+    }
+
+    fn apply(&self, x: &[f64], zero_phase: bool) -> Vec<f64> {
+        if !zero_phase {
+            let mut y = x.to_vec();
+            self.sections.iter().for_each(|s| s.filter(&mut y));
+            return y;
+        }
+
+        // Extend both ends with a point reflection so the backwards pass
+        // starts from a continuation of the signal, like scipy's filtfilt.
+        let pad = (3 * (2 * self.sections.len() + 1)).min(x.len() - 1);
+        let (first, last) = (x[0], x[x.len() - 1]);
+        let mut y: Vec<f64> = (1..=pad)
+            .rev()
+            .map(|i| 2.0 * first - x[i])
+            .chain(x.iter().copied())
+            .chain((1..=pad).map(|i| 2.0 * last - x[x.len() - 1 - i]))
+            .collect();
+
+        self.sections.iter().for_each(|s| s.filter(&mut y));
+        y.reverse();
+        self.sections.iter().for_each(|s| s.filter(&mut y));
+        y.reverse();
+
+        y[pad..pad + x.len()].to_vec()
+    }
+}
+
+fn prewarp(freq: f64, fs: f64) -> Result<f64> {
+    if !(freq > 0.0 && freq < fs / 2.0) {
+        return Err(anyhow::anyhow!(
+            "frequency {} Hz must be between 0 and the Nyquist frequency {} Hz",
+            freq,
+            fs / 2.0
+        ));
+    }
+    Ok((PI * freq / fs).tan())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::{DataFrame, IntoLazy, NamedFrom, Series};
+
+    use crate::builtins::testing::assert_close;
+
+    /// Magnitude of the frequency response at `freq`.
+    fn gain(filter: &Filter, freq: f64, fs: f64) -> f64 {
+        let w = 2.0 * PI * freq / fs;
+        filter
+            .sections
+            .iter()
+            .map(|s| {
+                // Evaluate b(z) / a(z) at z = e^{jw}
+                let eval = |c: [f64; 3]| {
+                    let re = c[0] + c[1] * w.cos() + c[2] * (2.0 * w).cos();
+                    let im = -c[1] * w.sin() - c[2] * (2.0 * w).sin();
+                    (re * re + im * im).sqrt()
+                };
+                eval(s.b) / eval([1.0, s.a[0], s.a[1]])
+            })
+            .product()
+    }
+
+    #[test]
+    fn test_butterworth_coefficients() {
+        // scipy.signal.butter(2, 0.2)
+        let filter = Filter::lowpass(10.0, 100.0, 2).unwrap();
+        let expected = Biquad {
+            b: [0.06745527, 0.13491055, 0.06745527],
+            a: [-1.1429805, 0.4128016],
+        };
+        for (actual, expected) in filter.sections[0]
+            .b
+            .iter()
+            .chain(&filter.sections[0].a)
+            .zip(expected.b.iter().chain(&expected.a))
+        {
+            assert_close(&[*actual], &[*expected], 1e-7);
+        }
+
+        // scipy.signal.butter(2, 0.2, "highpass")
+        let filter = Filter::highpass(10.0, 100.0, 2).unwrap();
+        assert_close(&[filter.sections[0].b[0]], &[0.63894553], 1e-7);
+        assert_close(&[filter.sections[0].a[0]], &[-1.1429805], 1e-7);
+    }
+
+    #[test]
+    fn test_butterworth_response() {
+        let fs = 1000.0;
+        for order in 1..=MAX_ORDER as usize {
+            let lowpass = Filter::lowpass(50.0, fs, order).unwrap();
+            assert_eq!(lowpass.sections.len(), order.div_ceil(2));
+            assert_close(&[gain(&lowpass, 0.0, fs)], &[1.0], 1e-9);
+            assert_close(&[gain(&lowpass, 50.0, fs)], &[0.5f64.sqrt()], 1e-9);
+            assert!(gain(&lowpass, 200.0, fs) < 0.3f64.powi(order as i32));
+
+            let highpass = Filter::highpass(50.0, fs, order).unwrap();
+            assert_close(&[gain(&highpass, 50.0, fs)], &[0.5f64.sqrt()], 1e-9);
+            assert_close(&[gain(&highpass, fs / 2.0, fs)], &[1.0], 1e-9);
+            assert!(gain(&highpass, 10.0, fs) < 0.3f64.powi(order as i32));
+        }
+
+        let notch = Filter::notch(60.0, fs, 30.0).unwrap();
+        assert!(gain(&notch, 60.0, fs) < 1e-9);
+        assert_close(&[gain(&notch, 0.0, fs)], &[1.0], 1e-9);
+        assert_close(&[gain(&notch, 120.0, fs)], &[1.0], 1e-2);
+
+        assert!(Filter::lowpass(600.0, fs, 2).is_err());
+        assert!(Filter::lowpass(0.0, fs, 2).is_err());
+    }
+
+    fn signal() -> DataFrame {
+        // 1 Hz tone with 60 Hz interference sampled at 1 kHz
+        let t: Vec<f64> = (0..2000).map(|i| i as f64 / 1000.0).collect();
+        let slow: Vec<f64> = t.iter().map(|t| (2.0 * PI * t).sin()).collect();
+        let x: Vec<f64> = t
+            .iter()
+            .zip(&slow)
+            .map(|(t, slow)| slow + 0.5 * (2.0 * PI * 60.0 * t).sin())
+            .collect();
+        DataFrame::new(vec![
+            Series::new("t".into(), t).into(),
+            Series::new("slow".into(), slow).into(),
+            Series::new("x".into(), x).into(),
+        ])
+        .unwrap()
+    }
+
+    fn max_error(df: &DataFrame, expr: &str) -> f64 {
+        let df = df.clone().lazy();
+        let actual = crate::eval(&df, expr).unwrap().remove(0).data();
+        let expected = crate::eval(&df, "slow").unwrap().remove(0).data();
+        // Skip the edges where the filters settle
+        actual[200..1800]
+            .iter()
+            .zip(&expected[200..1800])
+            .map(|(a, e)| (a - e).abs())
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn test_filter_signal() {
+        let df = signal();
+
+        // Causal filters lag the 1 Hz tone, zero phase ones don't
+        let causal = max_error(&df, "lowpass(x, 10, t, 4)");
+        assert!(causal < 0.3);
+        assert!(max_error(&df, "lowpass(x, 10, t, 4, 1)") < causal / 100.0);
+        assert!(max_error(&df, "notch(x, 60, t, 5, 1)") < 1e-2);
+        assert!(max_error(&df, "x - highpass(x, 10, t, 4, 1)") < 1e-2);
+        assert!(max_error(&df, "x - bandpass(x, 20, 200, t, 4, 1)") < 1e-2);
+    }
+
+    #[test]
+    fn test_filter_errors() {
+        let df = signal().lazy();
+        assert!(crate::eval(&df, "lowpass(x, 10, t, 9)").is_err());
+        assert!(crate::eval(&df, "lowpass(x, 10, t, 2, 3)").is_err());
+        assert!(crate::eval(&df, "lowpass(x, 600, t)").is_err());
+        assert!(crate::eval(&df, "lowpass(x, cutoff, t)").is_err());
+        assert!(crate::eval(&df, "bandpass(x, 90, 30, t)").is_err());
+        assert!(crate::eval(&df, "notch(x, 60, t, 0)").is_err());
+    }
+}
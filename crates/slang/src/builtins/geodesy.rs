@@ -0,0 +1,331 @@
+//! Positions on the WGS-84 ellipsoid. Latitude and longitude are in degrees,
+//! altitude above the ellipsoid and everything else in meters.
+
+use anyhow::Result;
+use polars::prelude::{DataType, GetOutput};
+use polars_lazy::prelude::*;
+
+use super::{map_columns, rows, struct_of, struct_type};
+use crate::functions::{ArgType::*, Arity, CallArgs, Function, FunctionRegistry};
+
+/// Semi-major axis.
+const A: f64 = 6_378_137.0;
+/// Flattening.
+const F: f64 = 1.0 / 298.257_223_563;
+/// Semi-minor axis.
+const B: f64 = A * (1.0 - F);
+/// First eccentricity squared.
+const E2: f64 = F * (2.0 - F);
+/// Second eccentricity squared.
+const EP2: f64 = E2 / (1.0 - E2);
+/// Mean radius `(2a + b) / 3`, for distances on a sphere.
+const MEAN_RADIUS: f64 = (2.0 * A + B) / 3.0;
+
+pub(crate) fn register(registry: &mut FunctionRegistry) {
+    registry.register(Function::new(
+        "lla_to_ecef",
+        Arity::Exact(3),
+        &[("lat", Numeric), ("lon", Numeric), ("alt", Numeric)],
+        "Earth centered, earth fixed `x, y, z` of a latitude, longitude and altitude.",
+        |args| {
+            Ok(map_coordinates(
+                "lla_to_ecef",
+                args.exprs(),
+                &["x", "y", "z"],
+                |row| lla_to_ecef([row[0], row[1], row[2]]).to_vec(),
+            ))
+        },
+    ));
+    registry.register(Function::new(
+        "ecef_to_lla",
+        Arity::Exact(3),
+        &[("x", Numeric), ("y", Numeric), ("z", Numeric)],
+        "Latitude, longitude and altitude of an earth centered, earth fixed position.",
+        |args| {
+            Ok(map_coordinates(
+                "ecef_to_lla",
+                args.exprs(),
+                &["lat", "lon", "alt"],
+                |row| ecef_to_lla([row[0], row[1], row[2]]).to_vec(),
+            ))
+        },
+    ));
+    registry.register(Function::new(
+        "lla_to_enu",
+        Arity::OneOf(&[3, 6]),
+        &[
+            ("lat", Numeric),
+            ("lon", Numeric),
+            ("alt", Numeric),
+            ("lat0", Numeric),
+            ("lon0", Numeric),
+            ("alt0", Numeric),
+        ],
+        "East, north and up from the origin `lat0, lon0, alt0`, which defaults \
+         to the first sample without nulls.",
+        |args| local_frame("lla_to_enu", args, &["e", "n", "u"], |[e, n, u]| [e, n, u]),
+    ));
+    registry.register(Function::new(
+        "lla_to_ned",
+        Arity::OneOf(&[3, 6]),
+        &[
+            ("lat", Numeric),
+            ("lon", Numeric),
+            ("alt", Numeric),
+            ("lat0", Numeric),
+            ("lon0", Numeric),
+            ("alt0", Numeric),
+        ],
+        "North, east and down from the origin `lat0, lon0, alt0`, which defaults \
+         to the first sample without nulls.",
+        |args| local_frame("lla_to_ned", args, &["n", "e", "d"], |[e, n, u]| [n, e, -u]),
+    ));
+    registry.register(Function::new(
+        "haversine",
+        Arity::Exact(4),
+        &[
+            ("lat1", Numeric),
+            ("lon1", Numeric),
+            ("lat2", Numeric),
+            ("lon2", Numeric),
+        ],
+        "Great circle distance between two positions on a sphere of the WGS-84 mean radius.",
+        |args| {
+            let [lat1, lon1, lat2, lon2] = [0, 1, 2, 3].map(|i| args.expr(i).radians());
+            let a = ((lat2.clone() - lat1.clone()) / lit(2.0)).sin().pow(2)
+                + lat1.cos() * lat2.cos() * ((lon2 - lon1) / lit(2.0)).sin().pow(2);
+            Ok(lit(2.0 * MEAN_RADIUS) * a.sqrt().arcsin())
+        },
+    ));
+}
+
+/// Lowers `lla_to_enu` or `lla_to_ned`, `frame` reorders east, north and up.
+fn local_frame(
+    name: &'static str,
+    args: &CallArgs<'_>,
+    fields: &'static [&'static str],
+    frame: fn([f64; 3]) -> [f64; 3],
+) -> Result<Expr> {
+    match args.len() {
+        3 => Ok(map_positions(
+            name,
+            args.exprs(),
+            fields,
+            move |positions| {
+                let origin = positions.iter().flatten().next().cloned();
+                positions
+                    .iter()
+                    .map(|position| {
+                        let [lat0, lon0, alt0] = origin.as_deref()?[..] else {
+                            unreachable!("the origin has three coordinates");
+                        };
+                        let [lat, lon, alt] = position.as_deref()?[..] else {
+                            unreachable!("positions have three coordinates");
+                        };
+                        Some(frame(lla_to_enu([lat, lon, alt], [lat0, lon0, alt0])).to_vec())
+                    })
+                    .collect()
+            },
+        )),
+        6 => Ok(map_coordinates(name, args.exprs(), fields, move |row| {
+            frame(lla_to_enu(
+                [row[0], row[1], row[2]],
+                [row[3], row[4], row[5]],
+            ))
+            .to_vec()
+        })),
+        found => Err(anyhow::anyhow!(
+            "`{}` takes an origin of `lat0, lon0, alt0` or none, found {} arguments",
+            name,
+            found
+        )),
+    }
+}
+
+fn lla_to_ecef([lat, lon, alt]: [f64; 3]) -> [f64; 3] {
+    let (sin_lat, cos_lat) = lat.to_radians().sin_cos();
+    let (sin_lon, cos_lon) = lon.to_radians().sin_cos();
+    // Prime vertical radius of curvature
+    let n = A / (1.0 - E2 * sin_lat * sin_lat).sqrt();
+    [
+        (n + alt) * cos_lat * cos_lon,
+        (n + alt) * cos_lat * sin_lon,
+        (n * (1.0 - E2) + alt) * sin_lat,
+    ]
+}
+
+/// Bowring's method, accurate to well under a millimeter near the surface.
+fn ecef_to_lla([x, y, z]: [f64; 3]) -> [f64; 3] {
+    let p = x.hypot(y);
+    let theta = (z * A).atan2(p * B);
+    let (sin_theta, cos_theta) = theta.sin_cos();
+    let lat = (z + EP2 * B * sin_theta.powi(3)).atan2(p - E2 * A * cos_theta.powi(3));
+    let (sin_lat, cos_lat) = lat.sin_cos();
+    let alt = p * cos_lat + z * sin_lat - A * (1.0 - E2 * sin_lat * sin_lat).sqrt();
+    [lat.to_degrees(), y.atan2(x).to_degrees(), alt]
+}
+
+fn lla_to_enu(lla: [f64; 3], origin: [f64; 3]) -> [f64; 3] {
+    let [x, y, z] = lla_to_ecef(lla);
+    let [x0, y0, z0] = lla_to_ecef(origin);
+    let [dx, dy, dz] = [x - x0, y - y0, z - z0];
+    let (sin_lat, cos_lat) = origin[0].to_radians().sin_cos();
+    let (sin_lon, cos_lon) = origin[1].to_radians().sin_cos();
+    [
+        -sin_lon * dx + cos_lon * dy,
+        -sin_lat * cos_lon * dx - sin_lat * sin_lon * dy + cos_lat * dz,
+        cos_lat * cos_lon * dx + cos_lat * sin_lon * dy + sin_lat * dz,
+    ]
+}
+
+/// Runs `f` on the coordinates of one row at a time, one per argument in
+/// order, producing a struct of `fields`. Arguments of a single row, e.g.
+/// literals, are broadcast and rows with a null anywhere are null.
+fn map_coordinates<F>(
+    name: &'static str,
+    args: &[Expr],
+    fields: &'static [&'static str],
+    f: F,
+) -> Expr
+where
+    F: Fn(&[f64]) -> Vec<f64> + Send + Sync + 'static,
+{
+    map_positions(name, args, fields, move |positions| {
+        positions
+            .iter()
+            .map(|position| position.as_deref().map(&f))
+            .collect()
+    })
+}
+
+/// Runs `f` over the rows of every argument at once, cast to `f64`,
+/// producing a struct of `fields` from the rows it returns.
+fn map_positions<F>(
+    name: &'static str,
+    args: &[Expr],
+    fields: &'static [&'static str],
+    f: F,
+) -> Expr
+where
+    F: Fn(&[Option<Vec<f64>>]) -> Vec<Option<Vec<f64>>> + Send + Sync + 'static,
+{
+    map_columns(
+        name,
+        args,
+        GetOutput::from_type(struct_type(fields)),
+        |_, column| {
+            let series = column.as_materialized_series().cast(&DataType::Float64)?;
+            Ok(series
+                .f64()?
+                .iter()
+                .map(|value| value.filter(|value| !value.is_nan()))
+                .collect::<Vec<_>>())
+        },
+        move |column, inputs| {
+            let positions: Vec<Option<Vec<f64>>> = rows(inputs)
+                .map(|row| row.map(|row| row.into_iter().copied().collect()))
+                .collect();
+            let fields = fields.iter().map(|field| (*field).into());
+            Ok(struct_of(column.name().clone(), fields, &f(&positions))?)
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+
+    use crate::builtins::testing::{assert_close, eval, eval_all};
+
+    /// 53°48'33.82"N 2°07'46.38"E at 73 m, from EPSG guidance note 7-2.
+    const EPSG_LLA: [f64; 3] = [
+        53.0 + 48.0 / 60.0 + 33.82 / 3600.0,
+        2.0 + 7.0 / 60.0 + 46.38 / 3600.0,
+        73.0,
+    ];
+    const EPSG_ECEF: [f64; 3] = [3_771_793.968, 140_253.342, 5_124_304.349];
+
+    #[test]
+    fn test_ecef() {
+        assert_close(&lla_to_ecef(EPSG_LLA), &EPSG_ECEF, 1e-3);
+        assert_close(&lla_to_ecef([0.0, 90.0, 0.0]), &[0.0, A, 0.0], 1e-6);
+        assert_close(
+            &lla_to_ecef([-90.0, 0.0, 10.0]),
+            &[0.0, 0.0, -B - 10.0],
+            1e-6,
+        );
+
+        assert_close(&ecef_to_lla(EPSG_ECEF), &EPSG_LLA, 1e-3);
+        for lla in [
+            [0.0, 0.0, 0.0],
+            [89.999, -45.0, 1000.0],
+            [-33.9, 151.2, -20.0],
+        ] {
+            let round_trip = ecef_to_lla(lla_to_ecef(lla));
+            assert_close(&round_trip[..2], &lla[..2], 1e-9);
+            assert_close(&round_trip[2..], &lla[2..], 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_enu() {
+        let origin = [0.0, 0.0, 0.0];
+        // Along the equator the exact position is a chord of the circle of radius `a`
+        let angle = 0.01f64.to_radians();
+        assert_close(
+            &lla_to_enu([0.0, 0.01, 0.0], origin),
+            &[A * angle.sin(), 0.0, A * angle.cos() - A],
+            1e-6,
+        );
+        assert_close(
+            &lla_to_enu([EPSG_LLA[0], EPSG_LLA[1], 173.0], EPSG_LLA),
+            &[0.0, 0.0, 100.0],
+            1e-6,
+        );
+    }
+
+    fn gps() -> DataFrame {
+        DataFrame::new(vec![
+            Series::new("lat".into(), [None, Some(36.12), Some(33.94)]).into(),
+            Series::new("lon".into(), [Some(-86.0), Some(-86.67), Some(-118.40)]).into(),
+            Series::new("alt".into(), [Some(0.0), Some(10.0), Some(10.0)]).into(),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_haversine() {
+        // Nashville to Los Angeles, 2887.26 km on a sphere of radius 6372.8 km
+        let distance = eval(&gps(), "haversine(36.12, -86.67, 33.94, -118.40)")[0];
+        assert_close(
+            &[distance],
+            &[2_887_259.950_607_11 * MEAN_RADIUS / 6_372_800.0],
+            1e-3,
+        );
+    }
+
+    #[test]
+    fn test_columns() {
+        let enu = eval_all(&gps(), "lla_to_enu(lat, lon, alt)");
+        assert!(enu[0][0].is_nan());
+        assert_eq!(enu[0][1], 0.0);
+        assert!(enu[0][2] < -2_000_000.0);
+        // 2887 km away is well below the horizon
+        assert!(enu[2][2] < -500_000.0);
+
+        let ned = eval_all(&gps(), "lla_to_ned(lat, lon, alt, 36.12, -86.67, 0)");
+        assert_close(&[ned[2][1]], &[-10.0], 1e-6);
+        assert_eq!(ned[1][2], enu[0][2]);
+
+        let lla = eval_all(&gps(), "ecef_to_lla(lla_to_ecef(lat, lon, alt).x, lla_to_ecef(lat, lon, alt).y, lla_to_ecef(lat, lon, alt).z)");
+        assert!(lla[0][0].is_nan());
+        assert_close(&[lla[1][2]], &[-118.40], 1e-9);
+
+        let expr = crate::to_polars_expr(&crate::parse("lla_to_enu(lat, lon, alt, 1)").unwrap());
+        assert_eq!(
+            expr.err().unwrap().to_string(),
+            "`lla_to_enu(lat, lon, alt[, lat0, lon0, alt0])` takes 3 or 6 arguments, found 4"
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! Builtin functions beyond basic math, grouped by topic. Each module adds
+//! its functions to the registry in `register`.
+
+pub(crate) mod bits;
+mod calculus;
+mod filter;
+mod geodesy;
+pub(crate) mod resample;
+mod rolling;
+mod rotation;
+pub(crate) mod spectral;
+pub(crate) mod strings;
+#[cfg(test)]
+mod testing;
+mod unwrap;
+mod vector;
+
+use anyhow::Result;
+use polars::prelude::{
+    Column, DataFrame, DataType, Field, GetOutput, IntoSeries, NamedFrom, PlSmallStr, PolarsError,
+    PolarsResult, Series,
+};
+use polars_lazy::prelude::*;
+
+use super::functions::FunctionRegistry;
+
+pub(crate) fn register(registry: &mut FunctionRegistry) {
+    bits::register(registry);
+    calculus::register(registry);
+    filter::register(registry);
+    geodesy::register(registry);
+    resample::register(registry);
+    rolling::register(registry);
+    rotation::register(registry);
+    spectral::register(registry);
+    strings::register(registry);
+    unwrap::register(registry);
+    vector::register(registry);
+}
+
+/// Runs `f` over the whole of every argument at once, for functions that
+/// depend on sample order (filters, scans) and can't be written as polars
+/// expressions. Arguments are cast to `f64` and must not contain nulls.
+pub(crate) fn map_f64<F>(name: &'static str, args: &[Expr], f: F) -> Expr
+where
+    F: Fn(&[Vec<f64>]) -> Result<Vec<f64>> + Send + Sync + 'static,
+{
+    let (first, rest) = args.split_first().expect("map_f64 needs an argument");
+    first.clone().apply_many(
+        move |columns: &mut [Column]| {
+            let values = columns
+                .iter()
+                .map(|column| f64_values(name, column))
+                .collect::<Result<Vec<_>, _>>()?;
+            let out = f(&values)
+                .map_err(|e| PolarsError::ComputeError(format!("`{}`: {}", name, e).into()))?;
+            Ok(Some(Series::new(columns[0].name().clone(), out).into()))
+        },
+        rest,
+        GetOutput::from_type(DataType::Float64),
+    )
+}
+
+/// Runs `f` over the whole of every argument at once, each read by `read`
+/// from its position and column, for functions producing a struct or vector
+/// per row, see [`rows`] and [`struct_of`]. Arguments must have as many rows
+/// as each other, or a single row, e.g. a literal, to be broadcast.
+pub(crate) fn map_columns<T, R, F>(
+    name: &'static str,
+    args: &[Expr],
+    output: GetOutput,
+    read: R,
+    f: F,
+) -> Expr
+where
+    R: Fn(usize, &Column) -> PolarsResult<Vec<T>> + Send + Sync + 'static,
+    F: Fn(&Column, &[Vec<T>]) -> Result<Series> + Send + Sync + 'static,
+{
+    let (first, rest) = args.split_first().expect("map_columns needs an argument");
+    first.clone().apply_many(
+        move |columns: &mut [Column]| {
+            let inputs = columns
+                .iter()
+                .enumerate()
+                .map(|(index, column)| read(index, column))
+                .collect::<PolarsResult<Vec<_>>>()?;
+            let len = inputs.iter().map(Vec::len).max().unwrap_or(0);
+            if let Some(input) = inputs.iter().find(|input| ![1, len].contains(&input.len())) {
+                return Err(PolarsError::ShapeMismatch(
+                    format!("`{}` arguments have {} and {} rows", name, input.len(), len).into(),
+                ));
+            }
+            let out = f(&columns[0], &inputs)
+                .map_err(|e| PolarsError::ComputeError(format!("`{}`: {}", name, e).into()))?;
+            Ok(Some(out.into()))
+        },
+        rest,
+        output,
+    )
+}
+
+/// Every row of `inputs`, broadcasting inputs of a single row, `None` where
+/// any of them is null.
+pub(crate) fn rows<T>(inputs: &[Vec<Option<T>>]) -> impl Iterator<Item = Option<Vec<&T>>> {
+    let len = inputs.iter().map(Vec::len).max().unwrap_or(0);
+    (0..len).map(move |index| {
+        inputs
+            .iter()
+            .map(|input| input[index.min(input.len() - 1)].as_ref())
+            .collect()
+    })
+}
+
+/// A struct of `fields`, each taking its values from its place in every row,
+/// rows that are `None` are null.
+pub(crate) fn struct_of(
+    name: PlSmallStr,
+    fields: impl IntoIterator<Item = PlSmallStr>,
+    rows: &[Option<Vec<f64>>],
+) -> PolarsResult<Series> {
+    let columns = fields
+        .into_iter()
+        .enumerate()
+        .map(|(index, field)| {
+            let values: Vec<Option<f64>> = rows
+                .iter()
+                .map(|row| row.as_ref().and_then(|row| row.get(index).copied()))
+                .collect();
+            Series::new(field, values).into()
+        })
+        .collect();
+    Ok(DataFrame::new(columns)?.into_struct(name).into_series())
+}
+
+/// The type [`struct_of`] builds from `fields`.
+pub(crate) fn struct_type(fields: &[&str]) -> DataType {
+    DataType::Struct(
+        fields
+            .iter()
+            .map(|field| Field::new((*field).into(), DataType::Float64))
+            .collect(),
+    )
+}
+
+fn f64_values(name: &str, column: &Column) -> Result<Vec<f64>, PolarsError> {
+    let series = column.as_materialized_series().cast(&DataType::Float64)?;
+    if series.null_count() > 0 {
+        return Err(PolarsError::ComputeError(
+            format!(
+                "`{}` needs every sample, `{}` has {} nulls",
+                name,
+                series.name(),
+                series.null_count()
+            )
+            .into(),
+        ));
+    }
+    Ok(series.f64()?.into_no_null_iter().collect())
+}
+
+/// Sample rate in Hz of the time column `t` in seconds, from the median
+/// spacing so dropped samples don't skew it.
+pub(crate) fn sample_rate(t: &[f64]) -> Result<f64> {
+    let mut dt: Vec<f64> = t
+        .windows(2)
+        .map(|pair| pair[1] - pair[0])
+        .filter(|dt| *dt > 0.0)
+        .collect();
+    if dt.is_empty() {
+        return Err(anyhow::anyhow!(
+            "can't find a sample rate, the time column needs at least two increasing samples"
+        ));
+    }
+
+    dt.sort_by(f64::total_cmp);
+    Ok(1.0 / dt[dt.len() / 2])
+}
@@ -0,0 +1,330 @@
+use anyhow::Result;
+use polars::prelude::{
+    Column, DataFrame, DataType, Field, GetOutput, IntoSeries, NamedFrom, PolarsError,
+    PolarsResult, Series,
+};
+use std::str::FromStr;
+
+use super::rolling::parse_duration;
+use crate::functions::{ArgType::*, Arity, CallArgs, Function, FunctionRegistry};
+use crate::X_FIELD;
+
+/// Resampled signals longer than this are almost certainly a unit mistake,
+/// e.g. a period in seconds against a time column in nanoseconds.
+const MAX_SAMPLES: f64 = 100_000_000.0;
+
+pub(crate) fn register(registry: &mut FunctionRegistry) {
+    registry.register(Function::new(
+        "interp",
+        Arity::Range(3, 4),
+        &[
+            ("y", Numeric),
+            ("t_src", Numeric),
+            ("t_dst", Numeric),
+            ("method", Literal),
+        ],
+        "Signal `y` sampled at times `t_src`, interpolated at times `t_dst`. \
+         `method` is \"linear\" (default), \"previous\" or \"nearest\".",
+        |args| {
+            let method = method(args, 3)?;
+            let output = GetOutput::from_type(DataType::Float64);
+            Ok(args.expr(0).apply_many(
+                move |columns: &mut [Column]| {
+                    let [y, t, t_new] = [&columns[0], &columns[1], &columns[2]].map(values);
+                    let out = interpolate(&t?, &y?, &t_new?, method);
+                    let out: Vec<Option<f64>> = out
+                        .into_iter()
+                        .map(|value| Some(value).filter(|value| !value.is_nan()))
+                        .collect();
+                    Ok(Some(Series::new(columns[0].name().clone(), out).into()))
+                },
+                &args.exprs()[1..3],
+                output,
+            ))
+        },
+    ));
+    registry.register(
+        Function::new(
+            "resample",
+            Arity::Range(3, 4),
+            &[
+                ("y", Numeric),
+                ("t", Numeric),
+                ("period", Literal),
+                ("method", Literal),
+            ],
+            "Signal `y` sampled at times `t` in seconds, interpolated every `period` \
+         from the first sample, e.g. `resample(y, t, \"10ms\")`. Plotted against \
+         its own time axis. `method` is as for `interp`.",
+            |args| {
+                let period = match args.ast(2) {
+                    crate::parser::Expr::Str(_) => {
+                        parse_duration(args.string_literal(2)?)? as f64 / 1e9
+                    }
+                    _ => args.float_literal(2)?,
+                };
+                if !(period > 0.0 && period.is_finite()) {
+                    return Err(anyhow::anyhow!(
+                        "`resample` needs a positive period, found {}",
+                        period
+                    ));
+                }
+                let method = method(args, 3)?;
+
+                let output = DataType::Struct(vec![
+                    Field::new(X_FIELD.into(), DataType::Float64),
+                    Field::new("y".into(), DataType::Float64),
+                ]);
+                Ok(args.expr(0).apply_many(
+                    move |columns: &mut [Column]| {
+                        let (y, t) = (values(&columns[0])?, values(&columns[1])?);
+                        let (first, last) = t
+                            .iter()
+                            .filter(|t| !t.is_nan())
+                            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), t| {
+                                (min.min(*t), max.max(*t))
+                            });
+                        let samples = match first <= last {
+                            true => ((last - first) / period).floor() + 1.0,
+                            false => 0.0,
+                        };
+                        if samples > MAX_SAMPLES {
+                            return Err(PolarsError::ComputeError(
+                                format!(
+                                "`resample` would make {} samples, check the period is in seconds",
+                                samples
+                            )
+                                .into(),
+                            ));
+                        }
+
+                        let t_new: Vec<f64> = (0..samples as usize)
+                            .map(|i| first + i as f64 * period)
+                            .collect();
+                        let y_new = interpolate(&t, &y, &t_new, method);
+                        let df = DataFrame::new(vec![
+                            Series::new(X_FIELD.into(), t_new).into(),
+                            Series::new("y".into(), y_new).into(),
+                        ])?;
+                        Ok(Some(
+                            df.into_struct(columns[0].name().clone())
+                                .into_series()
+                                .into(),
+                        ))
+                    },
+                    &args.exprs()[1..2],
+                    GetOutput::from_type(output),
+                ))
+            },
+        )
+        .changing_length(),
+    );
+}
+
+fn method(args: &CallArgs<'_>, index: usize) -> Result<Interpolation> {
+    match index < args.len() {
+        true => args.string_literal(index)?.parse(),
+        false => Ok(Interpolation::Linear),
+    }
+}
+
+/// The values of `column` as `f64`, with nulls as NaN.
+fn values(column: &Column) -> PolarsResult<Vec<f64>> {
+    let series = column.as_materialized_series().cast(&DataType::Float64)?;
+    Ok(series
+        .f64()?
+        .iter()
+        .map(|value| value.unwrap_or(f64::NAN))
+        .collect())
+}
+
+/// How to estimate a signal between its samples.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Straight lines between samples, nothing outside of them.
+    #[default]
+    Linear,
+    /// The latest sample at or before, like a zero order hold.
+    Previous,
+    /// Whichever sample is closest, the earlier one on ties.
+    Nearest,
+}
+
+impl FromStr for Interpolation {
+    type Err = anyhow::Error;
+
+    fn from_str(method: &str) -> Result<Self> {
+        match method {
+            "linear" => Ok(Interpolation::Linear),
+            "previous" => Ok(Interpolation::Previous),
+            "nearest" => Ok(Interpolation::Nearest),
+            _ => Err(anyhow::anyhow!(
+                "unknown interpolation \"{}\", expected \"linear\", \"previous\" or \"nearest\"",
+                method
+            )),
+        }
+    }
+}
+
+/// Estimates the signal `y` sampled at times `t` at each of `t_new`, which
+/// needn't be sorted. Samples where either is NaN are ignored, and the
+/// result is NaN where there's nothing to estimate from.
+pub fn interpolate(t: &[f64], y: &[f64], t_new: &[f64], method: Interpolation) -> Vec<f64> {
+    let mut samples: Vec<(f64, f64)> = t
+        .iter()
+        .zip(y)
+        .filter(|(t, y)| !t.is_nan() && !y.is_nan())
+        .map(|(t, y)| (*t, *y))
+        .collect();
+    samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    t_new
+        .iter()
+        .map(|t| {
+            if t.is_nan() || samples.is_empty() {
+                return f64::NAN;
+            }
+            // Index of the first sample after `t`
+            let after = samples.partition_point(|(sample, _)| sample <= t);
+            let before = after.checked_sub(1).map(|index| samples[index]);
+            let after = samples.get(after).copied();
+            match (method, before, after) {
+                (_, Some((t0, y0)), _) if t0 == *t => y0,
+                (Interpolation::Linear, Some((t0, y0)), Some((t1, y1))) => {
+                    y0 + (y1 - y0) * (t - t0) / (t1 - t0)
+                }
+                (Interpolation::Previous, Some((_, y0)), _) => y0,
+                (Interpolation::Nearest, Some((t0, y0)), Some((t1, y1))) => {
+                    match t - t0 <= t1 - t {
+                        true => y0,
+                        false => y1,
+                    }
+                }
+                (Interpolation::Nearest, Some((_, y)), None)
+                | (Interpolation::Nearest, None, Some((_, y))) => y,
+                _ => f64::NAN,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::IntoLazy;
+
+    fn assert_same(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (index, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert!(
+                (a.is_nan() && e.is_nan()) || (a - e).abs() < 1e-9,
+                "{} != {} at {}",
+                a,
+                e,
+                index
+            );
+        }
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let t = [3.0, 1.0, 2.0, f64::NAN];
+        let y = [30.0, 10.0, f64::NAN, 40.0];
+        let t_new = [0.0, 1.0, 1.5, 2.5, 3.0, 4.0, f64::NAN];
+        let nan = f64::NAN;
+
+        assert_same(
+            &interpolate(&t, &y, &t_new, Interpolation::Linear),
+            &[nan, 10.0, 15.0, 25.0, 30.0, nan, nan],
+        );
+        assert_same(
+            &interpolate(&t, &y, &t_new, Interpolation::Previous),
+            &[nan, 10.0, 10.0, 10.0, 30.0, 30.0, nan],
+        );
+        assert_same(
+            &interpolate(&t, &y, &t_new, Interpolation::Nearest),
+            &[10.0, 10.0, 10.0, 30.0, 30.0, 30.0, nan],
+        );
+        assert_same(
+            &interpolate(&[], &[], &[1.0], Interpolation::Nearest),
+            &[nan],
+        );
+        assert!("cubic".parse::<Interpolation>().is_err());
+    }
+
+    fn topics() -> DataFrame {
+        // A fast topic every 0.1 s and a slow one every 0.5 s, nulls where
+        // each has no sample
+        let t: Vec<f64> = (0..11).map(|i| i as f64 / 10.0).collect();
+        let fast: Vec<Option<f64>> = t.iter().map(|t| Some(2.0 * t)).collect();
+        let slow: Vec<Option<f64>> = t
+            .iter()
+            .enumerate()
+            .map(|(i, t)| Some(*t).filter(|_| i % 5 == 0))
+            .collect();
+        DataFrame::new(vec![
+            Series::new("t".into(), t).into(),
+            Series::new("fast".into(), fast).into(),
+            Series::new("slow".into(), slow).into(),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_interp() {
+        let df = topics().lazy();
+        let traces = crate::eval(&df, "fast - interp(slow, t, t)").unwrap();
+        assert_same(
+            &traces[0].data(),
+            &(0..11).map(|i| i as f64 / 10.0).collect::<Vec<_>>(),
+        );
+
+        let traces = crate::eval(&df, "interp(slow, t, t, \"previous\")").unwrap();
+        assert_same(&traces[0].data()[4..6], &[0.0, 0.5]);
+        assert!(crate::eval(&df, "interp(slow, t, t, \"cubic\")").is_err());
+    }
+
+    #[test]
+    fn test_resample() {
+        let df = topics().lazy();
+        let traces = crate::eval(&df, "resample(fast, t, \"250ms\")").unwrap();
+        assert_same(traces[0].x.as_ref().unwrap(), &[0.0, 0.25, 0.5, 0.75, 1.0]);
+        assert_same(&traces[0].data(), &[0.0, 0.5, 1.0, 1.5, 2.0]);
+
+        let traces = crate::eval(&df, "resample(fast, t, 0.4, \"previous\")").unwrap();
+        assert_same(&traces[0].data(), &[0.0, 0.8, 1.6]);
+
+        assert!(crate::eval(&df, "resample(fast, t, 0)").is_err());
+        assert!(crate::eval(&df, "resample(fast, t, \"1ns\")").is_err());
+    }
+
+    #[test]
+    fn test_eval_at() {
+        let df = topics().lazy();
+        let time = crate::TimeBase {
+            t: "t".to_owned(),
+            reference: vec![0.25, 0.75, 2.0],
+            method: Interpolation::Linear,
+        };
+        let traces = crate::eval_at(&df, "slow * 2", &crate::Context::new(), &time).unwrap();
+        assert_eq!(traces[0].name, "slow * 2");
+        assert_same(&traces[0].data(), &[0.5, 1.5, f64::NAN]);
+        assert_eq!(traces[0].x, Some(time.reference.clone()));
+
+        assert!(
+            crate::eval_at(&df, "resample(fast, t, 0.5)", &crate::Context::new(), &time).is_err()
+        );
+
+        let time = crate::TimeBase {
+            t: "tt * 2".to_owned(),
+            ..time
+        };
+        let err = crate::eval_at(&df, "slow", &crate::Context::new(), &time)
+            .err()
+            .unwrap();
+        assert_eq!(
+            format!("{:#}", err),
+            "can't read the time of each sample from `tt * 2`: unknown column `tt`, did you mean `t`?"
+        );
+    }
+}
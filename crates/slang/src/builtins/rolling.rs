@@ -0,0 +1,271 @@
+use anyhow::Result;
+use polars::prelude::{
+    ClosedWindow, DataType, Duration, EWMOptions, RollingOptionsDynamicWindow,
+    RollingOptionsFixedWindow,
+};
+use polars_lazy::prelude::*;
+
+use crate::functions::{ArgType::*, Arity, CallArgs, Function, FunctionRegistry};
+use crate::parser;
+
+pub(crate) fn register(registry: &mut FunctionRegistry) {
+    for (name, kind, doc) in [
+        (
+            "rolling_mean",
+            Rolling::Mean,
+            "Mean over a trailing window.",
+        ),
+        (
+            "rolling_std",
+            Rolling::Std,
+            "Standard deviation over a trailing window.",
+        ),
+        (
+            "rolling_min",
+            Rolling::Min,
+            "Minimum over a trailing window.",
+        ),
+        (
+            "rolling_max",
+            Rolling::Max,
+            "Maximum over a trailing window.",
+        ),
+    ] {
+        registry.register(Function::new(
+            name,
+            Arity::Range(2, 3),
+            &[("x", Numeric), ("window", Literal), ("t", Numeric)],
+            &format!(
+                "{} `window` is a number of samples, or a duration like \"100ms\" \
+                 over the time column `t` in seconds.",
+                doc
+            ),
+            move |args| rolling(args, kind),
+        ));
+    }
+
+    registry.register(Function::new(
+        "median_filter",
+        Arity::Exact(2),
+        &[("x", Numeric), ("n", Literal)],
+        "Median over a window of `n` samples centered on each sample.",
+        |args| {
+            let options = RollingOptionsFixedWindow {
+                window_size: window_samples(args, 1)?,
+                min_periods: 1,
+                center: true,
+                ..Default::default()
+            };
+            Ok(args.expr(0).cast(DataType::Float64).rolling_median(options))
+        },
+    ));
+    registry.register(Function::new(
+        "ewm_mean",
+        Arity::Exact(2),
+        &[("x", Numeric), ("alpha", Literal)],
+        "Exponentially weighted moving average, `alpha` in (0, 1] is the weight of the newest sample.",
+        |args| {
+            let alpha = args.float_literal(1)?;
+            if !(alpha > 0.0 && alpha <= 1.0) {
+                return Err(anyhow::anyhow!(
+                    "`ewm_mean` alpha must be in (0, 1], found {}",
+                    alpha
+                ));
+            }
+
+            Ok(args.expr(0).cast(DataType::Float64).ewm_mean(EWMOptions {
+                alpha,
+                adjust: false,
+                ..Default::default()
+            }))
+        },
+    ));
+}
+
+#[derive(Clone, Copy)]
+enum Rolling {
+    Mean,
+    Std,
+    Min,
+    Max,
+}
+
+fn rolling(args: &CallArgs<'_>, kind: Rolling) -> Result<Expr> {
+    let x = args.expr(0).cast(DataType::Float64);
+
+    if let parser::Expr::Str(window) = args.ast(1) {
+        if args.len() < 3 {
+            return Err(anyhow::anyhow!(
+                "time based window \"{}\" needs a time column `t`",
+                window
+            ));
+        }
+
+        // Windows over integer nanoseconds, polars can't roll by float columns
+        let by = (args.expr(2).cast(DataType::Float64) * lit(1e9))
+            .round(0)
+            .cast(DataType::Int64);
+        let options = RollingOptionsDynamicWindow {
+            window_size: Duration::new(parse_duration(window)?),
+            min_periods: 1,
+            closed_window: ClosedWindow::Right,
+            fn_params: Default::default(),
+        };
+        return Ok(match kind {
+            Rolling::Mean => x.rolling_mean_by(by, options),
+            Rolling::Std => x.rolling_std_by(by, options),
+            Rolling::Min => x.rolling_min_by(by, options),
+            Rolling::Max => x.rolling_max_by(by, options),
+        });
+    }
+
+    if args.len() == 3 {
+        return Err(anyhow::anyhow!(
+            "a time column is only used with duration windows like \"100ms\""
+        ));
+    }
+    let options = RollingOptionsFixedWindow {
+        window_size: window_samples(args, 1)?,
+        min_periods: 1,
+        ..Default::default()
+    };
+    Ok(match kind {
+        Rolling::Mean => x.rolling_mean(options),
+        Rolling::Std => x.rolling_std(options),
+        Rolling::Min => x.rolling_min(options),
+        Rolling::Max => x.rolling_max(options),
+    })
+}
+
+fn window_samples(args: &CallArgs<'_>, index: usize) -> Result<usize> {
+    match args.int_literal(index)? {
+        n if n > 0 => Ok(n as usize),
+        n => Err(anyhow::anyhow!(
+            "window must be at least one sample, found {}",
+            n
+        )),
+    }
+}
+
+/// Parses durations like "100ms", "1.5s" or "1m30s" into nanoseconds.
+pub(super) fn parse_duration(duration: &str) -> Result<i64> {
+    let invalid = || {
+        anyhow::anyhow!(
+            "invalid duration \"{}\", expected a number and a unit (ns, us, ms, s, m, h), e.g. \"100ms\"",
+            duration
+        )
+    };
+
+    let mut total = 0.0;
+    let mut rest = duration.trim();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .ok_or_else(invalid)?;
+        let unit_end = rest[number_end..]
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .map_or(rest.len(), |end| number_end + end);
+
+        let number: f64 = rest[..number_end].parse().map_err(|_| invalid())?;
+        let scale = match &rest[number_end..unit_end] {
+            "ns" => 1.0,
+            "us" => 1e3,
+            "ms" => 1e6,
+            "s" => 1e9,
+            "m" => 60e9,
+            "h" => 3600e9,
+            _ => return Err(invalid()),
+        };
+        total += number * scale;
+        rest = &rest[unit_end..];
+    }
+
+    if total < 1.0 {
+        return Err(invalid());
+    }
+    Ok(total.round() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+
+    use crate::builtins::testing::eval;
+
+    fn signal() -> DataFrame {
+        DataFrame::new(vec![
+            Series::new("x".into(), [1.0, 2.0, 3.0, 10.0, 5.0, 6.0]).into(),
+            Series::new("t".into(), [0.0, 0.05, 0.1, 0.15, 0.2, 0.3]).into(),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("100ms").unwrap(), 100_000_000);
+        assert_eq!(parse_duration("1.5s").unwrap(), 1_500_000_000);
+        assert_eq!(parse_duration("1m30s").unwrap(), 90_000_000_000);
+        assert_eq!(parse_duration("250us").unwrap(), 250_000);
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("100").is_err());
+        assert!(parse_duration("ms").is_err());
+        assert!(parse_duration("10 parsecs").is_err());
+    }
+
+    #[test]
+    fn test_rolling_samples() {
+        let df = signal();
+        assert_eq!(
+            eval(&df, "rolling_mean(x, 3)"),
+            vec![1.0, 1.5, 2.0, 5.0, 6.0, 7.0]
+        );
+        assert_eq!(
+            eval(&df, "rolling_min(x, 2)"),
+            vec![1.0, 1.0, 2.0, 3.0, 5.0, 5.0]
+        );
+        assert_eq!(
+            eval(&df, "rolling_max(x, 2)"),
+            vec![1.0, 2.0, 3.0, 10.0, 10.0, 6.0]
+        );
+        assert_eq!(eval(&df, "rolling_std(x, 2)")[1], 0.5f64.sqrt());
+        assert_eq!(
+            eval(&df, "median_filter(x, 3)"),
+            vec![1.5, 2.0, 3.0, 5.0, 6.0, 5.5]
+        );
+    }
+
+    #[test]
+    fn test_rolling_duration() {
+        let df = signal();
+        // (t - 100ms, t] windows
+        assert_eq!(
+            eval(&df, "rolling_mean(x, \"100ms\", t)"),
+            vec![1.0, 1.5, 2.5, 6.5, 7.5, 6.0]
+        );
+        assert_eq!(
+            eval(&df, "rolling_max(x, \"0.1s\", t)"),
+            vec![1.0, 2.0, 3.0, 10.0, 10.0, 6.0]
+        );
+    }
+
+    #[test]
+    fn test_ewm_mean() {
+        let df = signal();
+        assert_eq!(eval(&df, "ewm_mean(x, 0.5)")[..4], [1.0, 1.5, 2.25, 6.125]);
+        assert_eq!(eval(&df, "ewm_mean(x, 1)"), eval(&df, "x"));
+    }
+
+    #[test]
+    fn test_rolling_errors() {
+        let df = signal().lazy();
+        assert!(crate::eval(&df, "rolling_mean(x, \"100ms\")").is_err());
+        assert!(crate::eval(&df, "rolling_mean(x, 3, t)").is_err());
+        assert!(crate::eval(&df, "rolling_mean(x, 0)").is_err());
+        assert!(crate::eval(&df, "rolling_mean(x, n)").is_err());
+        assert!(crate::eval(&df, "ewm_mean(x, 2)").is_err());
+    }
+}
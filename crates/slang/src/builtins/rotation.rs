@@ -0,0 +1,807 @@
+//! Quaternions, rotation matrices and Euler angles.
+//!
+//! Quaternions, vectors and matrices are passed as a struct column with named
+//! fields (`w`, `x`, `y`, `z` / `x`, `y`, `z` / `m00` .. `m22`) or as a list
+//! column with the components in order, matrices in row major order. Results
+//! are structs, so they can be passed straight on to another function.
+//!
+//! Conventions are given as trailing string options in any order:
+//! - `"wxyz"` (default) or `"xyzw"`, the component order of list quaternions
+//!   and of quaternion results.
+//! - `"hamilton"` (default) or `"jpl"`. A JPL quaternion rotates the opposite
+//!   way to the Hamilton quaternion with the same components, and JPL products
+//!   multiply in the opposite order.
+//! - `"ZYX"` (default) or `"XYZ"`, the intrinsic Euler sequence, so `"ZYX"`
+//!   is yaw, pitch then roll.
+//! - `"rad"` (default) or `"deg"`, the unit of Euler angles.
+
+use anyhow::Result;
+use polars::prelude::{Column, DataType, GetOutput, PolarsError, PolarsResult};
+use polars_lazy::prelude::*;
+
+use super::{map_columns, rows, struct_of, struct_type};
+use crate::functions::{ArgType::*, Arity, CallArgs, Function, FunctionRegistry};
+
+pub(crate) fn register(registry: &mut FunctionRegistry) {
+    registry.register(Function::new(
+        "quat_mul",
+        Arity::Range(2, 4),
+        &[
+            ("q", Any),
+            ("p", Any),
+            ("order", Literal),
+            ("convention", Literal),
+        ],
+        "Product `q ⊗ p` of two quaternions, rotating by `p` then `q`.",
+        |args| {
+            let options = Options::parse(args, 2, &[Kind::Order, Kind::Convention])?;
+            Ok(map_components(
+                "quat_mul",
+                &[
+                    (args.expr(0), options.quaternion()),
+                    (args.expr(1), options.quaternion()),
+                ],
+                options.quaternion(),
+                move |rows| {
+                    let q = options.read_quaternion(rows[0]);
+                    let p = options.read_quaternion(rows[1]);
+                    options.write_quaternion(q.mul(&p))
+                },
+            ))
+        },
+    ));
+    registry.register(Function::new(
+        "quat_conj",
+        Arity::Range(1, 2),
+        &[("q", Any), ("order", Literal)],
+        "Conjugate of quaternion `q`, the inverse rotation of a unit quaternion.",
+        |args| {
+            let options = Options::parse(args, 1, &[Kind::Order])?;
+            Ok(map_components(
+                "quat_conj",
+                &[(args.expr(0), options.quaternion())],
+                options.quaternion(),
+                move |rows| {
+                    let q = options.read_quaternion(rows[0]);
+                    options.write_quaternion(q.conj())
+                },
+            ))
+        },
+    ));
+    registry.register(Function::new(
+        "quat_normalize",
+        Arity::Range(1, 2),
+        &[("q", Any), ("order", Literal)],
+        "Quaternion `q` scaled to unit length.",
+        |args| {
+            let options = Options::parse(args, 1, &[Kind::Order])?;
+            Ok(map_components(
+                "quat_normalize",
+                &[(args.expr(0), options.quaternion())],
+                options.quaternion(),
+                move |rows| {
+                    let q = options.read_quaternion(rows[0]);
+                    options.write_quaternion(q.normalize())
+                },
+            ))
+        },
+    ));
+    registry.register(Function::new(
+        "quat_rotate",
+        Arity::Range(2, 4),
+        &[
+            ("q", Any),
+            ("v", Any),
+            ("order", Literal),
+            ("convention", Literal),
+        ],
+        "Vector `v` rotated by quaternion `q`, which is normalized first.",
+        |args| {
+            let options = Options::parse(args, 2, &[Kind::Order, Kind::Convention])?;
+            Ok(map_components(
+                "quat_rotate",
+                &[
+                    (args.expr(0), options.quaternion()),
+                    (args.expr(1), Layout::Fields(&VECTOR)),
+                ],
+                Layout::Fields(&VECTOR),
+                move |rows| {
+                    let q = options.read_quaternion(rows[0]);
+                    q.rotate([rows[1][0], rows[1][1], rows[1][2]]).to_vec()
+                },
+            ))
+        },
+    ));
+    registry.register(Function::new(
+        "quat_to_matrix",
+        Arity::Range(1, 3),
+        &[("q", Any), ("order", Literal), ("convention", Literal)],
+        "Rotation matrix of quaternion `q` as fields `m00` .. `m22`, \
+         which rotates column vectors like `quat_rotate`.",
+        |args| {
+            let options = Options::parse(args, 1, &[Kind::Order, Kind::Convention])?;
+            Ok(map_components(
+                "quat_to_matrix",
+                &[(args.expr(0), options.quaternion())],
+                Layout::Fields(&MATRIX),
+                move |rows| {
+                    let q = options.read_quaternion(rows[0]);
+                    q.to_matrix().concat()
+                },
+            ))
+        },
+    ));
+    registry.register(Function::new(
+        "matrix_to_quat",
+        Arity::Range(1, 3),
+        &[("m", Any), ("order", Literal), ("convention", Literal)],
+        "Unit quaternion, with `w >= 0`, of rotation matrix `m`.",
+        |args| {
+            let options = Options::parse(args, 1, &[Kind::Order, Kind::Convention])?;
+            Ok(map_components(
+                "matrix_to_quat",
+                &[(args.expr(0), Layout::Fields(&MATRIX))],
+                options.quaternion(),
+                move |rows| {
+                    let m = rows[0];
+                    let q = Quaternion::from_matrix([
+                        [m[0], m[1], m[2]],
+                        [m[3], m[4], m[5]],
+                        [m[6], m[7], m[8]],
+                    ]);
+                    options.write_quaternion(q)
+                },
+            ))
+        },
+    ));
+    registry.register(Function::new(
+        "quat_to_euler",
+        Arity::Range(1, 5),
+        &[
+            ("q", Any),
+            ("sequence", Literal),
+            ("unit", Literal),
+            ("order", Literal),
+            ("convention", Literal),
+        ],
+        "Euler angles of quaternion `q`, with fields named by axis in sequence \
+         order, e.g. `quat_to_euler(q, \"deg\").z` is yaw in degrees.",
+        |args| {
+            let options = Options::parse(
+                args,
+                1,
+                &[Kind::Sequence, Kind::Unit, Kind::Order, Kind::Convention],
+            )?;
+            Ok(map_components(
+                "quat_to_euler",
+                &[(args.expr(0), options.quaternion())],
+                Layout::Fields(options.sequence.axes()),
+                move |rows| {
+                    let q = options.read_quaternion(rows[0]);
+                    let angles = q.to_euler(options.sequence);
+                    angles
+                        .map(|angle| angle * options.unit.per_radian())
+                        .to_vec()
+                },
+            ))
+        },
+    ));
+    registry.register(Function::new(
+        "euler_to_quat",
+        Arity::Range(3, 7),
+        &[
+            ("a", Numeric),
+            ("b", Numeric),
+            ("c", Numeric),
+            ("sequence", Literal),
+            ("unit", Literal),
+            ("order", Literal),
+            ("convention", Literal),
+        ],
+        "Unit quaternion of Euler angles `a, b, c` about the axes of the \
+         sequence in order, e.g. yaw, pitch and roll for `\"ZYX\"`.",
+        |args| {
+            let options = Options::parse(
+                args,
+                3,
+                &[Kind::Sequence, Kind::Unit, Kind::Order, Kind::Convention],
+            )?;
+            Ok(map_components(
+                "euler_to_quat",
+                &[
+                    (args.expr(0), Layout::Scalar),
+                    (args.expr(1), Layout::Scalar),
+                    (args.expr(2), Layout::Scalar),
+                ],
+                options.quaternion(),
+                move |rows| {
+                    let angles = [rows[0][0], rows[1][0], rows[2][0]]
+                        .map(|angle| angle / options.unit.per_radian());
+                    options.write_quaternion(Quaternion::from_euler(angles, options.sequence))
+                },
+            ))
+        },
+    ));
+}
+
+const WXYZ: [&str; 4] = ["w", "x", "y", "z"];
+const XYZW: [&str; 4] = ["x", "y", "z", "w"];
+const VECTOR: [&str; 3] = ["x", "y", "z"];
+const MATRIX: [&str; 9] = [
+    "m00", "m01", "m02", "m10", "m11", "m12", "m20", "m21", "m22",
+];
+
+/// How the values of an argument or result are laid out.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Layout {
+    /// A plain number per row.
+    Scalar,
+    /// A struct with these fields, or a list with these components in order.
+    Fields(&'static [&'static str]),
+}
+
+impl Layout {
+    fn len(&self) -> usize {
+        match self {
+            Layout::Scalar => 1,
+            Layout::Fields(fields) => fields.len(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Order,
+    Convention,
+    Sequence,
+    Unit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Order {
+    Wxyz,
+    Xyzw,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Convention {
+    Hamilton,
+    Jpl,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Sequence {
+    Zyx,
+    Xyz,
+}
+
+impl Sequence {
+    fn axes(&self) -> &'static [&'static str] {
+        match self {
+            Sequence::Zyx => &["z", "y", "x"],
+            Sequence::Xyz => &["x", "y", "z"],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Unit {
+    Radians,
+    Degrees,
+}
+
+impl Unit {
+    /// Size of a radian in this unit.
+    fn per_radian(self) -> f64 {
+        match self {
+            Unit::Radians => 1.0,
+            Unit::Degrees => 180.0 / std::f64::consts::PI,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Options {
+    order: Order,
+    convention: Convention,
+    sequence: Sequence,
+    unit: Unit,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            order: Order::Wxyz,
+            convention: Convention::Hamilton,
+            sequence: Sequence::Zyx,
+            unit: Unit::Radians,
+        }
+    }
+}
+
+impl Options {
+    /// Reads the string options from argument `from` on, each of a kind in `allowed`.
+    fn parse(args: &CallArgs<'_>, from: usize, allowed: &[Kind]) -> Result<Self> {
+        let mut options = Self::default();
+        let mut seen = vec![];
+        for index in from..args.len() {
+            let option = args.string_literal(index)?;
+            let kind = match option {
+                "wxyz" => Kind::Order,
+                "xyzw" => Kind::Order,
+                "hamilton" => Kind::Convention,
+                "jpl" => Kind::Convention,
+                "ZYX" => Kind::Sequence,
+                "XYZ" => Kind::Sequence,
+                "rad" => Kind::Unit,
+                "deg" => Kind::Unit,
+                _ => return Err(anyhow::anyhow!("unknown rotation option \"{}\"", option)),
+            };
+            if !allowed.contains(&kind) {
+                return Err(anyhow::anyhow!(
+                    "\"{}\" is not an option of this function",
+                    option
+                ));
+            }
+            if seen.contains(&kind) {
+                return Err(anyhow::anyhow!(
+                    "\"{}\" conflicts with an earlier option",
+                    option
+                ));
+            }
+            seen.push(kind);
+
+            match option {
+                "wxyz" => options.order = Order::Wxyz,
+                "xyzw" => options.order = Order::Xyzw,
+                "hamilton" => options.convention = Convention::Hamilton,
+                "jpl" => options.convention = Convention::Jpl,
+                "ZYX" => options.sequence = Sequence::Zyx,
+                "XYZ" => options.sequence = Sequence::Xyz,
+                "rad" => options.unit = Unit::Radians,
+                _ => options.unit = Unit::Degrees,
+            }
+        }
+        Ok(options)
+    }
+
+    fn quaternion(&self) -> Layout {
+        match self.order {
+            Order::Wxyz => Layout::Fields(&WXYZ),
+            Order::Xyzw => Layout::Fields(&XYZW),
+        }
+    }
+
+    /// The Hamilton quaternion of a row laid out as [`Options::quaternion`].
+    fn read_quaternion(&self, row: &[f64]) -> Quaternion {
+        let q = match self.order {
+            Order::Wxyz => Quaternion::new(row[0], row[1], row[2], row[3]),
+            Order::Xyzw => Quaternion::new(row[3], row[0], row[1], row[2]),
+        };
+        match self.convention {
+            Convention::Hamilton => q,
+            Convention::Jpl => q.conj(),
+        }
+    }
+
+    fn write_quaternion(&self, q: Quaternion) -> Vec<f64> {
+        let q = match self.convention {
+            Convention::Hamilton => q,
+            Convention::Jpl => q.conj(),
+        };
+        match self.order {
+            Order::Wxyz => vec![q.w, q.x, q.y, q.z],
+            Order::Xyzw => vec![q.x, q.y, q.z, q.w],
+        }
+    }
+}
+
+/// A Hamilton quaternion.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Quaternion {
+    w: f64,
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+impl Quaternion {
+    fn new(w: f64, x: f64, y: f64, z: f64) -> Self {
+        Self { w, x, y, z }
+    }
+
+    /// Rotation by `angle` radians about `axis`, 0 for x through 2 for z.
+    fn about_axis(axis: usize, angle: f64) -> Self {
+        let (sin, cos) = (angle / 2.0).sin_cos();
+        let mut v = [0.0; 3];
+        v[axis] = sin;
+        Self::new(cos, v[0], v[1], v[2])
+    }
+
+    fn mul(&self, p: &Self) -> Self {
+        let q = self;
+        Self::new(
+            q.w * p.w - q.x * p.x - q.y * p.y - q.z * p.z,
+            q.w * p.x + q.x * p.w + q.y * p.z - q.z * p.y,
+            q.w * p.y - q.x * p.z + q.y * p.w + q.z * p.x,
+            q.w * p.z + q.x * p.y - q.y * p.x + q.z * p.w,
+        )
+    }
+
+    fn conj(&self) -> Self {
+        Self::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    fn normalize(&self) -> Self {
+        let norm = (self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z).sqrt();
+        Self::new(self.w / norm, self.x / norm, self.y / norm, self.z / norm)
+    }
+
+    fn rotate(&self, v: [f64; 3]) -> [f64; 3] {
+        let m = self.to_matrix();
+        m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+    }
+
+    fn to_matrix(self) -> [[f64; 3]; 3] {
+        let Self { w, x, y, z } = self.normalize();
+        [
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - z * w),
+                2.0 * (x * z + y * w),
+            ],
+            [
+                2.0 * (x * y + z * w),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - x * w),
+            ],
+            [
+                2.0 * (x * z - y * w),
+                2.0 * (y * z + x * w),
+                1.0 - 2.0 * (x * x + y * y),
+            ],
+        ]
+    }
+
+    /// Shepperd's method, picking the best conditioned of four formulas.
+    fn from_matrix(m: [[f64; 3]; 3]) -> Self {
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        let q = if trace > 0.0 {
+            let s = 2.0 * (1.0 + trace).sqrt();
+            Self::new(
+                s / 4.0,
+                (m[2][1] - m[1][2]) / s,
+                (m[0][2] - m[2][0]) / s,
+                (m[1][0] - m[0][1]) / s,
+            )
+        } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+            let s = 2.0 * (1.0 + m[0][0] - m[1][1] - m[2][2]).sqrt();
+            Self::new(
+                (m[2][1] - m[1][2]) / s,
+                s / 4.0,
+                (m[0][1] + m[1][0]) / s,
+                (m[0][2] + m[2][0]) / s,
+            )
+        } else if m[1][1] > m[2][2] {
+            let s = 2.0 * (1.0 + m[1][1] - m[0][0] - m[2][2]).sqrt();
+            Self::new(
+                (m[0][2] - m[2][0]) / s,
+                (m[0][1] + m[1][0]) / s,
+                s / 4.0,
+                (m[1][2] + m[2][1]) / s,
+            )
+        } else {
+            let s = 2.0 * (1.0 + m[2][2] - m[0][0] - m[1][1]).sqrt();
+            Self::new(
+                (m[1][0] - m[0][1]) / s,
+                (m[0][2] + m[2][0]) / s,
+                (m[1][2] + m[2][1]) / s,
+                s / 4.0,
+            )
+        };
+        match q.w < 0.0 {
+            true => Self::new(-q.w, -q.x, -q.y, -q.z),
+            false => q,
+        }
+    }
+
+    fn from_euler(angles: [f64; 3], sequence: Sequence) -> Self {
+        let [a, b, c] = angles;
+        match sequence {
+            Sequence::Zyx => Self::about_axis(2, a)
+                .mul(&Self::about_axis(1, b))
+                .mul(&Self::about_axis(0, c)),
+            Sequence::Xyz => Self::about_axis(0, a)
+                .mul(&Self::about_axis(1, b))
+                .mul(&Self::about_axis(2, c)),
+        }
+    }
+
+    /// Angles in radians, the middle one in [-π/2, π/2]. At gimbal lock the
+    /// last angle is 0.
+    fn to_euler(self, sequence: Sequence) -> [f64; 3] {
+        const GIMBAL_LOCK: f64 = 1.0 - 1e-12;
+        let m = self.to_matrix();
+        match sequence {
+            // Rz(a) Ry(b) Rx(c)
+            Sequence::Zyx => {
+                let sin_b = (-m[2][0]).clamp(-1.0, 1.0);
+                if sin_b.abs() < GIMBAL_LOCK {
+                    [m[1][0].atan2(m[0][0]), sin_b.asin(), m[2][1].atan2(m[2][2])]
+                } else {
+                    [(-m[0][1]).atan2(m[1][1]), sin_b.asin(), 0.0]
+                }
+            }
+            // Rx(a) Ry(b) Rz(c)
+            Sequence::Xyz => {
+                let sin_b = m[0][2].clamp(-1.0, 1.0);
+                if sin_b.abs() < GIMBAL_LOCK {
+                    [
+                        (-m[1][2]).atan2(m[2][2]),
+                        sin_b.asin(),
+                        (-m[0][1]).atan2(m[0][0]),
+                    ]
+                } else {
+                    [m[2][1].atan2(m[1][1]), sin_b.asin(), 0.0]
+                }
+            }
+        }
+    }
+}
+
+/// Runs `f` on the components of one row at a time, a slice per argument
+/// read as its [`Layout`], producing a struct laid out as `output`. Arguments of a single row, e.g.
+/// literals, are broadcast and rows with a null anywhere are null.
+fn map_components<F>(name: &'static str, args: &[(Expr, Layout)], output: Layout, f: F) -> Expr
+where
+    F: Fn(&[&[f64]]) -> Vec<f64> + Send + Sync + 'static,
+{
+    let Layout::Fields(fields) = output else {
+        unreachable!("rotations produce structs");
+    };
+    let layouts: Vec<Layout> = args.iter().map(|(_, layout)| *layout).collect();
+    let exprs: Vec<Expr> = args.iter().map(|(expr, _)| expr.clone()).collect();
+
+    map_columns(
+        name,
+        &exprs,
+        GetOutput::from_type(struct_type(fields)),
+        move |index, column| read_rows(name, column, layouts[index]),
+        move |column, inputs| {
+            let out: Vec<Option<Vec<f64>>> = rows(inputs)
+                .map(|row| row.map(|row| f(&row.iter().map(|v| v.as_slice()).collect::<Vec<_>>())))
+                .collect();
+            let fields = fields.iter().map(|field| (*field).into());
+            Ok(struct_of(column.name().clone(), fields, &out)?)
+        },
+    )
+}
+
+/// The components of every row of `column`, `None` where any are null.
+fn read_rows(name: &str, column: &Column, layout: Layout) -> PolarsResult<Vec<Option<Vec<f64>>>> {
+    let series = column.as_materialized_series();
+    let components: Vec<Vec<Option<f64>>> = match (layout, series.dtype()) {
+        (Layout::Fields(fields), DataType::Struct(_)) => {
+            let structs = series.struct_()?;
+            let nulls = series.is_null();
+            fields
+                .iter()
+                .map(|field| {
+                    let values = structs.field_by_name(field)?.cast(&DataType::Float64)?;
+                    Ok(values
+                        .f64()?
+                        .iter()
+                        .zip(nulls.iter())
+                        .map(|(value, null)| value.filter(|_| null != Some(true)))
+                        .collect())
+                })
+                .collect::<PolarsResult<_>>()?
+        }
+        (Layout::Fields(fields), DataType::List(_)) => {
+            let lists = series.cast(&DataType::List(Box::new(DataType::Float64)))?;
+            let mut components = vec![Vec::with_capacity(series.len()); fields.len()];
+            for list in lists.list()?.into_iter() {
+                let values: Vec<Option<f64>> = match list {
+                    Some(list) => list.f64()?.iter().collect(),
+                    None => vec![None; fields.len()],
+                };
+                if values.len() != fields.len() {
+                    return Err(PolarsError::ShapeMismatch(
+                        format!(
+                            "`{}` expects lists of {} ({}), found a list of {}",
+                            name,
+                            fields.len(),
+                            fields.join(", "),
+                            values.len()
+                        )
+                        .into(),
+                    ));
+                }
+                components
+                    .iter_mut()
+                    .zip(values)
+                    .for_each(|(component, value)| component.push(value));
+            }
+            components
+        }
+        (Layout::Scalar, dtype) if dtype.is_numeric() => {
+            vec![series.cast(&DataType::Float64)?.f64()?.iter().collect()]
+        }
+        (layout, dtype) => {
+            return Err(PolarsError::SchemaMismatch(
+                format!(
+                    "`{}` expects {}, found {}",
+                    name,
+                    match layout {
+                        Layout::Scalar => "a number".to_owned(),
+                        Layout::Fields(fields) =>
+                            format!("a struct or list of {}", fields.join(", ")),
+                    },
+                    dtype
+                )
+                .into(),
+            ));
+        }
+    };
+
+    debug_assert_eq!(components.len(), layout.len());
+    Ok((0..series.len())
+        .map(|row| components.iter().map(|component| component[row]).collect())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::{DataFrame, IntoSeries, NamedFrom, Series, StructChunked};
+    use std::f64::consts::FRAC_PI_2;
+
+    use crate::builtins::testing::{assert_close, eval_all};
+
+    const EPSILON: f64 = 1e-9;
+
+    fn components(q: Quaternion) -> [f64; 4] {
+        [q.w, q.x, q.y, q.z]
+    }
+
+    #[test]
+    fn test_rotate() {
+        let q = Quaternion::about_axis(2, FRAC_PI_2);
+        assert_close(&q.rotate([1.0, 0.0, 0.0]), &[0.0, 1.0, 0.0], EPSILON);
+        assert_close(
+            &q.to_matrix().concat(),
+            &[0.0, -1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0],
+            EPSILON,
+        );
+
+        // Rotating by `p` then `q`
+        let p = Quaternion::about_axis(0, FRAC_PI_2);
+        assert_close(
+            &q.mul(&p).rotate([0.0, 1.0, 0.0]),
+            &[0.0, 0.0, 1.0],
+            EPSILON,
+        );
+        assert_close(
+            &q.mul(&q.conj()).rotate([1.0, 2.0, 3.0]),
+            &[1.0, 2.0, 3.0],
+            EPSILON,
+        );
+    }
+
+    #[test]
+    fn test_matrix_round_trip() {
+        for q in [
+            Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            Quaternion::new(0.0, 1.0, 0.0, 0.0),
+            Quaternion::new(0.0, 0.0, 1.0, 0.0),
+            Quaternion::new(0.0, 0.0, 0.0, 1.0),
+            Quaternion::new(0.9, -0.3, 0.2, 0.1).normalize(),
+            Quaternion::new(-0.1, 0.5, -0.7, 0.4).normalize(),
+        ] {
+            let expected = match q.w < 0.0 {
+                true => Quaternion::new(-q.w, -q.x, -q.y, -q.z),
+                false => q,
+            };
+            let actual = Quaternion::from_matrix(q.to_matrix());
+            // `w == 0` is ambiguous in sign
+            assert!(
+                (0..4).all(|i| (components(actual)[i] - components(expected)[i]).abs() < EPSILON)
+                    || (0..4)
+                        .all(|i| (components(actual)[i] + components(expected)[i]).abs() < EPSILON),
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_euler() {
+        let angles = [30f64, 20.0, 10.0].map(f64::to_radians);
+        let q = Quaternion::from_euler(angles, Sequence::Zyx);
+        // scipy.spatial.transform.Rotation.from_euler("ZYX", [30, 20, 10], degrees=True)
+        assert_close(
+            &components(q),
+            &[
+                0.9515485246437885,
+                0.03813457647485015,
+                0.189307857412,
+                0.2392983377447303,
+            ],
+            EPSILON,
+        );
+        assert_close(&q.to_euler(Sequence::Zyx), &angles, EPSILON);
+
+        let q = Quaternion::from_euler(angles, Sequence::Xyz);
+        assert_close(&q.to_euler(Sequence::Xyz), &angles, EPSILON);
+        assert_close(
+            &q.rotate([0.0, 0.0, 1.0]),
+            &Quaternion::about_axis(0, angles[0])
+                .rotate(Quaternion::about_axis(1, angles[1]).rotate([0.0, 0.0, 1.0])),
+            EPSILON,
+        );
+
+        // Gimbal lock puts all of the rotation about the first axis
+        let q = Quaternion::from_euler([0.5, FRAC_PI_2, 0.0], Sequence::Zyx);
+        assert_close(&q.to_euler(Sequence::Zyx), &[0.5, FRAC_PI_2, 0.0], EPSILON);
+    }
+
+    fn quaternions() -> DataFrame {
+        let half = std::f64::consts::FRAC_1_SQRT_2;
+        let q = StructChunked::from_series(
+            "q".into(),
+            2,
+            [
+                Series::new("w".into(), [1.0, half]),
+                Series::new("x".into(), [0.0, 0.0]),
+                Series::new("y".into(), [0.0, 0.0]),
+                Series::new("z".into(), [0.0, half]),
+            ]
+            .iter(),
+        )
+        .unwrap()
+        .into_series();
+        let q_xyzw = Series::new(
+            "q_xyzw".into(),
+            [
+                Series::new("".into(), [0.0, 0.0, 0.0, 1.0]),
+                Series::new("".into(), [0.0, 0.0, half, half]),
+            ],
+        );
+        let v = Series::new(
+            "v".into(),
+            [
+                Series::new("".into(), [1i64, 0, 0]),
+                Series::new("".into(), [1i64, 0, 0]),
+            ],
+        );
+        DataFrame::new(vec![q.into(), q_xyzw.into(), v.into()]).unwrap()
+    }
+
+    #[test]
+    fn test_columns() {
+        assert_eq!(
+            eval_all(&quaternions(), "quat_rotate(q, v)"),
+            eval_all(&quaternions(), "quat_rotate(q_xyzw, v, \"xyzw\")")
+        );
+        let rotated = eval_all(&quaternions(), "quat_rotate(q, v)");
+        assert_close(&rotated[0], &[1.0, 0.0], EPSILON);
+        assert_close(&rotated[1], &[0.0, 1.0], EPSILON);
+
+        // The same components rotate the other way under JPL
+        let rotated = eval_all(&quaternions(), "quat_rotate(q, v, \"jpl\")");
+        assert_close(&rotated[1], &[0.0, -1.0], EPSILON);
+
+        let euler = eval_all(&quaternions(), "quat_to_euler(q_xyzw, \"xyzw\", \"deg\")");
+        assert_close(&euler[0], &[0.0, 90.0], EPSILON);
+
+        let half = std::f64::consts::FRAC_1_SQRT_2;
+        let q = eval_all(&quaternions(), "euler_to_quat(90, 0, 0, \"deg\", \"xyzw\")");
+        assert_close(&q.concat(), &[0.0, 0.0, half, half], EPSILON);
+        assert_close(
+            &eval_all(&quaternions(), "quat_mul(q, quat_conj(q))").concat(),
+            &[1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+            EPSILON,
+        );
+    }
+}
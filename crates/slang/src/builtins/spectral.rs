@@ -0,0 +1,366 @@
+use anyhow::Result;
+use polars::prelude::{GetOutput, PlSmallStr};
+use polars_lazy::prelude::*;
+use std::f64::consts::PI;
+
+use super::{f64_values, map_columns, sample_rate, struct_of, struct_type};
+use crate::functions::{ArgType::*, Arity, Function, FunctionRegistry};
+use crate::X_FIELD;
+
+pub(crate) fn register(registry: &mut FunctionRegistry) {
+    registry.register(
+        Function::new(
+            "fft_mag",
+            Arity::Exact(2),
+            &[("x", Numeric), ("t", Numeric)],
+            "Single sided amplitude spectrum of `x` against frequency in Hz, a sine of \
+         amplitude A peaks at A. `x` is zero padded to a power of two samples.",
+            |args| {
+                Ok(map_spectrum(
+                    "fft_mag",
+                    "magnitude",
+                    &[args.expr(0), args.expr(1)],
+                    |x, fs| Ok(amplitude_spectrum(x, fs)),
+                ))
+            },
+        )
+        .changing_length(),
+    );
+    registry.register(
+        Function::new(
+            "psd",
+            Arity::Exact(3),
+            &[("x", Numeric), ("t", Numeric), ("nperseg", Literal)],
+            "Power spectral density of `x` in units²/Hz against frequency in Hz, by \
+         Welch's method with Hann windowed segments of `nperseg` samples overlapping by half.",
+            |args| {
+                let nperseg = args.int_literal(2)?;
+                if nperseg < 2 {
+                    return Err(anyhow::anyhow!(
+                        "`psd` nperseg must be at least 2, found {}",
+                        nperseg
+                    ));
+                }
+                Ok(map_spectrum(
+                    "psd",
+                    "psd",
+                    &[args.expr(0), args.expr(1)],
+                    move |x, fs| welch(x, fs, nperseg as usize),
+                ))
+            },
+        )
+        .changing_length(),
+    );
+}
+
+/// Runs a spectrum function over `x` and the sample rate of `t`, producing a
+/// struct with the frequencies in [`X_FIELD`] so each result gets its own x axis.
+fn map_spectrum<F>(name: &'static str, field: &'static str, args: &[Expr], f: F) -> Expr
+where
+    F: Fn(&[f64], f64) -> Result<(Vec<f64>, Vec<f64>)> + Send + Sync + 'static,
+{
+    map_columns(
+        name,
+        args,
+        GetOutput::from_type(struct_type(&[X_FIELD, field])),
+        move |_, column| f64_values(name, column),
+        move |column, inputs| {
+            let (frequencies, values) = f(&inputs[0], sample_rate(&inputs[1])?)?;
+            let rows: Vec<Option<Vec<f64>>> = frequencies
+                .into_iter()
+                .zip(values)
+                .map(|(frequency, value)| Some(vec![frequency, value]))
+                .collect();
+            let fields = [X_FIELD, field].map(PlSmallStr::from);
+            Ok(struct_of(column.name().clone(), fields, &rows)?)
+        },
+    )
+}
+
+/// In place radix-2 FFT of `(re, im)` pairs, the length must be a power of two.
+fn fft(buffer: &mut [(f64, f64)]) {
+    let n = buffer.len();
+    debug_assert!(n.is_power_of_two());
+
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            buffer.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_re, w_im) = ((angle * k as f64).cos(), (angle * k as f64).sin());
+                let (u_re, u_im) = buffer[start + k];
+                let (x_re, x_im) = buffer[start + k + len / 2];
+                let (v_re, v_im) = (x_re * w_re - x_im * w_im, x_re * w_im + x_im * w_re);
+                buffer[start + k] = (u_re + v_re, u_im + v_im);
+                buffer[start + k + len / 2] = (u_re - v_re, u_im - v_im);
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// `|X_k|^2` for the non-negative frequencies of `x` zero padded to `nfft`.
+fn power_spectrum(x: impl Iterator<Item = f64>, nfft: usize) -> Vec<f64> {
+    let mut buffer: Vec<(f64, f64)> = x.map(|x| (x, 0.0)).collect();
+    buffer.resize(nfft, (0.0, 0.0));
+    fft(&mut buffer);
+    buffer[..=nfft / 2]
+        .iter()
+        .map(|(re, im)| re * re + im * im)
+        .collect()
+}
+
+fn frequencies(nfft: usize, fs: f64) -> Vec<f64> {
+    (0..=nfft / 2)
+        .map(|k| k as f64 * fs / nfft as f64)
+        .collect()
+}
+
+/// Whether bin `k` of a one sided spectrum stands in for a negative
+/// frequency too, so its power is doubled.
+fn is_mirrored(k: usize, nfft: usize) -> bool {
+    k != 0 && 2 * k != nfft
+}
+
+fn amplitude_spectrum(x: &[f64], fs: f64) -> (Vec<f64>, Vec<f64>) {
+    let nfft = x.len().next_power_of_two();
+    let magnitudes = power_spectrum(x.iter().copied(), nfft)
+        .into_iter()
+        .enumerate()
+        .map(|(k, power)| {
+            let magnitude = power.sqrt() / x.len() as f64;
+            match is_mirrored(k, nfft) {
+                true => 2.0 * magnitude,
+                false => magnitude,
+            }
+        })
+        .collect();
+    (frequencies(nfft, fs), magnitudes)
+}
+
+/// Periodic Hann window, as used for spectral analysis by scipy.
+fn hann(n: usize) -> Vec<f64> {
+    (0..n)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f64 / n as f64).cos())
+        .collect()
+}
+
+/// Start index and one sided density of a segment.
+type Segment = (usize, Vec<f64>);
+
+/// Power spectral density of each Hann windowed, mean removed segment of
+/// `nperseg` samples, stepping by half a segment, along with the frequencies.
+fn segment_densities(x: &[f64], fs: f64, nperseg: usize) -> Result<(Vec<f64>, Vec<Segment>)> {
+    if x.len() < nperseg {
+        return Err(anyhow::anyhow!(
+            "segments of {} samples don't fit in {} samples",
+            nperseg,
+            x.len()
+        ));
+    }
+
+    let nfft = nperseg.next_power_of_two();
+    let window = hann(nperseg);
+    let scale = 1.0 / (fs * window.iter().map(|w| w * w).sum::<f64>());
+    let step = nperseg - nperseg / 2;
+
+    let densities = (0..=x.len() - nperseg)
+        .step_by(step)
+        .map(|start| {
+            let segment = &x[start..start + nperseg];
+            let mean = segment.iter().sum::<f64>() / nperseg as f64;
+            let windowed = segment.iter().zip(&window).map(|(x, w)| (x - mean) * w);
+            let density = power_spectrum(windowed, nfft)
+                .into_iter()
+                .enumerate()
+                .map(|(k, power)| match is_mirrored(k, nfft) {
+                    true => 2.0 * power * scale,
+                    false => power * scale,
+                })
+                .collect();
+            (start, density)
+        })
+        .collect();
+
+    Ok((frequencies(nfft, fs), densities))
+}
+
+fn welch(x: &[f64], fs: f64, nperseg: usize) -> Result<(Vec<f64>, Vec<f64>)> {
+    let (frequencies, densities) = segment_densities(x, fs, nperseg)?;
+    let mut average = vec![0.0; frequencies.len()];
+    for (_, density) in &densities {
+        average.iter_mut().zip(density).for_each(|(a, d)| *a += d);
+    }
+    average
+        .iter_mut()
+        .for_each(|a| *a /= densities.len() as f64);
+    Ok((frequencies, average))
+}
+
+/// Power spectral density over time, one Welch segment per column.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spectrogram {
+    /// Time at the center of each segment.
+    pub times: Vec<f64>,
+    pub frequencies: Vec<f64>,
+    /// Density in units²/Hz, indexed by `[time][frequency]`.
+    pub power: Vec<Vec<f64>>,
+}
+
+/// Spectrogram of `x` sampled at times `t` in seconds, from Hann windowed
+/// segments of `nperseg` samples overlapping by half.
+pub fn spectrogram(x: &[f64], t: &[f64], nperseg: usize) -> Result<Spectrogram> {
+    if x.len() != t.len() {
+        return Err(anyhow::anyhow!(
+            "signal has {} samples but time has {}",
+            x.len(),
+            t.len()
+        ));
+    }
+    if nperseg < 2 {
+        return Err(anyhow::anyhow!(
+            "nperseg must be at least 2, found {}",
+            nperseg
+        ));
+    }
+
+    let (frequencies, densities) = segment_densities(x, sample_rate(t)?, nperseg)?;
+    let (times, power) = densities
+        .into_iter()
+        .map(|(start, density)| (t[start + nperseg / 2], density))
+        .unzip();
+    Ok(Spectrogram {
+        times,
+        frequencies,
+        power,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::{DataFrame, IntoLazy, NamedFrom, Series};
+
+    use crate::builtins::testing::assert_close;
+
+    fn tone(n: usize, fs: f64, freq: f64, amplitude: f64, offset: f64) -> (Vec<f64>, Vec<f64>) {
+        let t: Vec<f64> = (0..n).map(|i| i as f64 / fs).collect();
+        let x = t
+            .iter()
+            .map(|t| offset + amplitude * (2.0 * PI * freq * t).sin())
+            .collect();
+        (t, x)
+    }
+
+    #[test]
+    fn test_fft_matches_dft() {
+        let x: Vec<f64> = (0..16).map(|i| ((i * 7) % 5) as f64 - 1.5).collect();
+        let fast = power_spectrum(x.iter().copied(), 16);
+        for (k, power) in fast.iter().enumerate() {
+            let (re, im) = x.iter().enumerate().fold((0.0, 0.0), |(re, im), (n, x)| {
+                let angle = -2.0 * PI * (k * n) as f64 / 16.0;
+                (re + x * angle.cos(), im + x * angle.sin())
+            });
+            assert_close(&[*power], &[re * re + im * im], 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_amplitude_spectrum() {
+        let (_, x) = tone(256, 256.0, 8.0, 2.0, 1.0);
+        let (frequencies, magnitudes) = amplitude_spectrum(&x, 256.0);
+        assert_eq!(frequencies.len(), 129);
+        assert_close(&[frequencies[8]], &[8.0], 1e-12);
+        assert_close(&[magnitudes[0]], &[1.0], 1e-9);
+        assert_close(&[magnitudes[8]], &[2.0], 1e-9);
+        assert!(magnitudes
+            .iter()
+            .enumerate()
+            .all(|(k, m)| k == 0 || k == 8 || *m < 1e-9));
+    }
+
+    #[test]
+    fn test_welch() {
+        let fs = 1000.0;
+        let (_, x) = tone(10_000, fs, 125.0, 2.0, 3.0);
+        let (frequencies, density) = welch(&x, fs, 256).unwrap();
+        let df = frequencies[1] - frequencies[0];
+
+        // Parseval: the density integrates to the variance, A^2 / 2
+        assert_close(&[density.iter().sum::<f64>() * df], &[2.0], 1e-2);
+
+        let peak = density
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap()
+            .0;
+        assert_close(&[frequencies[peak]], &[125.0], df);
+
+        assert!(welch(&x[..100], fs, 256).is_err());
+    }
+
+    #[test]
+    fn test_spectrogram() {
+        let fs = 1000.0;
+        let (t, mut x) = tone(4000, fs, 50.0, 1.0, 0.0);
+        let (_, high) = tone(4000, fs, 300.0, 1.0, 0.0);
+        // Switch from 50 Hz to 300 Hz half way through
+        x[2000..].copy_from_slice(&high[2000..]);
+
+        let spectrogram = spectrogram(&x, &t, 128).unwrap();
+        assert_eq!(spectrogram.times.len(), spectrogram.power.len());
+        assert_close(&[spectrogram.times[0]], &[0.064], 1e-12);
+
+        let peak = |power: &Vec<f64>| {
+            let k = power
+                .iter()
+                .enumerate()
+                .max_by(|a, b| a.1.total_cmp(b.1))
+                .unwrap()
+                .0;
+            spectrogram.frequencies[k]
+        };
+        let bin = spectrogram.frequencies[1];
+        assert_close(&[peak(&spectrogram.power[0])], &[50.0], bin);
+        assert_close(&[peak(spectrogram.power.last().unwrap())], &[300.0], bin);
+    }
+
+    #[test]
+    fn test_spectrum_traces() {
+        let (t, x) = tone(1024, 1024.0, 64.0, 1.0, 0.0);
+        let df = DataFrame::new(vec![
+            Series::new("t".into(), t).into(),
+            Series::new("x".into(), x).into(),
+        ])
+        .unwrap()
+        .lazy();
+
+        let traces = crate::eval(&df, "fft_mag(x, t)").unwrap();
+        assert_eq!(traces.len(), 1);
+        assert_eq!(traces[0].name, "fft_mag(x, t)");
+        let frequencies = traces[0].x.as_ref().unwrap();
+        assert_eq!(frequencies.len(), 513);
+        assert_close(&[frequencies[64]], &[64.0], 1e-12);
+        assert_close(&[traces[0].data()[64]], &[1.0], 1e-9);
+
+        let traces = crate::eval(&df, "psd(x, t, 256)").unwrap();
+        assert_eq!(traces[0].x.as_ref().unwrap().len(), 129);
+
+        assert!(crate::eval(&df, "psd(x, t, 1)").is_err());
+        assert!(crate::eval(&df, "psd(x, t, 4096)").is_err());
+    }
+}
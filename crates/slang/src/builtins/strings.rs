@@ -0,0 +1,181 @@
+//! Functions of string and categorical columns, e.g. the modes of a state
+//! machine. Compare against a string with `mode == "HOVER"`.
+
+use polars::prelude::{
+    Column, DataType, GetOutput, IntoSeries, NamedFrom, PolarsError, PolarsResult, Series,
+};
+use std::collections::BTreeMap;
+
+use crate::functions::{ArgType::*, Arity, Function, FunctionRegistry};
+
+pub(crate) fn register(registry: &mut FunctionRegistry) {
+    registry.register(Function::new(
+        "contains",
+        Arity::Exact(2),
+        &[("s", Any), ("pattern", Any)],
+        "Whether string `s` contains `pattern`, matched literally.",
+        |args| {
+            Ok(args
+                .expr(0)
+                .cast(DataType::String)
+                .str()
+                .contains_literal(args.expr(1)))
+        },
+    ));
+    registry.register(Function::new(
+        "starts_with",
+        Arity::Exact(2),
+        &[("s", Any), ("prefix", Any)],
+        "Whether string `s` starts with `prefix`.",
+        |args| {
+            Ok(args
+                .expr(0)
+                .cast(DataType::String)
+                .str()
+                .starts_with(args.expr(1)))
+        },
+    ));
+    registry.register(Function::new(
+        "code",
+        Arity::Exact(1),
+        &[("x", Any)],
+        "An integer for each category of `x` so its states can be plotted: the \
+         code polars stores for a categorical or enum, or for plain strings the \
+         position among the sorted distinct strings, which can change when other \
+         data has other strings.",
+        |args| {
+            Ok(args.expr(0).map(
+                |column: Column| Ok(Some(codes(column.as_materialized_series())?.into())),
+                GetOutput::from_type(DataType::UInt32),
+            ))
+        },
+    ));
+}
+
+/// Whether `dtype` holds strings that [`codes`] can number.
+pub(crate) fn is_categorical(dtype: &DataType) -> bool {
+    matches!(
+        dtype,
+        DataType::String | DataType::Categorical(..) | DataType::Enum(..)
+    )
+}
+
+/// Numbers the categories of a string, categorical or enum series.
+/// Categoricals and enums keep the codes polars stores for them, which are
+/// the same across frames sharing their categories. Plain strings are
+/// numbered in sorted order, so the codes don't depend on the order they
+/// appear in but are only stable within one frame.
+pub(crate) fn codes(series: &Series) -> PolarsResult<Series> {
+    match series.dtype() {
+        DataType::Categorical(..) | DataType::Enum(..) => {
+            Ok(series.to_physical_repr().into_owned())
+        }
+        DataType::String => {
+            let strings = series.cast(&DataType::String)?;
+            let strings = strings.str()?;
+            let codes: BTreeMap<&str, u32> = strings
+                .into_iter()
+                .flatten()
+                .map(|value| (value, 0))
+                .collect::<BTreeMap<_, _>>()
+                .into_keys()
+                .zip(0..)
+                .collect();
+            Ok(Series::new(
+                series.name().clone(),
+                strings
+                    .into_iter()
+                    .map(|value| value.map(|value| codes[value]))
+                    .collect::<Vec<_>>(),
+            )
+            .into_series())
+        }
+        dtype => Err(PolarsError::SchemaMismatch(
+            format!("`code` expects strings or categories, found {}", dtype).into(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use polars::prelude::{DataFrame, DataType, IntoLazy, NamedFrom, Series};
+
+    use crate::builtins::testing::eval;
+
+    fn modes() -> DataFrame {
+        let mode = Series::new(
+            "mode".into(),
+            [
+                Some("HOVER"),
+                Some("LAND"),
+                None,
+                Some("TAKEOFF"),
+                Some("HOVER"),
+            ],
+        );
+        let category = mode
+            .cast(&DataType::Categorical(None, Default::default()))
+            .unwrap();
+        DataFrame::new(vec![
+            mode.into(),
+            category.with_name("category".into()).into(),
+        ])
+        .unwrap()
+    }
+
+    fn assert_same(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!(
+                (a.is_nan() && e.is_nan()) || a == e,
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_compare_strings() {
+        let nan = f64::NAN;
+        assert_same(
+            &eval(&modes(), "mode == \"HOVER\""),
+            &[1.0, 0.0, nan, 0.0, 1.0],
+        );
+        assert_same(
+            &eval(&modes(), "category != \"HOVER\""),
+            &[0.0, 1.0, nan, 1.0, 0.0],
+        );
+        assert_same(
+            &eval(&modes(), "contains(mode, \"AKE\")"),
+            &[0.0, 0.0, nan, 1.0, 0.0],
+        );
+        assert_same(
+            &eval(&modes(), "starts_with(category, \"LA\")"),
+            &[0.0, 1.0, nan, 0.0, 0.0],
+        );
+    }
+
+    #[test]
+    fn test_code() {
+        let nan = f64::NAN;
+        assert_same(&eval(&modes(), "code(mode)"), &[0.0, 1.0, nan, 2.0, 0.0]);
+        assert_same(
+            &eval(&modes(), "code(category)"),
+            &[0.0, 1.0, nan, 2.0, 0.0],
+        );
+        // Plotting strings directly plots their codes
+        assert_same(&eval(&modes(), "mode"), &[0.0, 1.0, nan, 2.0, 0.0]);
+        assert!(crate::eval(&modes().lazy(), "code(1.5)").is_err());
+
+        // Categoricals keep the codes they were stored with, unsorted
+        let late = Series::new("late".into(), ["TAKEOFF", "HOVER", "TAKEOFF"])
+            .cast(&DataType::Categorical(None, Default::default()))
+            .unwrap();
+        let df = DataFrame::new(vec![late.into()]).unwrap().lazy();
+        assert_eq!(
+            crate::eval(&df, "code(late)").unwrap()[0].data(),
+            [0.0, 1.0, 0.0]
+        );
+    }
+}
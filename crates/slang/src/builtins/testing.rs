@@ -0,0 +1,46 @@
+//! Helpers shared by the tests of the builtins.
+
+use polars::prelude::{DataFrame, IntoLazy, Series};
+
+/// The values of every trace `source` plots from `df`, e.g. one per field
+/// of a struct, with missing values as NaN.
+pub(crate) fn eval_all(df: &DataFrame, source: &str) -> Vec<Vec<f64>> {
+    crate::eval(&df.clone().lazy(), source)
+        .unwrap()
+        .iter()
+        .map(|trace| trace.data())
+        .collect()
+}
+
+/// The values of the first trace `source` plots from `df`.
+pub(crate) fn eval(df: &DataFrame, source: &str) -> Vec<f64> {
+    eval_all(df, source).remove(0)
+}
+
+/// The column polars computes for `source` from `df`, before it is split
+/// into traces.
+pub(crate) fn select(df: &DataFrame, source: &str) -> Series {
+    let expr = crate::to_polars_expr(&crate::parse(source).unwrap()).unwrap();
+    let out = df.clone().lazy().select([expr]).collect().unwrap();
+    out.get_columns()[0].as_materialized_series().clone()
+}
+
+pub(crate) fn assert_close(actual: &[f64], expected: &[f64], tolerance: f64) {
+    assert_eq!(
+        actual.len(),
+        expected.len(),
+        "{:?} != {:?}",
+        actual,
+        expected
+    );
+    for (index, (a, e)) in actual.iter().zip(expected).enumerate() {
+        assert!(
+            (a - e).abs() <= tolerance,
+            "index {}: {} != {} (tolerance {})",
+            index,
+            a,
+            e,
+            tolerance
+        );
+    }
+}
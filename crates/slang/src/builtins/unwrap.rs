@@ -0,0 +1,151 @@
+use anyhow::Result;
+use std::f64::consts::TAU;
+
+use super::map_f64;
+use crate::functions::{ArgType::*, Arity, Function, FunctionRegistry};
+
+pub(crate) fn register(registry: &mut FunctionRegistry) {
+    registry.register(Function::new(
+        "unwrap_angle",
+        Arity::Range(1, 2),
+        &[("x", Numeric), ("period", Literal)],
+        "Removes the jumps where angle `x` wraps around, e.g. from π to -π. \
+         `period` defaults to 2π, use 360 for degrees.",
+        |args| {
+            let period = match args.len() {
+                2 => args.float_literal(1)?,
+                _ => TAU,
+            };
+            check_period("unwrap_angle", period)?;
+            Ok(map_f64(
+                "unwrap_angle",
+                &args.exprs()[..1],
+                move |columns| Ok(unwrap_angle(&columns[0], period)),
+            ))
+        },
+    ));
+    registry.register(Function::new(
+        "unwrap_counter",
+        Arity::Exact(2),
+        &[("x", Numeric), ("modulus", Literal)],
+        "Continues counter `x` past each roll over back to zero, e.g. 65536 for \
+         16 bit counters. The counter must never count down.",
+        |args| {
+            let modulus = args.float_literal(1)?;
+            check_period("unwrap_counter", modulus)?;
+            Ok(map_f64(
+                "unwrap_counter",
+                &args.exprs()[..1],
+                move |columns| Ok(unwrap_counter(&columns[0], modulus)),
+            ))
+        },
+    ));
+}
+
+fn check_period(name: &str, period: f64) -> Result<()> {
+    if period > 0.0 && period.is_finite() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "`{}` needs a positive period, found {}",
+            name,
+            period
+        ))
+    }
+}
+
+/// Adds the multiple of `period` to each sample that keeps every step within
+/// half a period, the same as `numpy.unwrap`.
+fn unwrap_angle(x: &[f64], period: f64) -> Vec<f64> {
+    unwrap_steps(x, |step| step - period * (step / period).round())
+}
+
+/// Takes every step as counting up by less than `modulus`.
+fn unwrap_counter(x: &[f64], modulus: f64) -> Vec<f64> {
+    unwrap_steps(x, |step| step.rem_euclid(modulus))
+}
+
+fn unwrap_steps(x: &[f64], step: impl Fn(f64) -> f64) -> Vec<f64> {
+    let mut unwrapped = Vec::with_capacity(x.len());
+    let mut total = match x.first() {
+        Some(first) => *first,
+        None => return unwrapped,
+    };
+    unwrapped.push(total);
+    for pair in x.windows(2) {
+        total += step(pair[1] - pair[0]);
+        unwrapped.push(total);
+    }
+    unwrapped
+}
+
+#[cfg(test)]
+mod tests {
+    use polars::prelude::{DataFrame, IntoLazy, NamedFrom, Series};
+    use std::f64::consts::PI;
+
+    use crate::builtins::testing::{assert_close, eval};
+
+    /// Wraps `x` into `[-half, half)`.
+    fn wrap(x: f64, half: f64) -> f64 {
+        (x + half).rem_euclid(2.0 * half) - half
+    }
+
+    #[test]
+    fn test_unwrap_angle() {
+        // Three turns one way and back, in steps under half a turn
+        let angle: Vec<f64> = (0..200)
+            .map(|i| 6.0 * PI * (i as f64 / 199.0 * PI).sin() - 1.0)
+            .collect();
+        let df = DataFrame::new(vec![
+            Series::new(
+                "yaw".into(),
+                angle.iter().map(|x| wrap(*x, PI)).collect::<Vec<_>>(),
+            )
+            .into(),
+            Series::new(
+                "heading".into(),
+                angle
+                    .iter()
+                    .map(|x| wrap(x.to_degrees(), 180.0))
+                    .collect::<Vec<_>>(),
+            )
+            .into(),
+        ])
+        .unwrap();
+
+        assert_close(&eval(&df, "unwrap_angle(yaw)"), &angle, 1e-9);
+        let degrees: Vec<f64> = angle.iter().map(|x| x.to_degrees()).collect();
+        assert_close(&eval(&df, "unwrap_angle(heading, 360)"), &degrees, 1e-9);
+
+        let err = crate::eval(&df.lazy(), "unwrap_angle(yaw, 0)")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "`unwrap_angle` needs a positive period, found 0"
+        );
+    }
+
+    #[test]
+    fn test_unwrap_counter() {
+        // A 16 bit counter rolling over three times, skipping samples as it goes
+        let count: Vec<i64> = (0..100).map(|i| 60_000 + i * 1_500 + i % 3).collect();
+        let df = DataFrame::new(vec![Series::new(
+            "counter".into(),
+            count
+                .iter()
+                .map(|c| (c % 65_536) as u32)
+                .collect::<Vec<_>>(),
+        )
+        .into()])
+        .unwrap();
+
+        let expected: Vec<f64> = count.iter().map(|c| *c as f64).collect();
+        assert_close(
+            &eval(&df, "unwrap_counter(counter, 65536)"),
+            &expected,
+            1e-9,
+        );
+    }
+}
@@ -0,0 +1,324 @@
+//! Vector functions over list columns, e.g. `data`, and struct columns, e.g.
+//! `{x, y, z}`. Struct fields are taken in order, whatever their names.
+
+use polars::prelude::{
+    Column, DataType, Field, GetOutput, IntoSeries, ListChunked, NamedFrom, PlSmallStr,
+    PolarsError, PolarsResult, Series,
+};
+use polars_lazy::prelude::*;
+
+use super::{map_columns, rows, struct_of};
+use crate::functions::{ArgType::*, Arity, Function, FunctionRegistry};
+
+pub(crate) fn register(registry: &mut FunctionRegistry) {
+    registry.register(Function::new(
+        "norm",
+        Arity::Exact(1),
+        &[("v", Any)],
+        "Euclidean length of vector `v`.",
+        |args| {
+            Ok(map_vectors("norm", &[args.expr(0)], Output::Scalar, |v| {
+                Ok(vec![dot(v[0], v[0]).sqrt()])
+            }))
+        },
+    ));
+    registry.register(Function::new(
+        "dot",
+        Arity::Exact(2),
+        &[("a", Any), ("b", Any)],
+        "Dot product of vectors `a` and `b`.",
+        |args| {
+            Ok(map_vectors(
+                "dot",
+                &[args.expr(0), args.expr(1)],
+                Output::Scalar,
+                |v| {
+                    same_length(v[0], v[1])?;
+                    Ok(vec![dot(v[0], v[1])])
+                },
+            ))
+        },
+    ));
+    registry.register(Function::new(
+        "cross",
+        Arity::Exact(2),
+        &[("a", Any), ("b", Any)],
+        "Cross product of 3-vectors `a` and `b`, laid out like `a`.",
+        |args| {
+            Ok(map_vectors(
+                "cross",
+                &[args.expr(0), args.expr(1)],
+                Output::LikeFirst,
+                |v| {
+                    same_length(v[0], v[1])?;
+                    let [a, b] = [v[0], v[1]];
+                    if a.len() != 3 {
+                        return Err(format!("needs 3-vectors, found {} elements", a.len()));
+                    }
+                    Ok(vec![
+                        a[1] * b[2] - a[2] * b[1],
+                        a[2] * b[0] - a[0] * b[2],
+                        a[0] * b[1] - a[1] * b[0],
+                    ])
+                },
+            ))
+        },
+    ));
+    registry.register(Function::new(
+        "normalize",
+        Arity::Exact(1),
+        &[("v", Any)],
+        "Vector `v` scaled to unit length, laid out like `v`.",
+        |args| {
+            Ok(map_vectors(
+                "normalize",
+                &[args.expr(0)],
+                Output::LikeFirst,
+                |v| {
+                    let norm = dot(v[0], v[0]).sqrt();
+                    Ok(v[0].iter().map(|x| x / norm).collect())
+                },
+            ))
+        },
+    ));
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn same_length(a: &[f64], b: &[f64]) -> Result<(), String> {
+    match a.len() == b.len() {
+        true => Ok(()),
+        false => Err(format!("vectors have {} and {} elements", a.len(), b.len())),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Output {
+    /// A number per row.
+    Scalar,
+    /// A vector per row, laid out like the first argument.
+    LikeFirst,
+}
+
+/// How a column holds its vectors.
+#[derive(Clone, Debug, PartialEq)]
+enum Shape {
+    List,
+    Struct(Vec<PlSmallStr>),
+}
+
+impl Shape {
+    fn of(dtype: &DataType) -> Option<Self> {
+        match dtype {
+            DataType::List(_) => Some(Shape::List),
+            DataType::Struct(fields) => Some(Shape::Struct(
+                fields.iter().map(|field| field.name().clone()).collect(),
+            )),
+            _ => None,
+        }
+    }
+
+    fn dtype(&self) -> DataType {
+        match self {
+            Shape::List => DataType::List(Box::new(DataType::Float64)),
+            Shape::Struct(names) => DataType::Struct(
+                names
+                    .iter()
+                    .map(|name| Field::new(name.clone(), DataType::Float64))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Runs `f` on the vectors of each row of the arguments, single row
+/// arguments are broadcast and rows with a null anywhere are null.
+fn map_vectors<F>(name: &'static str, args: &[Expr], output: Output, f: F) -> Expr
+where
+    F: Fn(&[&[f64]]) -> Result<Vec<f64>, String> + Send + Sync + 'static,
+{
+    let output_type = GetOutput::map_dtype(move |dtype| match output {
+        Output::Scalar => Ok(DataType::Float64),
+        Output::LikeFirst => Ok(Shape::of(dtype)
+            .map(|shape| shape.dtype())
+            .unwrap_or(dtype.clone())),
+    });
+
+    map_columns(
+        name,
+        args,
+        output_type,
+        move |_, column| read_vectors(name, column),
+        move |column, inputs| {
+            let rows = rows(inputs)
+                .map(|row| {
+                    row.map(|row| f(&row.iter().map(|v| v.as_slice()).collect::<Vec<_>>()))
+                        .transpose()
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(anyhow::Error::msg)?;
+
+            let name = column.name().clone();
+            Ok(match (output, Shape::of(column.dtype())) {
+                (Output::Scalar, _) => Series::new(
+                    name,
+                    rows.into_iter()
+                        .map(|row| row.map(|row| row[0]))
+                        .collect::<Vec<_>>(),
+                ),
+                (Output::LikeFirst, Some(Shape::List)) => rows
+                    .into_iter()
+                    .map(|row| row.map(|row| Series::new(PlSmallStr::EMPTY, row)))
+                    .collect::<ListChunked>()
+                    .with_name(name)
+                    .into_series(),
+                (Output::LikeFirst, Some(Shape::Struct(fields))) => struct_of(name, fields, &rows)?,
+                (Output::LikeFirst, None) => unreachable!("vectors were read from the column"),
+            })
+        },
+    )
+}
+
+/// The elements of every row of a list or struct column, `None` where any are null.
+fn read_vectors(name: &str, column: &Column) -> PolarsResult<Vec<Option<Vec<f64>>>> {
+    let series = column.as_materialized_series();
+    let collect =
+        |values: Series| -> PolarsResult<Option<Vec<f64>>> { Ok(values.f64()?.iter().collect()) };
+
+    match series.dtype() {
+        DataType::List(_) => series
+            .cast(&DataType::List(Box::new(DataType::Float64)))?
+            .list()?
+            .into_iter()
+            .map(|row| row.map(collect).transpose().map(Option::flatten))
+            .collect(),
+        DataType::Struct(_) => {
+            let fields = series
+                .struct_()?
+                .fields_as_series()
+                .iter()
+                .map(|field| field.cast(&DataType::Float64))
+                .collect::<PolarsResult<Vec<_>>>()?;
+            let fields = fields
+                .iter()
+                .map(|field| field.f64())
+                .collect::<PolarsResult<Vec<_>>>()?;
+            let nulls = series.is_null();
+            Ok((0..series.len())
+                .map(|row| match nulls.get(row) {
+                    Some(true) => None,
+                    _ => fields.iter().map(|field| field.get(row)).collect(),
+                })
+                .collect())
+        }
+        dtype => Err(PolarsError::SchemaMismatch(
+            format!(
+                "`{}` expects a list or struct vector, found {}",
+                name, dtype
+            )
+            .into(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::{DataFrame, IntoLazy};
+
+    use crate::builtins::testing::select;
+
+    fn vectors() -> DataFrame {
+        let list = |name: &str, rows: [[f64; 3]; 2]| {
+            Series::new(
+                name.into(),
+                rows.map(|row| Series::new(PlSmallStr::EMPTY, row)),
+            )
+        };
+        let position = DataFrame::new(vec![
+            Series::new("x".into(), [3.0, 0.0]).into(),
+            Series::new("y".into(), [4.0, 0.0]).into(),
+            Series::new("z".into(), [0.0, 2.0]).into(),
+        ])
+        .unwrap()
+        .into_struct("position".into())
+        .into_series();
+        DataFrame::new(vec![
+            list("a", [[1.0, 0.0, 0.0], [1.0, 2.0, 3.0]]).into(),
+            list("b", [[0.0, 1.0, 0.0], [4.0, 5.0, 6.0]]).into(),
+            position.into(),
+        ])
+        .unwrap()
+    }
+
+    fn values(series: Series) -> Vec<f64> {
+        series.f64().unwrap().into_no_null_iter().collect()
+    }
+
+    fn lists(series: Series) -> Vec<Vec<f64>> {
+        series
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|row| values(row.unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_norm_and_dot() {
+        assert_eq!(values(select(&vectors(), "norm(position)")), [5.0, 2.0]);
+        assert_eq!(values(select(&vectors(), "norm(a)")), [1.0, 14f64.sqrt()]);
+        assert_eq!(values(select(&vectors(), "dot(a, b)")), [0.0, 32.0]);
+        assert_eq!(values(select(&vectors(), "dot(a, position)")), [3.0, 6.0]);
+    }
+
+    #[test]
+    fn test_cross() {
+        assert_eq!(
+            lists(select(&vectors(), "cross(a, b)")),
+            [[0.0, 0.0, 1.0], [-3.0, 6.0, -3.0]]
+        );
+
+        let crossed = select(&vectors(), "cross(position, a)");
+        let z = crossed.struct_().unwrap().field_by_name("z").unwrap();
+        assert_eq!(values(z), [-4.0, 0.0]);
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            lists(select(&vectors(), "normalize(b)"))[0],
+            [0.0, 1.0, 0.0]
+        );
+        let normalized = select(&vectors(), "normalize(position)");
+        let fields = normalized.struct_().unwrap().fields_as_series();
+        assert_eq!(values(fields[0].clone()), [0.6, 0.0]);
+        assert_eq!(values(fields[1].clone()), [0.8, 0.0]);
+        assert!(values(select(&vectors(), "norm(normalize(a - b))"))
+            .iter()
+            .all(|norm| (norm - 1.0).abs() < 1e-12));
+    }
+
+    #[test]
+    fn test_list_arithmetic() {
+        assert_eq!(
+            lists(select(&vectors(), "a - b")),
+            [[1.0, -1.0, 0.0], [-3.0, -3.0, -3.0]]
+        );
+        assert_eq!(
+            lists(select(&vectors(), "a * 2 + b")),
+            [[2.0, 1.0, 0.0], [6.0, 9.0, 12.0]]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let df = vectors().lazy();
+        for source in ["norm(position.x)", "cross(a, a[0:2])", "dot(a, a[0:2])"] {
+            let expr = crate::to_polars_expr(&crate::parse(source).unwrap()).unwrap();
+            assert!(df.clone().select([expr]).collect().is_err(), "{}", source);
+        }
+    }
+}
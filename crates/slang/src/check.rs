@@ -0,0 +1,632 @@
+//! Static checks of a program against the schema of the frame it will run
+//! on, so mistakes like `position.data.x` are found without collecting any
+//! data. Types are worked out where the schema says what they are, anything
+//! unknown, e.g. the result of most functions, is given the benefit of the
+//! doubt.
+
+use polars::prelude::{DataType, Schema};
+use std::collections::HashMap;
+
+use crate::diagnostic::{did_you_mean, Diagnostic};
+use crate::functions::{ArgType, FunctionRegistry};
+use crate::optimize;
+use crate::parser::{Expr, Op, Program, ProgramSpans, Spans, Statement, Wildcard};
+
+/// Every problem found in `program`, in the order they appear, pointing at
+/// the parts of the source `spans` says they came from.
+pub fn check(
+    program: &Program,
+    spans: &ProgramSpans,
+    schema: &Schema,
+    functions: &FunctionRegistry,
+) -> Vec<Diagnostic> {
+    let mut checker = Checker::new(schema, functions);
+    if let Some(dtype) = checker.program(program, spans) {
+        if !plottable(&dtype) {
+            checker.report(
+                &spans.result,
+                format!("the result is {}, which can't be plotted", dtype),
+                None,
+            );
+        }
+    }
+    checker.diagnostics
+}
+
+/// The type of the result of `program`, where it can be worked out.
+pub(crate) fn result_type(
+    program: &Program,
+    spans: &ProgramSpans,
+    schema: &Schema,
+    functions: &FunctionRegistry,
+) -> Option<DataType> {
+    Checker::new(schema, functions).program(program, spans)
+}
+
+/// What a value can be used as.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Integer,
+    Float,
+    Boolean,
+    /// Strings, categoricals and enums.
+    Text,
+    List,
+    Struct,
+    /// Dates, times and anything else that casts to a number.
+    Other,
+}
+
+impl Kind {
+    fn of(dtype: &DataType) -> Self {
+        match dtype {
+            dtype if dtype.is_integer() => Kind::Integer,
+            dtype if dtype.is_float() => Kind::Float,
+            DataType::Boolean => Kind::Boolean,
+            DataType::String | DataType::Categorical(..) | DataType::Enum(..) => Kind::Text,
+            DataType::List(_) => Kind::List,
+            DataType::Struct(_) => Kind::Struct,
+            _ => Kind::Other,
+        }
+    }
+}
+
+fn plottable(dtype: &DataType) -> bool {
+    match dtype {
+        DataType::Binary | DataType::BinaryOffset => false,
+        DataType::List(inner) => plottable(inner),
+        DataType::Struct(fields) => fields.iter().all(|field| plottable(field.dtype())),
+        _ => true,
+    }
+}
+
+/// The type polars gives arithmetic between integers of types `a` and `b`,
+/// if it's another integer.
+fn integer_supertype(a: &DataType, b: &DataType) -> Option<DataType> {
+    let width = |dtype: &DataType| match dtype {
+        DataType::Int8 | DataType::UInt8 => 8,
+        DataType::Int16 | DataType::UInt16 => 16,
+        DataType::Int32 | DataType::UInt32 => 32,
+        _ => 64,
+    };
+    let (signed, unsigned) = match (a.is_signed_integer(), b.is_signed_integer()) {
+        (true, false) => (a, b),
+        (false, true) => (b, a),
+        _ => {
+            return match width(a) >= width(b) {
+                true => Some(a.clone()),
+                false => Some(b.clone()),
+            }
+        }
+    };
+    match width(signed) > width(unsigned) {
+        true => Some(signed.clone()),
+        // An unsigned integer only fits in a wider signed one
+        false => match width(unsigned) {
+            8 => Some(DataType::Int16),
+            16 => Some(DataType::Int32),
+            32 => Some(DataType::Int64),
+            _ => None,
+        },
+    }
+}
+
+/// The type polars gives an integer literal on its own.
+fn literal_type(value: i64) -> DataType {
+    match i32::try_from(value) {
+        Ok(_) => DataType::Int32,
+        Err(_) => DataType::Int64,
+    }
+}
+
+/// The type polars gives arithmetic between an integer of type `dtype` and
+/// an integer literal.
+fn literal_supertype(dtype: &DataType) -> Option<DataType> {
+    match dtype.is_unsigned_integer() {
+        true => integer_supertype(dtype, &DataType::Int64),
+        false => Some(dtype.clone()),
+    }
+}
+
+struct Checker<'a> {
+    schema: &'a Schema,
+    functions: &'a FunctionRegistry,
+    /// Functions defined by the program so far, with their parameter counts.
+    defs: HashMap<String, usize>,
+    /// `let` bindings and parameters that shadow columns, with their types
+    /// where known. Later entries shadow earlier ones.
+    scope: Vec<(String, Option<DataType>)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(schema: &'a Schema, functions: &'a FunctionRegistry) -> Self {
+        Self {
+            schema,
+            functions,
+            defs: HashMap::new(),
+            scope: vec![],
+            diagnostics: vec![],
+        }
+    }
+
+    /// Checks each statement of `program` in turn, returning the type of
+    /// the result where it can be worked out.
+    fn program(&mut self, program: &Program, spans: &ProgramSpans) -> Option<DataType> {
+        for (statement, spans) in program.statements.iter().zip(&spans.statements) {
+            match statement {
+                Statement::Let { name, value } => {
+                    let dtype = self.expr(value, spans);
+                    self.scope.push((name.clone(), dtype));
+                }
+                Statement::Def { name, params, body } => {
+                    let outer = self.scope.len();
+                    self.scope
+                        .extend(params.iter().map(|param| (param.clone(), None)));
+                    self.expr(body, spans);
+                    self.scope.truncate(outer);
+                    self.defs.insert(name.clone(), params.len());
+                }
+            }
+        }
+        self.expr(&program.result, &spans.result)
+    }
+
+    /// Adds a diagnostic pointing at the name the expression at `spans` is
+    /// known by, e.g. the field of an attribute, or all of it if it has none.
+    fn report(&mut self, spans: &Spans, message: String, help: Option<String>) {
+        self.diagnostics
+            .push(Diagnostic::new(message, spans.name.clone()).with_help(help));
+    }
+
+    /// Checks `expr`, written at `spans`, returning its type where it can be
+    /// worked out.
+    fn expr(&mut self, expr: &Expr, spans: &Spans) -> Option<DataType> {
+        let children = &spans.children;
+        match expr {
+            Expr::Int(_) => Some(DataType::Int64),
+            Expr::Float(_) => Some(DataType::Float64),
+            Expr::Str(_) => Some(DataType::String),
+            Expr::Ident(name) => {
+                if let Some((_, dtype)) = self.scope.iter().rev().find(|(bound, _)| bound == name) {
+                    return dtype.clone();
+                }
+                let dtype = self.schema.get(name).cloned();
+                if dtype.is_none() {
+                    let candidates = self
+                        .schema
+                        .iter_names()
+                        .map(|name| name.as_str())
+                        .chain(self.scope.iter().map(|(name, _)| name.as_str()));
+                    let help = did_you_mean(name, candidates);
+                    self.report(spans, format!("unknown column `{}`", name), help);
+                }
+                dtype
+            }
+            Expr::Topic(name) => self.schema.get(name).cloned(),
+            Expr::Attribute { obj, attr } => match self.expr(obj, &children[0])? {
+                DataType::Struct(fields) => {
+                    let field = fields.iter().find(|field| field.name() == attr.as_str());
+                    if field.is_none() {
+                        let help =
+                            did_you_mean(attr, fields.iter().map(|field| field.name().as_str()));
+                        self.report(spans, format!("no field `{}`", attr), help);
+                    }
+                    field.map(|field| field.dtype().clone())
+                }
+                dtype => {
+                    self.report(
+                        spans,
+                        format!("no field `{}`, {} has no fields", attr, dtype),
+                        None,
+                    );
+                    None
+                }
+            },
+            Expr::ArrayIndex { obj, index } => {
+                let list = self.list(obj, &children[0]);
+                self.index(index, &children[1]);
+                match list? {
+                    DataType::List(inner) => Some(*inner),
+                    _ => None,
+                }
+            }
+            Expr::ArraySlice {
+                obj,
+                start,
+                end,
+                step,
+            } => {
+                let list = self.list(obj, &children[0]);
+                let bounds = [start, end, step].into_iter().flatten();
+                for (bound, spans) in bounds.zip(&children[1..]) {
+                    self.index(bound, spans);
+                }
+                list
+            }
+            Expr::Call { name, args } => {
+                self.call(spans, name, args);
+                None
+            }
+            Expr::BinOp { lhs, op, rhs } => self.bin_op(lhs, op, rhs, children),
+            Expr::Wildcard { obj, over } => {
+                let dtype = self.expr(obj, &children[0])?;
+                let (kind, wildcard, needs) = match over {
+                    Wildcard::Fields => (Kind::Struct, ".*", "a struct"),
+                    Wildcard::Elements => (Kind::List, "[*]", "a list"),
+                };
+                if Kind::of(&dtype) != kind {
+                    self.report(
+                        &children[0],
+                        format!("`{}` needs {}, found {}", wildcard, needs, dtype),
+                        None,
+                    );
+                    return None;
+                }
+                Some(dtype)
+            }
+        }
+    }
+
+    /// Checks `obj` can be indexed, returning its type if so.
+    fn list(&mut self, obj: &Expr, spans: &Spans) -> Option<DataType> {
+        let dtype = self.expr(obj, spans)?;
+        match Kind::of(&dtype) {
+            Kind::List => Some(dtype),
+            _ => {
+                self.report(
+                    spans,
+                    format!("only lists can be indexed, found {}", dtype),
+                    None,
+                );
+                None
+            }
+        }
+    }
+
+    fn index(&mut self, index: &Expr, spans: &Spans) {
+        if let Some(dtype) = self.expr(index, spans) {
+            if Kind::of(&dtype) != Kind::Integer {
+                self.report(
+                    spans,
+                    format!("list indices must be integers, found {}", dtype),
+                    None,
+                );
+            }
+        }
+    }
+
+    fn call(&mut self, spans: &Spans, name: &str, args: &[Expr]) {
+        let arg_types: Vec<Option<DataType>> = args
+            .iter()
+            .zip(&spans.children)
+            .map(|(arg, spans)| self.expr(arg, spans))
+            .collect();
+
+        if let Some(params) = self.defs.get(name) {
+            if *params != args.len() {
+                let message = format!(
+                    "`{}` takes {} argument{}, found {}",
+                    name,
+                    params,
+                    if *params == 1 { "" } else { "s" },
+                    args.len()
+                );
+                self.report(spans, message, None);
+            }
+            return;
+        }
+
+        let Some(function) = self.functions.get(name) else {
+            let candidates = self
+                .functions
+                .iter()
+                .map(|function| function.name.as_str())
+                .chain(self.defs.keys().map(String::as_str));
+            let help = did_you_mean(name, candidates);
+            self.report(spans, format!("unknown function `{}`", name), help);
+            return;
+        };
+        if let Err(error) = function.check_arity(args.len()) {
+            self.report(spans, error.to_string(), None);
+            return;
+        }
+
+        for (index, (arg, dtype)) in args.iter().zip(&arg_types).enumerate() {
+            let expected = match function.param_type(index) {
+                ArgType::Literal => match arg {
+                    Expr::Int(_) | Expr::Float(_) | Expr::Str(_) => continue,
+                    _ => "a number or string literal",
+                },
+                ArgType::Any => continue,
+                ty => {
+                    let Some(dtype) = dtype else { continue };
+                    match (ty, Kind::of(dtype)) {
+                        (ArgType::Numeric, Kind::Text | Kind::List | Kind::Struct) => "numeric",
+                        (ArgType::List, kind) if kind != Kind::List => "a list",
+                        (ArgType::Struct, kind) if kind != Kind::Struct => "a struct",
+                        _ => continue,
+                    }
+                }
+            };
+            let found = match dtype {
+                Some(dtype) => format!(", found {}", dtype),
+                None => "".to_owned(),
+            };
+            self.report(
+                spans,
+                format!(
+                    "argument {} of `{}` must be {}{}",
+                    index + 1,
+                    function.signature(),
+                    expected,
+                    found
+                ),
+                None,
+            );
+        }
+    }
+
+    /// Checks `lhs op rhs`, whose operands are written at `spans`.
+    fn bin_op(&mut self, lhs: &Expr, op: &Op, rhs: &Expr, spans: &[Spans]) -> Option<DataType> {
+        let types = [self.expr(lhs, &spans[0]), self.expr(rhs, &spans[1])];
+        let kinds = types.clone().map(|dtype| dtype.as_ref().map(Kind::of));
+
+        // The first operand of a kind `op` doesn't work on
+        let reject = |checker: &mut Self, allowed: &dyn Fn(Kind) -> bool, expected: &str| {
+            let operand = spans
+                .iter()
+                .zip(&types)
+                .find(|(_, dtype)| matches!(dtype, Some(dtype) if !allowed(Kind::of(dtype))));
+            if let Some((operand, Some(dtype))) = operand {
+                checker.report(
+                    operand,
+                    format!("`{}` needs {}, found {}", op, expected, dtype),
+                    None,
+                );
+                return true;
+            }
+            false
+        };
+
+        match op {
+            Op::Add | Op::Subtract | Op::Multiply | Op::Divide | Op::Modulus | Op::Power => {
+                let element_wise = !matches!(op, Op::Power);
+                let allowed = |kind: Kind| match kind {
+                    Kind::Text => false,
+                    Kind::List | Kind::Struct => element_wise,
+                    _ => true,
+                };
+                let expected = match element_wise {
+                    true => "numbers, lists or structs",
+                    false => "numbers",
+                };
+                if reject(self, &allowed, expected) {
+                    return None;
+                }
+                match kinds {
+                    [Some(Kind::List | Kind::Struct), _] => types[0].clone(),
+                    [_, Some(Kind::List | Kind::Struct)] => types[1].clone(),
+                    // Polars floor divides integers, raises them to the type
+                    // of the base, and gives literals the other operand's type
+                    // unless it's unsigned, as the literal might be negative
+                    [Some(Kind::Integer), Some(Kind::Integer)] => match (op, lhs, rhs) {
+                        (Op::Power, Expr::Int(base), _) => Some(literal_type(*base)),
+                        (Op::Power, _, _) => types[0].clone(),
+                        // Polars folds arithmetic on literals before typing it
+                        (_, Expr::Int(lhs), Expr::Int(rhs)) => {
+                            optimize::fold_int(*lhs, op, *rhs).map(literal_type)
+                        }
+                        (_, Expr::Int(_), _) => literal_supertype(types[1].as_ref()?),
+                        (_, _, Expr::Int(_)) => literal_supertype(types[0].as_ref()?),
+                        _ => integer_supertype(types[0].as_ref()?, types[1].as_ref()?),
+                    },
+                    _ if matches!(op, Op::Divide | Op::Power) => Some(DataType::Float64),
+                    [Some(Kind::Float), Some(_)] | [Some(_), Some(Kind::Float)] => {
+                        Some(DataType::Float64)
+                    }
+                    _ => None,
+                }
+            }
+            Op::Equal | Op::NotEqual => {
+                let text = kinds.map(|kind| kind == Some(Kind::Text));
+                let number = kinds
+                    .map(|kind| matches!(kind, Some(Kind::Integer | Kind::Float | Kind::Boolean)));
+                if (text[0] && number[1]) || (number[0] && text[1]) {
+                    let [lhs_type, rhs_type] = types.map(Option::unwrap);
+                    self.report(
+                        &spans[0],
+                        format!("can't compare {} with {}", lhs_type, rhs_type),
+                        None,
+                    );
+                }
+                Some(DataType::Boolean)
+            }
+            Op::BitAnd | Op::BitOr | Op::BitXor => {
+                let allowed = |kind: Kind| matches!(kind, Kind::Integer | Kind::Boolean);
+                if reject(self, &allowed, "integers or booleans") {
+                    return None;
+                }
+                match kinds {
+                    [Some(Kind::Boolean), Some(Kind::Boolean)] => Some(DataType::Boolean),
+                    [Some(Kind::Integer), _] => types[0].clone(),
+                    _ => None,
+                }
+            }
+            Op::ShiftLeft | Op::ShiftRight => {
+                if reject(self, &|kind: Kind| kind == Kind::Integer, "integers") {
+                    return None;
+                }
+                types[0]
+                    .as_ref()
+                    .map(|dtype| match dtype.is_unsigned_integer() {
+                        true => DataType::UInt64,
+                        false => DataType::Int64,
+                    })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_program_with_spans;
+    use polars::prelude::{DataFrame, Field, IntoLazy};
+
+    fn schema() -> Schema {
+        let position = DataType::Struct(vec![
+            Field::new("x".into(), DataType::Float64),
+            Field::new("y".into(), DataType::Float64),
+            Field::new("data".into(), DataType::List(Box::new(DataType::Float64))),
+        ]);
+        Schema::from_iter([
+            Field::new("utime".into(), DataType::Int64),
+            Field::new("status".into(), DataType::UInt16),
+            Field::new("mode".into(), DataType::String),
+            Field::new("position".into(), position),
+            Field::new("data".into(), DataType::List(Box::new(DataType::Float64))),
+            Field::new("blob".into(), DataType::Binary),
+        ])
+    }
+
+    fn check(source: &str) -> Vec<String> {
+        let (program, spans) = parse_program_with_spans(source).unwrap();
+        super::check(
+            &program,
+            &spans,
+            &schema(),
+            &FunctionRegistry::with_builtins(),
+        )
+        .iter()
+        .map(ToString::to_string)
+        .collect()
+    }
+
+    #[test]
+    fn test_valid() {
+        for source in [
+            "let p = position\np.x + sin(utime) + data[0]",
+            "def f(a) = a * 2\nf(position.y)",
+            "position.data[1:] * 2 - data",
+            "mode == \"HOVER\"",
+            "bit(status, 3) | status >> 4 & 1",
+            "lowpass(position.x, 5, utime / 1000000.0)",
+            "explode(position.data)",
+            "code(mode)",
+        ] {
+            assert_eq!(check(source), Vec::<String>::new(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_names() {
+        let (program, spans) =
+            parse_program_with_spans("let s = \"posiiton\" # posiiton\n1 + posiiton.x").unwrap();
+        let diagnostics = super::check(
+            &program,
+            &spans,
+            &schema(),
+            &FunctionRegistry::with_builtins(),
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "unknown column `posiiton`");
+        assert_eq!(diagnostics[0].span, 34..42);
+        assert_eq!(
+            diagnostics[0].help.as_deref(),
+            Some("did you mean `position`?")
+        );
+
+        assert_eq!(
+            check("position.z + sn(x.y) + position.data.x"),
+            [
+                "no field `z`, did you mean `x`?",
+                "unknown column `x`",
+                "unknown function `sn`, did you mean `sin`?",
+                "no field `x`, list[f64] has no fields",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_result_type() {
+        // Polars can't build empty columns of every type in `schema()`
+        let schema = Schema::from_iter([
+            Field::new("utime".into(), DataType::Int64),
+            Field::new("count".into(), DataType::Int32),
+            Field::new("unsigned".into(), DataType::UInt32),
+            Field::new("x".into(), DataType::Float64),
+        ]);
+        let functions = FunctionRegistry::with_builtins();
+        let df = DataFrame::empty_with_schema(&schema).lazy();
+        for source in [
+            "utime / 2",
+            "utime / count",
+            "count / utime",
+            "unsigned / count",
+            "unsigned % unsigned",
+            "unsigned * 2",
+            "-2 * count",
+            "3000000000 / 2",
+            "utime / 2.0",
+            "x / 2",
+            "count ^ utime",
+            "unsigned ^ 2",
+            "2 ^ utime",
+            "3000000000 ^ count",
+            "utime ^ 0.5",
+        ] {
+            let (program, spans) = parse_program_with_spans(source).unwrap();
+            let expr = crate::to_polars_expr(&program.result).unwrap();
+            // Literals are only given a type when the frame is collected
+            let polars = df.clone().select([expr]).collect().unwrap().schema();
+            assert_eq!(
+                result_type(&program, &spans, &schema, &functions).as_ref(),
+                polars.get_at_index(0).map(|(_, dtype)| dtype),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_types() {
+        assert_eq!(
+            check("position[0] + data[1.5] + utime[0:2]"),
+            [
+                "only lists can be indexed, found struct[3]",
+                "list indices must be integers, found f64",
+                "only lists can be indexed, found i64",
+            ]
+        );
+        assert_eq!(
+            check("sin(mode) + atan2(1) + explode(utime) + lowpass(utime, utime, utime)"),
+            [
+                "argument 1 of `sin(x)` must be numeric, found str",
+                "`atan2(y, x)` takes 2 arguments, found 1",
+                "argument 1 of `explode(list)` must be a list, found i64",
+                "argument 2 of `lowpass(x, cutoff_hz, t, order?, zero_phase?)` must be a number \
+                 or string literal, found i64",
+            ]
+        );
+        assert_eq!(
+            check("mode * 2 + data ^ 2"),
+            [
+                "`*` needs numbers, lists or structs, found str",
+                "`^` needs numbers, found list[f64]",
+            ]
+        );
+        assert_eq!(
+            check("(mode == 1) + (position.x & 1) + (position.x << 2)"),
+            [
+                "can't compare str with i64",
+                "`&` needs integers or booleans, found f64",
+                "`<<` needs integers, found f64",
+            ]
+        );
+        assert_eq!(
+            check("blob"),
+            ["the result is binary, which can't be plotted"]
+        );
+    }
+}
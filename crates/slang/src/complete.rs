@@ -0,0 +1,372 @@
+//! Completions for the word being typed at a cursor, e.g. the fields of
+//! `position` after `position.`. Works on incomplete source, by looking at
+//! the text around the cursor rather than parsing all of it.
+
+use polars::prelude::{DataType, Schema};
+use std::ops::Range;
+
+use crate::functions::{FunctionRegistry, BUILTINS};
+use crate::parser::{parse_program_with_spans, Expr};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompletionKind {
+    Column,
+    /// A `let` binding or a `def` earlier in the source.
+    Binding,
+    Field,
+    Function,
+    Index,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Completion {
+    /// What to insert, names that aren't identifiers are quoted in backticks.
+    pub text: String,
+    /// Shown alongside the text, e.g. the type of a column or the signature
+    /// of a function.
+    pub detail: String,
+    pub kind: CompletionKind,
+    /// Byte range of the source `text` replaces, the part of the word typed
+    /// before the cursor.
+    pub replace: Range<usize>,
+}
+
+/// Completions at byte offset `cursor` of `source`, resolving calls against
+/// the builtin functions.
+pub fn complete(source: &str, cursor: usize, schema: &Schema) -> Vec<Completion> {
+    complete_with(source, cursor, schema, &BUILTINS)
+}
+
+/// Like [`complete`], offering the functions in `functions`.
+pub fn complete_with(
+    source: &str,
+    cursor: usize,
+    schema: &Schema,
+    functions: &FunctionRegistry,
+) -> Vec<Completion> {
+    let cursor = cursor.min(source.len());
+    if !source.is_char_boundary(cursor) {
+        return vec![];
+    }
+    let Some(word) = word_at(source, cursor) else {
+        return vec![];
+    };
+    let prefix = word.prefix.to_lowercase();
+    let matches = |name: &str| name.to_lowercase().starts_with(&prefix);
+    let completion = |name: &str, detail: String, kind| Completion {
+        text: Expr::Ident(name.to_owned()).to_string(),
+        detail,
+        kind,
+        replace: word.start..cursor,
+    };
+
+    match source[..word.start].chars().next_back() {
+        Some('.') => {
+            let dtype = object_type(source, word.start - 1, schema, functions);
+            match dtype {
+                Some(DataType::Struct(fields)) => fields
+                    .iter()
+                    .filter(|field| matches(field.name()))
+                    .map(|field| {
+                        completion(
+                            field.name(),
+                            field.dtype().to_string(),
+                            CompletionKind::Field,
+                        )
+                    })
+                    .collect(),
+                _ => vec![],
+            }
+        }
+        Some('[') if !word.quoted => {
+            let dtype = object_type(source, word.start - 1, schema, functions);
+            match dtype {
+                Some(DataType::List(inner)) => [("0", "first"), ("-1", "last")]
+                    .into_iter()
+                    .filter(|(index, _)| index.starts_with(word.prefix))
+                    .map(|(index, position)| Completion {
+                        text: index.to_owned(),
+                        detail: format!("{} {}", position, inner),
+                        kind: CompletionKind::Index,
+                        replace: word.start..cursor,
+                    })
+                    .collect(),
+                _ => vec![],
+            }
+        }
+        // Numbers aren't names
+        _ if word.prefix.starts_with(|c: char| c.is_ascii_digit()) => vec![],
+        _ => {
+            let mut completions: Vec<Completion> = bindings(&source[..word.start])
+                .into_iter()
+                .filter(|(name, _)| matches(name))
+                .map(|(name, detail)| completion(&name, detail, CompletionKind::Binding))
+                .collect();
+            completions.extend(
+                schema
+                    .iter()
+                    .filter(|(name, _)| matches(name))
+                    .map(|(name, dtype)| {
+                        completion(name, dtype.to_string(), CompletionKind::Column)
+                    }),
+            );
+            // Functions are called by name, they can't be quoted
+            if !word.quoted {
+                completions.extend(
+                    functions
+                        .iter()
+                        .filter(|function| matches(&function.name))
+                        .map(|function| {
+                            completion(
+                                &function.name,
+                                function.signature(),
+                                CompletionKind::Function,
+                            )
+                        }),
+                );
+            }
+            completions
+        }
+    }
+}
+
+/// The part of a name typed before the cursor.
+struct Word<'a> {
+    /// Where the word starts, at the opening backtick of a quoted name.
+    start: usize,
+    prefix: &'a str,
+    quoted: bool,
+}
+
+/// The word being typed at `cursor`, or `None` inside a string or comment.
+fn word_at(source: &str, cursor: usize) -> Option<Word<'_>> {
+    let bytes = source.as_bytes();
+    let mut index = 0;
+    let mut quote = None;
+    while index < cursor {
+        match bytes[index] {
+            b'"' | b'`' if quote.is_none() => quote = Some(index),
+            byte if quote.is_some_and(|start| bytes[start] == byte) => quote = None,
+            b'\\' if quote.is_some() => index += 1,
+            b'#' if quote.is_none() => {
+                while index < cursor && bytes[index] != b'\n' {
+                    index += 1;
+                }
+                if index == cursor {
+                    return None;
+                }
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+
+    match quote {
+        Some(start) if bytes[start] == b'`' => Some(Word {
+            start,
+            prefix: &source[start + 1..cursor],
+            quoted: true,
+        }),
+        Some(_) => None,
+        None => {
+            let start = source[..cursor]
+                .char_indices()
+                .rev()
+                .find(|&(_, c)| !(c.is_ascii_alphanumeric() || c == '_'))
+                .map_or(0, |(index, c)| index + c.len_utf8());
+            Some(Word {
+                start,
+                prefix: &source[start..cursor],
+                quoted: false,
+            })
+        }
+    }
+}
+
+/// The type of the value a trailer at byte `end` of `source` follows, e.g.
+/// `position.data` for the `.` of `position.data.x`. Earlier lines are taken
+/// into account where they are complete `let`s and `def`s.
+fn object_type(
+    source: &str,
+    end: usize,
+    schema: &Schema,
+    functions: &FunctionRegistry,
+) -> Option<DataType> {
+    let object = &source[object_start(source, end)..end];
+    let line_start = source[..end].rfind('\n').map_or(0, |index| index + 1);
+    let (program, spans) =
+        parse_program_with_spans(&format!("{}{}", &source[..line_start], object))
+            .or_else(|_| parse_program_with_spans(object))
+            .ok()?;
+    crate::check::result_type(&program, &spans, schema, functions)
+}
+
+/// Where the chain of names and trailers ending at byte `end` starts.
+fn object_start(source: &str, end: usize) -> usize {
+    let bytes = source.as_bytes();
+    let is_name = |byte: u8| byte.is_ascii_alphanumeric() || byte == b'_';
+    let mut start = end;
+    loop {
+        match start.checked_sub(1).map(|index| bytes[index]) {
+            // A call or index, what it applies to comes before
+            Some(close @ (b')' | b']')) => {
+                let open = if close == b')' { b'(' } else { b'[' };
+                let mut depth = 0;
+                while start > 0 {
+                    start -= 1;
+                    match bytes[start] {
+                        byte if byte == close => depth += 1,
+                        byte if byte == open => depth -= 1,
+                        _ => {}
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                continue;
+            }
+            Some(b'`') => match source[..start - 1].rfind('`') {
+                Some(open) => start = open,
+                None => return end,
+            },
+            Some(byte) if is_name(byte) => {
+                while start > 0 && is_name(bytes[start - 1]) {
+                    start -= 1;
+                }
+                // A topic, unless the `/` divides a value before it
+                let mut topic = start;
+                while topic > 1 && bytes[topic - 1] == b'/' && is_name(bytes[topic - 2]) {
+                    topic -= 2;
+                    while topic > 0 && is_name(bytes[topic - 1]) {
+                        topic -= 1;
+                    }
+                }
+                if topic > 0 && bytes[topic - 1] == b'/' {
+                    topic -= 1;
+                    let divides = topic > 0
+                        && (matches!(bytes[topic - 1], b')' | b']' | b'`')
+                            || is_name(bytes[topic - 1]));
+                    if !divides {
+                        start = topic;
+                    }
+                }
+            }
+            _ => return start,
+        }
+        match start.checked_sub(1).map(|index| bytes[index]) {
+            Some(b'.') => start -= 1,
+            _ => return start,
+        }
+    }
+}
+
+/// Names bound by `let` or `def` in `source`, with what they are.
+fn bindings(source: &str) -> Vec<(String, String)> {
+    let mut bindings = vec![];
+    for line in source.lines() {
+        let line = line.trim_start();
+        let (keyword, rest) = match line.split_once(char::is_whitespace) {
+            Some((keyword @ ("let" | "def"), rest)) => (keyword, rest.trim_start()),
+            _ => continue,
+        };
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if end == 0 {
+            continue;
+        }
+        let detail = match keyword {
+            "def" => match rest.find(')') {
+                Some(close) => format!("def {}", &rest[..=close]),
+                None => "def".to_owned(),
+            },
+            _ => "let".to_owned(),
+        };
+        bindings.push((rest[..end].to_owned(), detail));
+    }
+    bindings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::Field;
+
+    fn schema() -> Schema {
+        let position = DataType::Struct(vec![
+            Field::new("x".into(), DataType::Float64),
+            Field::new("data".into(), DataType::List(Box::new(DataType::Float64))),
+            Field::new("Motor RPM".into(), DataType::Float64),
+        ]);
+        Schema::from_iter([
+            Field::new("utime".into(), DataType::Int64),
+            Field::new("position".into(), position.clone()),
+            Field::new("poses".into(), DataType::List(Box::new(position.clone()))),
+            Field::new("/imu".into(), position),
+            Field::new("sine wave".into(), DataType::Float64),
+        ])
+    }
+
+    /// Completions at the `|` in `source`.
+    fn complete(source: &str) -> Vec<String> {
+        let cursor = source.find('|').unwrap();
+        let source = source.replace('|', "");
+        super::complete(&source, cursor, &schema())
+            .into_iter()
+            .map(|completion| completion.text)
+            .collect()
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(complete("po|"), ["position", "poses"]);
+        assert_eq!(
+            complete("1 + sin(u|"),
+            ["utime", "unwrap_angle", "unwrap_counter"]
+        );
+        assert_eq!(complete("si|"), ["`sine wave`", "sin"]);
+        assert_eq!(complete("`si|"), ["`sine wave`"]);
+        assert!(complete("|").len() > 40);
+        assert_eq!(complete("let speed = 1\nsp|"), ["speed"]);
+        assert_eq!(
+            complete("def sq(v) = v * v\ns|"),
+            ["sq", "`sine wave`", "sin", "starts_with"]
+        );
+
+        // Nothing to complete in strings, comments and numbers
+        assert!(complete("\"po|").is_empty());
+        assert!(complete("1 # po|").is_empty());
+        assert!(complete("12|").is_empty());
+
+        // Names end at characters of any width
+        assert_eq!(complete("°+po|"), ["position", "poses"]);
+        assert_eq!(complete("a + µ|").len(), complete("|").len());
+        assert!(complete("\"µ|").is_empty());
+
+        let completions = super::complete("at + 1", 2, &schema());
+        assert_eq!(completions[0].replace, 0..2);
+        assert_eq!(completions[0].detail, "atan2(y, x)");
+    }
+
+    #[test]
+    fn test_fields() {
+        assert_eq!(complete("position.|"), ["x", "data", "`Motor RPM`"]);
+        assert_eq!(complete("2 * position.d|"), ["data"]);
+        assert_eq!(complete("poses[0].|"), ["x", "data", "`Motor RPM`"]);
+        assert_eq!(complete("/imu.M|"), ["`Motor RPM`"]);
+        assert_eq!(complete("topic(\"/imu\").x|"), ["x"]);
+        assert_eq!(complete("let p = poses[-1]\np.x|"), ["x"]);
+        assert_eq!(complete("position.data[0] + position.`M|"), ["`Motor RPM`"]);
+        // Incomplete, but the object before the `.` is complete
+        assert_eq!(complete("sin(position.d|"), ["data"]);
+        assert!(complete("utime.|").is_empty());
+        assert!(complete("nope.|").is_empty());
+    }
+
+    #[test]
+    fn test_indices() {
+        assert_eq!(complete("position.data[|"), ["0", "-1"]);
+        assert_eq!(complete("poses[|]"), ["0", "-1"]);
+        assert!(complete("utime[|").is_empty());
+    }
+}
@@ -0,0 +1,86 @@
+use anyhow::{Context as _, Result};
+use std::path::Path;
+
+use super::functions::{Function, FunctionRegistry};
+use super::parser::{parse_library, Statement};
+use super::topics::Topics;
+
+/// Everything besides the data that expressions are evaluated against.
+#[derive(Clone, Debug)]
+pub struct Context {
+    pub functions: FunctionRegistry,
+    /// Other topics expressions can refer to, e.g. `/imu.accel.x`.
+    pub topics: Topics,
+    /// The most traces a list or struct result is split into, e.g. by
+    /// `position.*`. Any further elements or fields are left out.
+    pub max_fields: usize,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self {
+            functions: FunctionRegistry::with_builtins(),
+            topics: Topics::new(),
+            max_fields: 100,
+        }
+    }
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defines every function in a `.slang` library file, replacing any
+    /// existing functions with the same names.
+    pub fn load_library(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read slang library {}", path.display()))?;
+        self.load_library_source(&source)
+            .with_context(|| format!("Failed to load slang library {}", path.display()))
+    }
+
+    pub fn load_library_source(&mut self, source: &str) -> Result<()> {
+        let mut names = vec![];
+        for statement in parse_library(source)? {
+            if let Statement::Def { name, params, body } = statement {
+                self.functions
+                    .register(Function::define(&name, &params, body)?);
+                names.push(name);
+            }
+        }
+        // Only once every function is defined, they can call each other
+        for name in names {
+            self.functions.check_recursion(&name)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_library() {
+        let path = std::env::temp_dir().join("slang_test_load_library.slang");
+        std::fs::write(&path, "# helpers\ndef double(x) = x * 2\n").unwrap();
+
+        let mut context = Context::new();
+        context.load_library(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            context.functions.get("double").unwrap().signature(),
+            "double(x)"
+        );
+        assert!(context.functions.get("sin").is_some());
+
+        assert!(context.load_library(&path).is_err());
+        assert!(context.load_library_source("double(1)").is_err());
+        assert!(context
+            .load_library_source("def f(x) = g(x)\ndef g(x) = f(x)")
+            .is_err());
+    }
+}
@@ -0,0 +1,155 @@
+//! Errors that point at the part of an expression they are about, e.g.
+//!
+//! ```text
+//! unknown column `posiiton`
+//!   |
+//! 1 | posiiton.x * 2
+//!   | ^^^^^^^^
+//!   = did you mean `position`?
+//! ```
+
+use std::fmt;
+use std::ops::Range;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    /// Byte range of the source the diagnostic is about, empty at the end
+    /// of the source.
+    pub span: Range<usize>,
+    /// A likely fix, e.g. "did you mean `position`?".
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, span: Range<usize>) -> Self {
+        Self {
+            message: message.into(),
+            span,
+            help: None,
+        }
+    }
+
+    pub fn with_help(self, help: Option<String>) -> Self {
+        Self { help, ..self }
+    }
+
+    /// The message, the line of `source` it is on with a caret under the
+    /// span, and the help if any.
+    pub fn render(&self, source: &str) -> String {
+        let start = self.span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |index| index + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |index| start + index);
+        let line = &source[line_start..line_end];
+        let number = source[..line_start].matches('\n').count() + 1;
+
+        let column = source[line_start..start].chars().count();
+        let width = source[start..self.span.end.clamp(start, line_end)]
+            .chars()
+            .count()
+            .max(1);
+        let gutter = " ".repeat(number.to_string().len());
+
+        let mut out = format!(
+            "{}\n{} |\n{} | {}\n{} | {}{}",
+            self.message,
+            gutter,
+            number,
+            line,
+            gutter,
+            " ".repeat(column),
+            "^".repeat(width)
+        );
+        if let Some(help) = &self.help {
+            out.push_str(&format!("\n{} = {}", gutter, help));
+        }
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.help {
+            Some(help) => write!(f, "{}, {}", self.message, help),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Every problem found with an expression, as one error.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let messages: Vec<String> = self.0.iter().map(ToString::to_string).collect();
+        write!(f, "{}", messages.join("\n"))
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+/// "did you mean `candidate`?" for the candidate closest to `name`, if any
+/// is close enough to be a typo.
+pub fn did_you_mean<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<String> {
+    let name = name.to_lowercase();
+    candidates
+        .into_iter()
+        .map(|candidate| (distance(&name, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, candidate)| *distance <= (candidate.chars().count() / 3).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| format!("did you mean `{}`?", candidate))
+}
+
+/// Levenshtein distance between `a` and `b`, in characters.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(a != *b);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_did_you_mean() {
+        assert_eq!(distance("kitten", "sitting"), 3);
+        assert_eq!(
+            did_you_mean("posiiton", ["utime", "position"]),
+            Some("did you mean `position`?".to_owned())
+        );
+        assert_eq!(did_you_mean("speed", ["utime", "position"]), None);
+    }
+
+    #[test]
+    fn test_render() {
+        let source = "let a = 1\nposiiton.x * a";
+        let diagnostic = Diagnostic::new("unknown column `posiiton`", 10..18)
+            .with_help(Some("did you mean `position`?".to_owned()));
+        assert_eq!(
+            diagnostic.render(source),
+            "unknown column `posiiton`\n  |\n2 | posiiton.x * a\n  | ^^^^^^^^\n  = did you mean `position`?"
+        );
+        assert_eq!(
+            Diagnostic::new("expected a value", 4..4).render("1 + "),
+            "expected a value\n  |\n1 | 1 + \n  |     ^"
+        );
+    }
+}
@@ -0,0 +1,376 @@
+//! Turns the AST back into source text, e.g. to normalize saved expressions
+//! or label a trace. Parentheses are only added where precedence needs them,
+//! and the output parses back to the same AST: `parse(&e.to_string()) == e`.
+//! The one exception is non-finite floats, which slang can't write.
+
+use std::fmt::{self, Display, Formatter};
+
+use crate::parser::{Expr, Op, Program, Statement, Wildcard};
+
+impl Op {
+    fn symbol(&self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::Subtract => "-",
+            Op::Multiply => "*",
+            Op::Divide => "/",
+            Op::Power => "^",
+            Op::Modulus => "%",
+            Op::Equal => "==",
+            Op::NotEqual => "!=",
+            Op::BitAnd => "&",
+            Op::BitOr => "|",
+            Op::BitXor => "xor",
+            Op::ShiftLeft => "<<",
+            Op::ShiftRight => ">>",
+        }
+    }
+
+    /// How tightly `self` binds, as in the parser's Pratt table.
+    fn precedence(&self) -> u8 {
+        match self {
+            Op::Equal | Op::NotEqual => 0,
+            Op::BitOr => 1,
+            Op::BitXor => 2,
+            Op::BitAnd => 3,
+            Op::ShiftLeft | Op::ShiftRight => 4,
+            Op::Add | Op::Subtract => 5,
+            Op::Multiply | Op::Divide | Op::Modulus => 6,
+            Op::Power => 7,
+        }
+    }
+}
+
+impl Display for Op {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Int(value) => write!(f, "{}", value),
+            Expr::Float(value) => {
+                // The shortest form that parses back to `value`, but slang
+                // floats always have a decimal point, e.g. `1.0e20`
+                let text = format!("{:?}", value);
+                match (text.contains('.'), text.find('e')) {
+                    (false, Some(e)) => write!(f, "{}.0{}", &text[..e], &text[e..]),
+                    _ => f.write_str(&text),
+                }
+            }
+            Expr::Str(value) => write!(f, "\"{}\"", escape(value, '"')),
+            Expr::Ident(name) => write_name(f, name),
+            Expr::Topic(name) => match is_topic(name) {
+                true => f.write_str(name),
+                false => write!(f, "topic(\"{}\")", escape(name, '"')),
+            },
+            Expr::Call { name, args } => {
+                write_name(f, name)?;
+                f.write_str("(")?;
+                for (index, arg) in args.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                f.write_str(")")
+            }
+            Expr::Attribute { obj, attr } => {
+                write_trailed(f, obj)?;
+                f.write_str(".")?;
+                write_name(f, attr)
+            }
+            Expr::ArrayIndex { obj, index } => {
+                write_trailed(f, obj)?;
+                write!(f, "[{}]", index)
+            }
+            Expr::ArraySlice {
+                obj,
+                start,
+                end,
+                step,
+            } => {
+                write_trailed(f, obj)?;
+                f.write_str("[")?;
+                if let Some(start) = start {
+                    write!(f, "{}", start)?;
+                }
+                f.write_str(":")?;
+                if let Some(end) = end {
+                    write!(f, "{}", end)?;
+                }
+                if let Some(step) = step {
+                    write!(f, ":{}", step)?;
+                }
+                f.write_str("]")
+            }
+            Expr::BinOp { lhs, op, rhs } => {
+                // Every operator is left associative, so an operand on the
+                // right that binds as loosely still needs parentheses
+                let lhs_parens = binds_looser(lhs, op.precedence());
+                let rhs_parens = binds_looser(rhs, op.precedence() + 1);
+                write_operand(f, lhs, lhs_parens)?;
+                write!(f, " {} ", op)?;
+                write_operand(f, rhs, rhs_parens)
+            }
+            Expr::Wildcard { obj, over } => {
+                write_trailed(f, obj)?;
+                match over {
+                    Wildcard::Fields => f.write_str(".*"),
+                    Wildcard::Elements => f.write_str("[*]"),
+                }
+            }
+        }
+    }
+}
+
+impl Display for Statement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Let { name, value } => write!(f, "let {} = {}", name, value),
+            Statement::Def { name, params, body } => {
+                write!(f, "def {}({}) = {}", name, params.join(", "), body)
+            }
+        }
+    }
+}
+
+impl Display for Program {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for statement in &self.statements {
+            writeln!(f, "{}", statement)?;
+        }
+        write!(f, "{}", self.result)
+    }
+}
+
+/// Whether `expr` is an operation binding less tightly than `precedence`.
+fn binds_looser(expr: &Expr, precedence: u8) -> bool {
+    matches!(expr, Expr::BinOp { op, .. } if op.precedence() < precedence)
+}
+
+fn write_operand(f: &mut Formatter<'_>, expr: &Expr, parens: bool) -> fmt::Result {
+    match parens {
+        true => write!(f, "({})", expr),
+        false => write!(f, "{}", expr),
+    }
+}
+
+/// Writes the value a trailer like `.x` or `[0]` follows. The parser only
+/// puts trailers on names, so anything else is parenthesized as the best
+/// that can be done.
+fn write_trailed(f: &mut Formatter<'_>, obj: &Expr) -> fmt::Result {
+    match obj {
+        Expr::Ident(_)
+        | Expr::Topic(_)
+        | Expr::Call { .. }
+        | Expr::Attribute { .. }
+        | Expr::ArrayIndex { .. }
+        | Expr::ArraySlice { .. } => write!(f, "{}", obj),
+        _ => write!(f, "({})", obj),
+    }
+}
+
+/// Writes `name` bare if it is an identifier, otherwise between backticks.
+fn write_name(f: &mut Formatter<'_>, name: &str) -> fmt::Result {
+    let mut chars = name.chars();
+    let bare = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    match bare {
+        true => f.write_str(name),
+        false => write!(f, "`{}`", escape(name, '`')),
+    }
+}
+
+/// Whether `name` can be written as a topic, e.g. `/robot/imu`.
+fn is_topic(name: &str) -> bool {
+    name.strip_prefix('/').is_some_and(|path| {
+        path.split('/').all(|part| {
+            !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+    })
+}
+
+/// Backslash escapes `quote` and backslashes.
+fn escape(s: &str, quote: char) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == quote || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(source: &str) -> String {
+        crate::parse(source).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format("(a+b)*c"), "(a + b) * c");
+        assert_eq!(format("a+(b*c)"), "a + b * c");
+        assert_eq!(format("a-(b-c)"), "a - (b - c)");
+        assert_eq!(format("(a-b)-c"), "a - b - c");
+        assert_eq!(format("(x>>4)&1"), "x >> 4 & 1");
+        assert_eq!(format("2^(1/2)"), "2 ^ (1 / 2)");
+        assert_eq!(format("(a==b)==(c!=d)"), "a == b == (c != d)");
+        assert_eq!(
+            format("f( position.`Motor 1`[1:] , 100000000000000000000.0,\"a\\\"b\")"),
+            "f(position.`Motor 1`[1:], 1.0e20, \"a\\\"b\")"
+        );
+        assert_eq!(format("x[::-1]"), "x[::-1]");
+        assert_eq!(format("a.b.* -c[*]"), "a.b.* - c[*]");
+        assert_eq!(
+            format("topic(\"/imu\").x + topic(\"/a b\")"),
+            "/imu.x + topic(\"/a b\")"
+        );
+
+        let program =
+            crate::parse_program("let a = 1 # one\n\ndef f(x,y) = x*(y+a)\nf(a, 2)").unwrap();
+        assert_eq!(
+            program.to_string(),
+            "let a = 1\ndef f(x, y) = x * (y + a)\nf(a, 2)"
+        );
+    }
+
+    /// A small xorshift generator, so the round trip test is repeatable.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn pick<'a>(&mut self, options: &[&'a str]) -> &'a str {
+            options[self.below(options.len())]
+        }
+    }
+
+    const NAMES: &[&str] = &[
+        "x",
+        "_a1",
+        "xor",
+        "e",
+        "Motor 1",
+        "1st",
+        "a`b",
+        "back\\slash",
+    ];
+    const OPS: &[Op] = &[
+        Op::Add,
+        Op::Subtract,
+        Op::Multiply,
+        Op::Divide,
+        Op::Power,
+        Op::Modulus,
+        Op::Equal,
+        Op::NotEqual,
+        Op::BitAnd,
+        Op::BitOr,
+        Op::BitXor,
+        Op::ShiftLeft,
+        Op::ShiftRight,
+    ];
+
+    /// A random expression of the shapes the parser produces.
+    fn expr(rng: &mut Rng, depth: usize) -> Expr {
+        match rng.below(if depth == 0 { 4 } else { 8 }) {
+            0 => Expr::Int(rng.next() as i64 >> rng.below(64)),
+            1 => loop {
+                let value = f64::from_bits(rng.next());
+                if value.is_finite() {
+                    break Expr::Float(value);
+                }
+            },
+            2 => Expr::Str(
+                rng.pick(&["", "a b", "quote \"", "\\", "#not a comment"])
+                    .into(),
+            ),
+            3 | 4 => trailed(rng, depth),
+            _ => Expr::BinOp {
+                lhs: Box::new(expr(rng, depth - 1)),
+                op: OPS[rng.below(OPS.len())].clone(),
+                rhs: Box::new(expr(rng, depth - 1)),
+            },
+        }
+    }
+
+    /// A random name, call or topic followed by trailers, and maybe a wildcard.
+    fn trailed(rng: &mut Rng, depth: usize) -> Expr {
+        let arg = |rng: &mut Rng| Box::new(expr(rng, depth.saturating_sub(1)));
+        let mut value = match rng.below(3) {
+            0 => Expr::Ident(rng.pick(NAMES).into()),
+            1 => Expr::Topic(
+                rng.pick(&["/imu", "/robot/imu_2", "/with space", "imu"])
+                    .into(),
+            ),
+            _ => Expr::Call {
+                name: rng.pick(NAMES).into(),
+                args: (0..rng.below(3)).map(|_| *arg(rng)).collect(),
+            },
+        };
+        for _ in 0..rng.below(3) {
+            let obj = Box::new(value);
+            value = match rng.below(3) {
+                0 => Expr::Attribute {
+                    obj,
+                    attr: rng.pick(NAMES).into(),
+                },
+                1 => Expr::ArrayIndex {
+                    obj,
+                    index: arg(rng),
+                },
+                _ => {
+                    let part = |rng: &mut Rng| (rng.below(2) == 0).then(|| arg(rng));
+                    Expr::ArraySlice {
+                        obj,
+                        start: part(rng),
+                        end: part(rng),
+                        step: part(rng),
+                    }
+                }
+            };
+        }
+        match rng.below(6) {
+            0 => Expr::Wildcard {
+                obj: Box::new(value),
+                over: Wildcard::Fields,
+            },
+            1 => Expr::Wildcard {
+                obj: Box::new(value),
+                over: Wildcard::Elements,
+            },
+            _ => value,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = Rng(0x5eed);
+        for _ in 0..2000 {
+            let expr = expr(&mut rng, 4);
+            let source = expr.to_string();
+            match crate::parse(&source) {
+                Ok(parsed) => assert_eq!(parsed, expr, "{}", source),
+                Err(error) => panic!("{} doesn't parse: {}", source, error),
+            }
+        }
+    }
+}
@@ -0,0 +1,650 @@
+use anyhow::Result;
+use polars_lazy::prelude::*;
+use std::collections::{BTreeMap, HashSet};
+use std::{f64::consts::FRAC_PI_2, fmt, sync::Arc};
+
+use super::parser::Expr;
+
+lazy_static::lazy_static! {
+    pub static ref BUILTINS: FunctionRegistry = {
+        let mut registry = FunctionRegistry::new();
+        register_builtins(&mut registry);
+        registry
+    };
+}
+
+/// How many arguments a function accepts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arity {
+    Exact(usize),
+    /// Inclusive range, trailing parameters past `min` are optional.
+    Range(usize, usize),
+    /// At least `min` arguments, the last parameter repeats.
+    Variadic(usize),
+    /// Any of the counts, in increasing order, for parameters that only make
+    /// sense together, e.g. the three coordinates of an origin.
+    OneOf(&'static [usize]),
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match *self {
+            Arity::Exact(n) => count == n,
+            Arity::Range(min, max) => (min..=max).contains(&count),
+            Arity::Variadic(min) => count >= min,
+            Arity::OneOf(counts) => counts.contains(&count),
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        match *self {
+            Arity::Exact(n) => write!(f, "{} argument{}", n, plural(n)),
+            Arity::Range(min, max) => write!(f, "{} to {} arguments", min, max),
+            Arity::Variadic(min) => write!(f, "at least {} argument{}", min, plural(min)),
+            Arity::OneOf(counts) => {
+                let counts: Vec<String> = counts.iter().map(usize::to_string).collect();
+                match counts.split_last() {
+                    Some((last, [])) => write!(f, "{} argument{}", last, plural(counts.len())),
+                    Some((last, rest)) => write!(f, "{} or {} arguments", rest.join(", "), last),
+                    None => write!(f, "no arguments"),
+                }
+            }
+        }
+    }
+}
+
+/// The kind of value a function expects for a parameter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArgType {
+    Any,
+    Numeric,
+    List,
+    Struct,
+    /// A number or string written directly in the expression, e.g. a window size.
+    Literal,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub ty: ArgType,
+}
+
+/// Arguments of a call, lowered to polars, alongside the slang expressions
+/// they came from so functions can require literal values.
+pub struct CallArgs<'a> {
+    name: &'a str,
+    exprs: Vec<polars_lazy::dsl::Expr>,
+    ast: &'a [Expr],
+}
+
+impl CallArgs<'_> {
+    pub fn len(&self) -> usize {
+        self.exprs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exprs.is_empty()
+    }
+
+    pub fn expr(&self, index: usize) -> polars_lazy::dsl::Expr {
+        self.exprs[index].clone()
+    }
+
+    pub fn exprs(&self) -> &[polars_lazy::dsl::Expr] {
+        &self.exprs
+    }
+
+    pub fn ast(&self, index: usize) -> &Expr {
+        &self.ast[index]
+    }
+
+    pub fn int_literal(&self, index: usize) -> Result<i64> {
+        match &self.ast[index] {
+            Expr::Int(i) => Ok(*i),
+            expr => Err(anyhow::anyhow!(
+                "argument {} of `{}` must be an integer literal, found {:?}",
+                index + 1,
+                self.name,
+                expr
+            )),
+        }
+    }
+
+    pub fn float_literal(&self, index: usize) -> Result<f64> {
+        match &self.ast[index] {
+            Expr::Int(i) => Ok(*i as f64),
+            Expr::Float(f) => Ok(*f),
+            expr => Err(anyhow::anyhow!(
+                "argument {} of `{}` must be a number literal, found {:?}",
+                index + 1,
+                self.name,
+                expr
+            )),
+        }
+    }
+
+    pub fn string_literal(&self, index: usize) -> Result<&str> {
+        match &self.ast[index] {
+            Expr::Str(s) => Ok(s),
+            expr => Err(anyhow::anyhow!(
+                "argument {} of `{}` must be a string literal, found {:?}",
+                index + 1,
+                self.name,
+                expr
+            )),
+        }
+    }
+}
+
+pub type Lowering =
+    Arc<dyn Fn(&CallArgs<'_>) -> Result<polars_lazy::dsl::Expr> + Send + Sync + 'static>;
+
+#[derive(Clone)]
+enum Implementation {
+    Native(Lowering),
+    /// Written in slang, the arguments are substituted into `body` at each call.
+    Slang {
+        params: Vec<String>,
+        body: Expr,
+    },
+}
+
+#[derive(Clone)]
+pub struct Function {
+    pub name: String,
+    pub arity: Arity,
+    pub params: Vec<Param>,
+    pub doc: String,
+    /// Whether the result can have a different number of rows than the
+    /// frame, e.g. `explode`. Such calls are never moved into a column of
+    /// their own.
+    pub changes_length: bool,
+    implementation: Implementation,
+}
+
+impl Function {
+    pub fn new<F>(name: &str, arity: Arity, params: &[(&str, ArgType)], doc: &str, lower: F) -> Self
+    where
+        F: Fn(&CallArgs<'_>) -> Result<polars_lazy::dsl::Expr> + Send + Sync + 'static,
+    {
+        Self {
+            name: name.to_owned(),
+            arity,
+            params: params
+                .iter()
+                .map(|(name, ty)| Param {
+                    name: (*name).to_owned(),
+                    ty: *ty,
+                })
+                .collect(),
+            doc: doc.to_owned(),
+            changes_length: false,
+            implementation: Implementation::Native(Arc::new(lower)),
+        }
+    }
+
+    /// Marks the function as returning a different number of rows than it
+    /// is given.
+    pub fn changing_length(self) -> Self {
+        Self {
+            changes_length: true,
+            ..self
+        }
+    }
+
+    /// A function written in slang, e.g. from `def name(params) = body`.
+    pub fn define(name: &str, params: &[String], body: Expr) -> Result<Self> {
+        if calls(&body, name) {
+            return Err(anyhow::anyhow!("`{}` cannot call itself", name));
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            arity: Arity::Exact(params.len()),
+            params: params
+                .iter()
+                .map(|param| Param {
+                    name: param.clone(),
+                    ty: ArgType::Any,
+                })
+                .collect(),
+            doc: "User defined function.".to_owned(),
+            changes_length: false,
+            implementation: Implementation::Slang {
+                params: params.to_vec(),
+                body,
+            },
+        })
+    }
+
+    /// Parameter names and body of a function written in slang.
+    pub fn definition(&self) -> Option<(&[String], &Expr)> {
+        match &self.implementation {
+            Implementation::Native(_) => None,
+            Implementation::Slang { params, body } => Some((params, body)),
+        }
+    }
+
+    pub fn check_arity(&self, count: usize) -> Result<()> {
+        if self.arity.accepts(count) {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "`{}` takes {}, found {}",
+                self.signature(),
+                self.arity,
+                count
+            ))
+        }
+    }
+
+    /// Type of the parameter at `index`, repeating the last one for varargs.
+    pub fn param_type(&self, index: usize) -> ArgType {
+        self.params
+            .get(index)
+            .or(self.params.last())
+            .map(|param| param.ty)
+            .unwrap_or(ArgType::Any)
+    }
+
+    /// Human readable signature, e.g. `atan2(y, x)`.
+    pub fn signature(&self) -> String {
+        let mut params: Vec<String> = self
+            .params
+            .iter()
+            .enumerate()
+            .map(|(index, param)| match self.arity {
+                Arity::Range(min, _) if index >= min => format!("{}?", param.name),
+                _ => param.name.clone(),
+            })
+            .collect();
+        if let (Arity::Variadic(_), Some(last)) = (self.arity, params.last_mut()) {
+            last.push_str("...");
+        }
+        if let Arity::OneOf(counts) = self.arity {
+            // Each group of parameters that has to be given together is
+            // bracketed, e.g. `f(x[, y, z])`
+            let mut signature = String::new();
+            let mut start = 0;
+            for &count in counts {
+                let group = params[start..count.min(params.len())].join(", ");
+                signature += &match start {
+                    0 => group,
+                    _ => format!("[, {}", group),
+                };
+                start = count.min(params.len());
+            }
+            let close = "]".repeat(counts.len().saturating_sub(1));
+            return format!("{}({}{})", self.name, signature, close);
+        }
+        format!("{}({})", self.name, params.join(", "))
+    }
+
+    pub fn lower(
+        &self,
+        ast: &[Expr],
+        exprs: Vec<polars_lazy::dsl::Expr>,
+    ) -> Result<polars_lazy::dsl::Expr> {
+        self.check_arity(exprs.len())?;
+
+        match &self.implementation {
+            Implementation::Native(lower) => lower(&CallArgs {
+                name: &self.name,
+                exprs,
+                ast,
+            }),
+            Implementation::Slang { .. } => Err(anyhow::anyhow!(
+                "`{}` is defined in slang and must be expanded before lowering",
+                self.name
+            )),
+        }
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Function")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .field("params", &self.params)
+            .finish()
+    }
+}
+
+/// Functions callable from slang, keyed by name.
+#[derive(Clone, Debug, Default)]
+pub struct FunctionRegistry {
+    functions: BTreeMap<String, Function>,
+}
+
+impl FunctionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry pre-populated with every builtin function.
+    pub fn with_builtins() -> Self {
+        BUILTINS.clone()
+    }
+
+    /// Adds a function, replacing any existing function with the same name.
+    pub fn register(&mut self, function: Function) {
+        self.functions.insert(function.name.clone(), function);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Function> {
+        self.functions.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Function> {
+        self.functions.values()
+    }
+
+    /// Fails if calling `name` would expand functions written in slang
+    /// forever, e.g. `def f(x) = g(x)` with `def g(x) = f(x)`.
+    pub fn check_recursion(&self, name: &str) -> Result<()> {
+        self.check_chain(&mut vec![name], &mut HashSet::new())
+    }
+
+    /// Follows the calls of the last function in `chain`, the functions
+    /// being expanded. `done` holds functions already known to end.
+    fn check_chain<'a>(
+        &'a self,
+        chain: &mut Vec<&'a str>,
+        done: &mut HashSet<&'a str>,
+    ) -> Result<()> {
+        let Some((_, body)) = self
+            .get(chain[chain.len() - 1])
+            .and_then(Function::definition)
+        else {
+            return Ok(());
+        };
+        let mut names = vec![];
+        callees(body, &mut names);
+        for callee in names {
+            if let Some(start) = chain.iter().position(|name| *name == callee) {
+                let through: Vec<String> = chain[start + 1..]
+                    .iter()
+                    .map(|name| format!("`{}`", name))
+                    .collect();
+                return Err(match through.is_empty() {
+                    true => anyhow::anyhow!("`{}` cannot call itself", callee),
+                    false => {
+                        anyhow::anyhow!("`{}` calls itself through {}", callee, through.join(", "))
+                    }
+                });
+            }
+            if done.contains(callee) {
+                continue;
+            }
+            chain.push(callee);
+            self.check_chain(chain, done)?;
+            done.insert(callee);
+            chain.pop();
+        }
+        Ok(())
+    }
+
+    pub fn lower_call(
+        &self,
+        name: &str,
+        ast: &[Expr],
+        exprs: Vec<polars_lazy::dsl::Expr>,
+    ) -> Result<polars_lazy::dsl::Expr> {
+        self.get(name)
+            .ok_or(anyhow::anyhow!("unknown function `{}`", name))?
+            .lower(ast, exprs)
+    }
+}
+
+fn calls(expr: &Expr, name: &str) -> bool {
+    let mut names = vec![];
+    callees(expr, &mut names);
+    names.contains(&name)
+}
+
+/// Adds the name of every function `expr` calls to `names`.
+fn callees<'a>(expr: &'a Expr, names: &mut Vec<&'a str>) {
+    match expr {
+        Expr::Int(_) | Expr::Float(_) | Expr::Str(_) | Expr::Ident(_) | Expr::Topic(_) => {}
+        Expr::Call { name, args } => {
+            names.push(name);
+            args.iter().for_each(|arg| callees(arg, names));
+        }
+        Expr::Attribute { obj, .. } | Expr::Wildcard { obj, .. } => callees(obj, names),
+        Expr::ArrayIndex { obj, index } => {
+            callees(obj, names);
+            callees(index, names);
+        }
+        Expr::ArraySlice {
+            obj,
+            start,
+            end,
+            step,
+        } => {
+            callees(obj, names);
+            for bound in [start, end, step].into_iter().flatten() {
+                callees(bound, names);
+            }
+        }
+        Expr::BinOp { lhs, rhs, .. } => {
+            callees(lhs, names);
+            callees(rhs, names);
+        }
+    }
+}
+
+fn register_builtins(registry: &mut FunctionRegistry) {
+    use ArgType::*;
+
+    registry.register(
+        Function::new(
+            "explode",
+            Arity::Exact(1),
+            &[("list", List)],
+            "Flattens a list column into one row per element.",
+            |args| Ok(args.expr(0).explode()),
+        )
+        .changing_length(),
+    );
+    registry.register(Function::new(
+        "sin",
+        Arity::Exact(1),
+        &[("x", Numeric)],
+        "Sine of `x` in radians.",
+        |args| Ok(args.expr(0).sin()),
+    ));
+    registry.register(Function::new(
+        "cos",
+        Arity::Exact(1),
+        &[("x", Numeric)],
+        "Cosine of `x` in radians.",
+        |args| Ok(args.expr(0).cos()),
+    ));
+    registry.register(Function::new(
+        "tan",
+        Arity::Exact(1),
+        &[("x", Numeric)],
+        "Tangent of `x` in radians.",
+        |args| Ok(args.expr(0).tan()),
+    ));
+    registry.register(Function::new(
+        "atan2",
+        Arity::Exact(2),
+        &[("y", Numeric), ("x", Numeric)],
+        "Four quadrant arctangent of `y / x` in radians.",
+        |args| Ok(args.expr(0).arctan2(args.expr(1))),
+    ));
+
+    let quaternion = [
+        ("w", Numeric),
+        ("x", Numeric),
+        ("y", Numeric),
+        ("z", Numeric),
+    ];
+    registry.register(Function::new(
+        "roll",
+        Arity::Exact(4),
+        &quaternion,
+        "Rotation about the x axis in radians, from a `w, x, y, z` quaternion.",
+        |args| {
+            let [w, x, y, z] = [args.expr(0), args.expr(1), args.expr(2), args.expr(3)];
+            let sinr_cosp = lit(2) * (w * x.clone() + y.clone() * z);
+            let cosr_cosp = lit(1) - lit(2) * (x.pow(2.0) + y.pow(2.0));
+            Ok(sinr_cosp.arctan2(cosr_cosp))
+        },
+    ));
+    registry.register(Function::new(
+        "pitch",
+        Arity::Exact(4),
+        &quaternion,
+        "Rotation about the y axis in radians, from a `w, x, y, z` quaternion.",
+        |args| {
+            let [w, x, y, z] = [args.expr(0), args.expr(1), args.expr(2), args.expr(3)];
+            let t = w * y - x * z;
+            let sinp = (lit(1) + lit(2) * t.clone()).sqrt();
+            let cosp = (lit(1) - lit(2) * t).sqrt();
+            Ok(lit(2) * sinp.arctan2(cosp) - lit(FRAC_PI_2))
+        },
+    ));
+    registry.register(Function::new(
+        "yaw",
+        Arity::Exact(4),
+        &quaternion,
+        "Rotation about the z axis in radians, from a `w, x, y, z` quaternion.",
+        |args| {
+            let [w, x, y, z] = [args.expr(0), args.expr(1), args.expr(2), args.expr(3)];
+            let siny_cosp = lit(2) * (w * z.clone() + x * y.clone());
+            let cosy_cosp = lit(1) - lit(2) * (y.pow(2.0) + z.pow(2.0));
+            Ok(siny_cosp.arctan2(cosy_cosp))
+        },
+    ));
+    crate::builtins::register(registry);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arity() {
+        assert!(Arity::Exact(2).accepts(2));
+        assert!(!Arity::Exact(2).accepts(1));
+        assert!(Arity::Range(1, 3).accepts(3));
+        assert!(!Arity::Range(1, 3).accepts(4));
+        assert!(Arity::Variadic(1).accepts(10));
+        assert!(!Arity::Variadic(1).accepts(0));
+        assert!(Arity::OneOf(&[1, 3]).accepts(3));
+        assert!(!Arity::OneOf(&[1, 3]).accepts(2));
+        assert_eq!(Arity::OneOf(&[1, 3]).to_string(), "1 or 3 arguments");
+    }
+
+    #[test]
+    fn test_signature() {
+        assert_eq!(BUILTINS.get("atan2").unwrap().signature(), "atan2(y, x)");
+
+        let function = Function::new(
+            "f",
+            Arity::Range(1, 2),
+            &[("x", ArgType::Numeric), ("n", ArgType::Literal)],
+            "",
+            |args| Ok(args.expr(0)),
+        );
+        assert_eq!(function.signature(), "f(x, n?)");
+
+        let function = Function::new(
+            "g",
+            Arity::OneOf(&[1, 3]),
+            &[
+                ("x", ArgType::Numeric),
+                ("y", ArgType::Numeric),
+                ("z", ArgType::Numeric),
+            ],
+            "",
+            |args| Ok(args.expr(0)),
+        );
+        assert_eq!(function.signature(), "g(x[, y, z])");
+    }
+
+    #[test]
+    fn test_lower_call() {
+        let ast = [Expr::Ident("a".to_owned()), Expr::Ident("b".to_owned())];
+        assert_eq!(
+            BUILTINS
+                .lower_call("atan2", &ast, vec![col("a"), col("b")])
+                .unwrap(),
+            col("a").arctan2(col("b"))
+        );
+
+        let err = BUILTINS
+            .lower_call("atan2", &ast[..1], vec![col("a")])
+            .unwrap_err();
+        assert_eq!(err.to_string(), "`atan2(y, x)` takes 2 arguments, found 1");
+
+        assert!(BUILTINS.lower_call("nope", &[], vec![]).is_err());
+    }
+
+    #[test]
+    fn test_register() {
+        let mut registry = FunctionRegistry::with_builtins();
+        registry.register(Function::new(
+            "double",
+            Arity::Exact(1),
+            &[("x", ArgType::Numeric)],
+            "Twice `x`.",
+            |args| Ok(args.expr(0) * lit(2)),
+        ));
+
+        let ast = [Expr::Ident("a".to_owned())];
+        assert_eq!(
+            registry.lower_call("double", &ast, vec![col("a")]).unwrap(),
+            col("a") * lit(2)
+        );
+        assert!(FunctionRegistry::new().get("sin").is_none());
+    }
+
+    #[test]
+    fn test_define() {
+        let body = crate::parse("x * 2").unwrap();
+        let function = Function::define("double", &["x".to_owned()], body.clone()).unwrap();
+        assert_eq!(function.signature(), "double(x)");
+        assert_eq!(function.definition(), Some((&["x".to_owned()][..], &body)));
+
+        let body = crate::parse("f(x) + 1").unwrap();
+        assert!(Function::define("f", &["x".to_owned()], body).is_err());
+    }
+
+    #[test]
+    fn test_check_recursion() {
+        let define = |registry: &mut FunctionRegistry, name: &str, body: &str| {
+            let body = crate::parse(body).unwrap();
+            registry.register(Function::define(name, &["x".to_owned()], body).unwrap());
+        };
+        let mut registry = FunctionRegistry::with_builtins();
+        define(&mut registry, "f", "g(x) + sin(x)");
+        define(&mut registry, "g", "x * 2");
+        assert!(registry.check_recursion("f").is_ok());
+        assert!(registry.check_recursion("sin").is_ok());
+
+        // Redefining `g` to call back into `f`
+        define(&mut registry, "g", "f(x)");
+        assert_eq!(
+            registry.check_recursion("f").unwrap_err().to_string(),
+            "`f` calls itself through `g`"
+        );
+        define(&mut registry, "h", "1 + g(x)");
+        assert_eq!(
+            registry.check_recursion("h").unwrap_err().to_string(),
+            "`g` calls itself through `f`"
+        );
+
+        // Lowering fails rather than expanding it forever
+        let program = crate::parse_program("def f(x) = g(x)\ndef g(x) = f(x)\nf(1)").unwrap();
+        let error = crate::to_polars_program(&program, &BUILTINS).err().unwrap();
+        assert_eq!(error.to_string(), "`f` calls itself through `g`");
+    }
+}
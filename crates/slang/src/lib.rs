@@ -1,15 +1,36 @@
+mod builtins;
+pub mod check;
+pub mod complete;
+pub mod context;
+pub mod diagnostic;
+mod format;
+pub mod functions;
+pub mod optimize;
 pub mod parser;
 pub mod to_polars;
-pub use parser::parse;
-pub use to_polars::to_polars_expr;
+pub mod topics;
+mod trace;
+pub use builtins::resample::{interpolate, Interpolation};
+pub use builtins::spectral::{spectrogram, Spectrogram};
+pub use complete::{complete, complete_with, Completion, CompletionKind};
+pub use context::Context;
+pub use diagnostic::{Diagnostic, Diagnostics};
+pub use functions::{ArgType, Arity, Function, FunctionRegistry};
+pub use optimize::optimize;
+pub use parser::{parse, parse_library, parse_program};
+pub use to_polars::{to_polars_expr, to_polars_expr_with, to_polars_program};
+pub use topics::{Topic, Topics};
+pub use trace::{Trace, Values};
 
 pub use polars::error::PolarsResult;
 pub use polars::prelude::DataType;
 pub use polars::prelude::PolarsError;
-pub use polars::prelude::{LazyFrame, ListToStructArgs, ToStruct};
+pub use polars::prelude::{LazyFrame, ListToStructArgs, Schema, TimeUnit, ToStruct};
 
-use anyhow::Result;
+use anyhow::{Context as _, Result};
+use std::collections::HashSet;
 use std::ffi::OsStr;
+use std::fmt::{self, Display, Formatter};
 use std::path::PathBuf;
 
 use polars::prelude::*;
@@ -26,63 +47,422 @@ pub fn read_data(path: PathBuf) -> PolarsResult<LazyFrame> {
     LazyFrame::scan_parquet(path, ScanArgsParquet::default())
 }
 
-pub struct Trace {
-    pub name: String,
-    pub data: Vec<f64>,
-}
+/// Name of the struct field holding a result's own x axis, e.g. the
+/// frequencies of a spectrum. The remaining fields are plotted against it.
+pub const X_FIELD: &str = "__x";
 
 pub fn eval(df: &LazyFrame, expr: &str) -> Result<Vec<Trace>> {
-    let slang_expr = crate::parse(expr)?;
-    let polars_expr = crate::to_polars_expr(&slang_expr)?;
+    eval_with(df, expr, &Context::default())
+}
+
+/// Like [`eval`], resolving calls against the functions in `context`.
+/// Expressions referring to other topics are evaluated against those topics
+/// joined by [`Topics::join`] instead of `df`, and plotted against the time
+/// of the first topic.
+pub fn eval_with(df: &LazyFrame, expr: &str, context: &Context) -> Result<Vec<Trace>> {
+    let (parsed, spans) = parser::parse_program_with_spans(expr)?;
+    let name = &expr[spans.result.span.clone()];
+    let (df, joined) = frame(df, &parsed, context)?;
+    check_or_fail(&parsed, &spans, &df, context)?;
+    let program = optimize(&parsed, &context.functions);
+    let program = crate::to_polars_program(&program, &context.functions)?;
+    let to_traces = |series: &Series| to_traces(name, &parsed.result, series, context);
+
+    if !joined {
+        let data = program
+            .with_bindings(df)
+            .select([program.result])
+            .collect()?;
 
-    let data = df
-        .clone() // TODO: remove clone
-        .lazy()
-        .select([polars_expr])
+        let series = data
+            .get_columns()
+            .iter()
+            .next()
+            .ok_or(anyhow::anyhow!("No data"))?
+            .as_materialized_series();
+        return to_traces(series);
+    }
+
+    let data = program
+        .with_bindings(df)
+        .select([program.result.alias("__y"), col(topics::TIME_COLUMN)])
         .collect()?;
+    let time = f64_data(data.column(topics::TIME_COLUMN)?.as_materialized_series())?;
+    Ok(to_traces(data.column("__y")?.as_materialized_series())?
+        .into_iter()
+        .map(|trace| Trace {
+            x: trace.x.or_else(|| Some(time.clone())),
+            ..trace
+        })
+        .collect())
+}
+
+/// An error in one of the expressions given to [`eval_many_with`].
+#[derive(Debug)]
+pub struct ExprError {
+    /// Where the expression is in the expressions given.
+    pub index: usize,
+    pub error: anyhow::Error,
+}
+
+impl Display for ExprError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "in expression {}", self.index + 1)
+    }
+}
+
+impl std::error::Error for ExprError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.error.as_ref())
+    }
+}
+
+pub fn eval_many(df: &LazyFrame, exprs: &[&str]) -> Result<Vec<Vec<Trace>>> {
+    eval_many_with(df, exprs, &Context::default())
+}
+
+/// Like [`eval_with`] for each of `exprs`, but scanning `df` once: every
+/// expression is selected in a single query. Expressions that read other
+/// topics, change the number of rows or don't read `df` at all are still
+/// evaluated on their own. Fails with an [`ExprError`] when the failure is
+/// in one expression.
+pub fn eval_many_with(
+    df: &LazyFrame,
+    exprs: &[&str],
+    context: &Context,
+) -> Result<Vec<Vec<Trace>>> {
+    let in_expr = |index| move |error| ExprError { index, error };
+    let mut traces: Vec<Option<Vec<Trace>>> = exprs.iter().map(|_| None).collect();
+    let mut batch = vec![];
+    let mut batched = df.clone();
+    for (index, expr) in exprs.iter().enumerate() {
+        let (parsed, spans) = parser::parse_program_with_spans(expr).map_err(in_expr(index))?;
+        let (frame, joined) = frame(df, &parsed, context).map_err(in_expr(index))?;
+        check_or_fail(&parsed, &spans, &frame, context).map_err(in_expr(index))?;
+        let program = optimize(&parsed, &context.functions);
+
+        if joined || !batchable(&program, &context.functions) {
+            traces[index] = Some(eval_with(df, expr, context).map_err(in_expr(index))?);
+            continue;
+        }
+        // Every program adds its bindings to the same frame, so each gets
+        // names of its own
+        let program = scoped(&program, &format!("__e{}_", index));
+        let program =
+            crate::to_polars_program(&program, &context.functions).map_err(in_expr(index))?;
+        batched = program.with_bindings(batched);
+        let name = &expr[spans.result.span.clone()];
+        batch.push((
+            index,
+            name,
+            parsed,
+            program.result.alias(format!("__y{}", index)),
+        ));
+    }
+
+    if !batch.is_empty() {
+        let results: Vec<_> = batch.iter().map(|(.., result)| result.clone()).collect();
+        let data = match batched.select(results).collect() {
+            Ok(data) => data,
+            Err(error) => {
+                // Find the expression at fault, if any one is
+                for (index, ..) in &batch {
+                    eval_with(df, exprs[*index], context).map_err(in_expr(*index))?;
+                }
+                return Err(error.into());
+            }
+        };
+        for (index, name, parsed, _) in &batch {
+            let series = data
+                .column(&format!("__y{}", index))?
+                .as_materialized_series();
+            traces[*index] =
+                Some(to_traces(name, &parsed.result, series, context).map_err(in_expr(*index))?);
+        }
+    }
+
+    Ok(traces.into_iter().flatten().collect())
+}
+
+/// Whether `program` gives a row for each row of the frame it reads, so
+/// can be selected alongside others.
+fn batchable(program: &parser::Program, functions: &FunctionRegistry) -> bool {
+    let reads = |expr: &parser::Expr| {
+        optimize::any(expr, &|expr| {
+            matches!(expr, parser::Expr::Ident(_) | parser::Expr::Topic(_))
+        })
+    };
+    let changes_length = |expr: &parser::Expr| {
+        optimize::any(expr, &|expr| match expr {
+            parser::Expr::Call { name, .. } => functions
+                .get(name)
+                .is_some_and(|function| function.changes_length),
+            _ => false,
+        })
+    };
+    let values = program
+        .statements
+        .iter()
+        .filter_map(|statement| match statement {
+            parser::Statement::Let { value, .. } => Some(value),
+            parser::Statement::Def { .. } => None,
+        });
+    reads(&program.result) && !values.chain([&program.result]).any(changes_length)
+}
+
+/// `program` with the names it binds starting with `prefix`. Calls to `def`s
+/// must already be expanded, see [`optimize`].
+fn scoped(program: &parser::Program, prefix: &str) -> parser::Program {
+    fn rename(expr: &parser::Expr, bound: &HashSet<String>, prefix: &str) -> parser::Expr {
+        match expr {
+            parser::Expr::Ident(name) if bound.contains(name) => {
+                parser::Expr::Ident(format!("{}{}", prefix, name))
+            }
+            _ => optimize::map_children(expr, |child| rename(child, bound, prefix)),
+        }
+    }
+
+    let mut bound = HashSet::new();
+    let statements = program
+        .statements
+        .iter()
+        .map(|statement| match statement {
+            parser::Statement::Let { name, value } => {
+                // A binding's value sees the bindings before it, not itself
+                let value = rename(value, &bound, prefix);
+                bound.insert(name.clone());
+                parser::Statement::Let {
+                    name: format!("{}{}", prefix, name),
+                    value,
+                }
+            }
+            def => def.clone(),
+        })
+        .collect();
+    parser::Program {
+        statements,
+        result: rename(&program.result, &bound, prefix),
+    }
+}
+
+/// The frame to evaluate `program` against, and whether it is the join of
+/// the topics it refers to rather than `df`.
+fn frame(
+    df: &LazyFrame,
+    program: &parser::Program,
+    context: &Context,
+) -> Result<(LazyFrame, bool)> {
+    let names = topics::referenced(program, &context.functions);
+    match names.is_empty() {
+        true => Ok((df.clone(), false)),
+        false => Ok((context.topics.join(&names)?, true)),
+    }
+}
+
+/// Every problem with `expr` found by checking it against the schema of
+/// `df`, without collecting any data. Fails if it doesn't parse.
+pub fn check(df: &LazyFrame, expr: &str, context: &Context) -> Result<Vec<Diagnostic>> {
+    let (program, spans) = parser::parse_program_with_spans(expr)?;
+    let (df, _) = frame(df, &program, context)?;
+    Ok(check_program(&program, &spans, &df, context))
+}
+
+fn check_program(
+    program: &parser::Program,
+    spans: &parser::ProgramSpans,
+    df: &LazyFrame,
+    context: &Context,
+) -> Vec<Diagnostic> {
+    // Without a schema polars reports any errors when collecting instead
+    match df.clone().collect_schema() {
+        Ok(schema) => check::check(program, spans, &schema, &context.functions),
+        Err(_) => vec![],
+    }
+}
+
+/// Fails with every problem [`check`] finds, rather than the first opaque
+/// polars error.
+fn check_or_fail(
+    program: &parser::Program,
+    spans: &parser::ProgramSpans,
+    df: &LazyFrame,
+    context: &Context,
+) -> Result<()> {
+    let diagnostics = check_program(program, spans, df, context);
+    match diagnostics.is_empty() {
+        true => Ok(()),
+        false => Err(Diagnostics(diagnostics).into()),
+    }
+}
+
+/// A time axis to evaluate expressions against, so signals sampled at
+/// different times can be plotted and compared sample for sample.
+#[derive(Clone, Debug)]
+pub struct TimeBase {
+    /// Slang expression for the time of each row of the data.
+    pub t: String,
+    /// The times to estimate every trace at.
+    pub reference: Vec<f64>,
+    pub method: Interpolation,
+}
 
-    let series = data
-        .get_columns()
+/// Like [`eval_with`], interpolating every trace from the time `time.t` of
+/// its samples onto `time.reference`, which becomes each trace's x axis.
+pub fn eval_at(
+    df: &LazyFrame,
+    expr: &str,
+    context: &Context,
+    time: &TimeBase,
+) -> Result<Vec<Trace>> {
+    let (parsed, spans) = parser::parse_program_with_spans(expr)?;
+    let name = &expr[spans.result.span.clone()];
+    let (df, joined) = frame(df, &parsed, context)?;
+    if joined {
+        // The join has a time of its own, which `time.t` can't be read from
+        return Err(anyhow::anyhow!(
+            "`{}` refers to other topics, so can't be interpolated onto a time base",
+            expr
+        ));
+    }
+    check_or_fail(&parsed, &spans, &df, context)?;
+    let program = optimize(&parsed, &context.functions);
+    let program = crate::to_polars_program(&program, &context.functions)?;
+
+    let (t, t_spans) = parser::parse_with_spans(&time.t)?;
+    let t = parser::Program {
+        statements: vec![],
+        result: t,
+    };
+    let t_spans = parser::ProgramSpans {
+        statements: vec![],
+        result: t_spans,
+    };
+    check_or_fail(&t, &t_spans, &df, context)
+        .with_context(|| format!("can't read the time of each sample from `{}`", time.t))?;
+    let t = crate::to_polars_expr_with(&t.result, &context.functions)?;
+
+    let data = program
+        .with_bindings(df)
+        .select([program.result.alias("__y"), t.alias("__t")])
+        .collect()?;
+    let t = f64_data(data.column("__t")?.as_materialized_series())?;
+
+    let series = data.column("__y")?.as_materialized_series();
+    to_traces(name, &parsed.result, series, context)?
         .into_iter()
-        .next()
-        .ok_or(anyhow::anyhow!("No data"))?
-        .as_series()
-        .ok_or(anyhow::anyhow!("Can't make series from column"))?;
-
-    let splat_series = match series.dtype() {
-        DataType::List(_) => unnest_series(series)?,
-        DataType::Struct(_) => unnest_series(series)?,
+        .map(|trace| {
+            if trace.x.is_some() || trace.len() != t.len() {
+                return Err(anyhow::anyhow!(
+                    "`{}` isn't sampled at the times `{}`, so can't be interpolated",
+                    expr,
+                    time.t
+                ));
+            }
+            // Missing values are NaN, so become gaps in the interpolated trace
+            let data = interpolate(&t, &trace.data(), &time.reference, time.method);
+            Ok(Trace {
+                values: Values::Float(data),
+                validity: None,
+                x: Some(time.reference.clone()),
+                ..trace
+            })
+        })
+        .collect()
+}
+
+/// Splits a result into one trace per list element or struct field, at most
+/// `context.max_fields` of them, named after `name`. Results written with a
+/// wildcard, e.g. `position.*`, name each trace after its field instead,
+/// e.g. `position.x`.
+fn to_traces(
+    name: &str,
+    result: &parser::Expr,
+    series: &Series,
+    context: &Context,
+) -> Result<Vec<Trace>> {
+    let mut splat_series = match series.dtype() {
+        DataType::List(_) => unnest_series(series, context.max_fields)?,
+        DataType::Struct(_) => unnest_series(series, context.max_fields)?,
         _ => vec![series.clone()],
     };
 
+    let x = match splat_series.first() {
+        Some(first) if first.name().as_str() == X_FIELD => Some(f64_data(&splat_series.remove(0))?),
+        _ => None,
+    };
+    splat_series.truncate(context.max_fields);
+
+    let wildcard = expands(result);
     splat_series
         .iter()
         .enumerate()
         .map(|(index, s)| {
-            let data = s
-                .cast(&DataType::Float64)?
-                .f64()?
-                .to_vec_null_aware()
-                .left()
-                .ok_or(anyhow::anyhow!("Can't convert to f64"))?;
-            Ok(Trace {
-                name: if splat_series.len() == 1 {
-                    expr.to_owned()
-                } else {
-                    format!("{}[{}]", expr.to_owned(), index)
-                },
-                data,
-            })
+            let name = if wildcard {
+                select(result, index, s.name()).to_string()
+            } else if splat_series.len() == 1 {
+                name.to_owned()
+            } else {
+                format!("{}[{}]", name, index)
+            };
+            Trace::from_series(name, s, x.clone())
         })
         .collect()
 }
 
-fn unnest_series(series: &Series) -> Result<Vec<Series>> {
+fn f64_data(series: &Series) -> Result<Vec<f64>> {
+    // Strings can't be cast, so plot the code of each instead
+    let series = match builtins::strings::is_categorical(series.dtype()) {
+        true => builtins::strings::codes(series)?,
+        false => series.clone(),
+    };
+    Ok(series
+        .cast(&DataType::Float64)?
+        .f64()?
+        .iter()
+        // nulls, e.g. the first sample of `diff`, become gaps
+        .map(|value| value.unwrap_or(f64::NAN))
+        .collect())
+}
+
+/// Whether the traces of `expr` are the fields or elements of a wildcard,
+/// rather than of something it was passed to, e.g. `position.* * 2` but not
+/// `norm(position.*)`.
+fn expands(expr: &parser::Expr) -> bool {
+    match expr {
+        parser::Expr::Wildcard { .. } => true,
+        parser::Expr::Call { .. } => false,
+        _ => optimize::children(expr).into_iter().any(expands),
+    }
+}
+
+/// `expr` with each wildcard that [`expands`] replaced by the element `index`
+/// or the `field` it expands to, e.g. `position.x` for `position.*`.
+fn select(expr: &parser::Expr, index: usize, field: &str) -> parser::Expr {
+    match expr {
+        parser::Expr::Call { .. } => expr.clone(),
+        parser::Expr::Wildcard { obj, over } => {
+            let obj = Box::new(select(obj, index, field));
+            match over {
+                parser::Wildcard::Fields => parser::Expr::Attribute {
+                    obj,
+                    attr: field.to_owned(),
+                },
+                parser::Wildcard::Elements => parser::Expr::ArrayIndex {
+                    obj,
+                    index: Box::new(parser::Expr::Int(index as i64)),
+                },
+            }
+        }
+        _ => optimize::map_children(expr, |child| select(child, index, field)),
+    }
+}
+
+fn unnest_series(series: &Series, max_fields: usize) -> Result<Vec<Series>> {
     let structs = match series.dtype() {
         DataType::List(_) => series.list()?.to_struct(&ListToStructArgs::InferWidth {
             infer_field_strategy: polars::prelude::ListToStructWidthStrategy::FirstNonNull,
             get_index_name: None,
-            max_fields: 100,
+            max_fields,
         })?,
         // TODO: really don't clone
         DataType::Struct(_inner) => series.struct_()?.clone(),
@@ -96,3 +476,125 @@ fn unnest_series(series: &Series) -> Result<Vec<Series>> {
 
     Ok(structs.fields_as_series())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn df() -> LazyFrame {
+        let position = df!(
+            "x" => [1.0, 2.0],
+            "y" => [3.0, 4.0],
+            "Motor RPM" => [5.0, 6.0],
+        )
+        .unwrap()
+        .into_struct("position".into())
+        .into_series();
+        let data = Series::new(
+            "data".into(),
+            [
+                Series::new("".into(), [1.0, 2.0, 3.0]),
+                Series::new("".into(), [4.0, 5.0, 6.0]),
+            ],
+        );
+        DataFrame::new(vec![position.into(), data.into()])
+            .unwrap()
+            .lazy()
+    }
+
+    fn names(expr: &str, context: &Context) -> Vec<String> {
+        eval_with(&df(), expr, context)
+            .unwrap()
+            .into_iter()
+            .map(|trace| trace.name)
+            .collect()
+    }
+
+    #[test]
+    fn test_wildcards() {
+        let context = Context::default();
+        assert_eq!(
+            names("position.*", &context),
+            ["position.x", "position.y", "position.`Motor RPM`"]
+        );
+        assert_eq!(
+            names("position.* * 2", &context),
+            [
+                "position.x * 2",
+                "position.y * 2",
+                "position.`Motor RPM` * 2"
+            ]
+        );
+        assert_eq!(
+            names("data[*]", &context),
+            ["data[0]", "data[1]", "data[2]"]
+        );
+        assert_eq!(
+            names("position", &context),
+            ["position[0]", "position[1]", "position[2]"]
+        );
+
+        // Passed to a function, the wildcard doesn't name the traces
+        assert_eq!(names("norm(position.*)", &context), ["norm(position.*)"]);
+        assert_eq!(
+            names("position.* - norm(position.*)", &context),
+            [
+                "position.x - norm(position.*)",
+                "position.y - norm(position.*)",
+                "position.`Motor RPM` - norm(position.*)"
+            ]
+        );
+
+        let traces = eval_with(&df(), "data[*] * 10.0", &context).unwrap();
+        assert_eq!(traces[2].name, "data[2] * 10.0");
+        assert_eq!(traces[2].data(), [30.0, 60.0]);
+
+        let context = Context {
+            max_fields: 2,
+            ..Context::default()
+        };
+        assert_eq!(names("position.*", &context), ["position.x", "position.y"]);
+        assert_eq!(names("data[*]", &context), ["data[0]", "data[1]"]);
+
+        for expr in ["data.*", "position[*]"] {
+            let error = eval(&df(), expr).err().unwrap();
+            assert!(error.downcast_ref::<Diagnostics>().is_some(), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn test_eval_many() {
+        let df = df().with_column(col("position").struct_().field_by_name("x").alias("x"));
+        let exprs = [
+            "x",
+            "let d = x * 2\nd + data[0]",
+            // The same binding as another expression
+            "let d = x * -1.0\nd",
+            "position.*",
+            // Not one value per row, so evaluated on their own
+            "explode(data)",
+            "1.5",
+        ];
+
+        let many = eval_many(&df, &exprs).unwrap();
+        assert_eq!(many.len(), exprs.len());
+        for (expr, traces) in exprs.iter().zip(&many) {
+            let one = eval(&df, expr).unwrap();
+            assert_eq!(traces.len(), one.len(), "{}", expr);
+            for (trace, expected) in traces.iter().zip(&one) {
+                assert_eq!(trace.name, expected.name);
+                assert_eq!(trace.values, expected.values, "{}", expr);
+            }
+        }
+        assert_eq!(many[1][0].data(), [3.0, 8.0]);
+        assert_eq!(many[2][0].data(), [-1.0, -2.0]);
+        assert_eq!(many[4][0].len(), 6);
+
+        // Errors say which expression they are in
+        let error = eval_many(&df, &["x", "x + nope"]).err().unwrap();
+        let error = error.downcast::<ExprError>().unwrap();
+        assert_eq!(error.index, 1);
+        assert!(error.error.downcast_ref::<Diagnostics>().is_some());
+        assert!(eval_many(&df, &[]).unwrap().is_empty());
+    }
+}
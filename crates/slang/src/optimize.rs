@@ -0,0 +1,434 @@
+//! Rewrites a program before it is lowered so polars has less to do. Calls
+//! to functions written in slang are expanded, constant arithmetic is folded,
+//! identities like `x * 1` are dropped, and operations that appear more than
+//! once in a statement are computed once into a temporary column.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::functions::{Function, FunctionRegistry};
+use crate::parser::{Expr, Op, Program, Statement};
+use crate::to_polars::substitute;
+
+/// Prefix of the temporary columns holding common subexpressions.
+pub const TEMPORARY_PREFIX: &str = "__cse";
+
+/// `program` rewritten to compute the same result with less work.
+pub fn optimize(program: &Program, functions: &FunctionRegistry) -> Program {
+    // `def`s are local to the program, so only copy the registry if there are any
+    let mut functions = Cow::Borrowed(functions);
+    let mut statements = vec![];
+    let mut temporaries = 0;
+    for statement in &program.statements {
+        match statement {
+            Statement::Let { name, value } => {
+                let value = simplify(&expand(value, &functions));
+                let value = hoist(value, &functions, &mut temporaries, &mut statements);
+                statements.push(Statement::Let {
+                    name: name.clone(),
+                    value,
+                });
+            }
+            Statement::Def { name, params, body } => {
+                // Bad definitions are kept for lowering to report
+                if let Ok(function) = Function::define(name, params, body.clone()) {
+                    functions.to_mut().register(function);
+                }
+                statements.push(statement.clone());
+            }
+        }
+    }
+
+    let result = simplify(&expand(&program.result, &functions));
+    let result = hoist(result, &functions, &mut temporaries, &mut statements);
+    Program { statements, result }
+}
+
+/// The expressions directly inside `expr`, in the order they are written.
+pub(crate) fn children(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::Int(_) | Expr::Float(_) | Expr::Str(_) | Expr::Ident(_) | Expr::Topic(_) => vec![],
+        Expr::Call { args, .. } => args.iter().collect(),
+        Expr::Attribute { obj, .. } | Expr::Wildcard { obj, .. } => vec![obj.as_ref()],
+        Expr::ArrayIndex { obj, index } => vec![obj.as_ref(), index.as_ref()],
+        Expr::ArraySlice {
+            obj,
+            start,
+            end,
+            step,
+        } => std::iter::once(obj)
+            .chain([start, end, step].into_iter().flatten())
+            .map(|expr| &**expr)
+            .collect(),
+        Expr::BinOp { lhs, rhs, .. } => vec![lhs.as_ref(), rhs.as_ref()],
+    }
+}
+
+/// `expr` with `f` applied to each of its children.
+pub(crate) fn map_children(expr: &Expr, mut f: impl FnMut(&Expr) -> Expr) -> Expr {
+    let mut boxed = |expr: &Expr| Box::new(f(expr));
+    match expr {
+        Expr::Int(_) | Expr::Float(_) | Expr::Str(_) | Expr::Ident(_) | Expr::Topic(_) => {
+            expr.clone()
+        }
+        Expr::Call { name, args } => Expr::Call {
+            name: name.clone(),
+            args: args.iter().map(|arg| *boxed(arg)).collect(),
+        },
+        Expr::Attribute { obj, attr } => Expr::Attribute {
+            obj: boxed(obj),
+            attr: attr.clone(),
+        },
+        Expr::ArrayIndex { obj, index } => Expr::ArrayIndex {
+            obj: boxed(obj),
+            index: boxed(index),
+        },
+        Expr::ArraySlice {
+            obj,
+            start,
+            end,
+            step,
+        } => Expr::ArraySlice {
+            obj: boxed(obj),
+            start: start.as_deref().map(&mut boxed),
+            end: end.as_deref().map(&mut boxed),
+            step: step.as_deref().map(&mut boxed),
+        },
+        Expr::BinOp { lhs, op, rhs } => Expr::BinOp {
+            lhs: boxed(lhs),
+            op: op.clone(),
+            rhs: boxed(rhs),
+        },
+        Expr::Wildcard { obj, over } => Expr::Wildcard {
+            obj: boxed(obj),
+            over: *over,
+        },
+    }
+}
+
+/// Whether `f` holds for `expr` or anything in it.
+pub(crate) fn any(expr: &Expr, f: &impl Fn(&Expr) -> bool) -> bool {
+    f(expr) || children(expr).into_iter().any(|child| any(child, f))
+}
+
+/// `expr` with every call to a function written in slang replaced by its
+/// body, so the other passes can see into it. Calls that would expand
+/// forever are kept for lowering to report.
+fn expand(expr: &Expr, functions: &FunctionRegistry) -> Expr {
+    let expr = map_children(expr, |child| expand(child, functions));
+    let definition = match &expr {
+        Expr::Call { name, args } => functions
+            .get(name)
+            .filter(|function| function.arity.accepts(args.len()))
+            .filter(|_| functions.check_recursion(name).is_ok())
+            .and_then(Function::definition)
+            .map(|definition| (definition, args)),
+        _ => None,
+    };
+    match definition {
+        Some(((params, body), args)) => {
+            let bindings = params.iter().map(String::as_str).zip(args).collect();
+            expand(&substitute(body, &bindings), functions)
+        }
+        None => expr,
+    }
+}
+
+/// `expr` with constant arithmetic folded and identities dropped.
+fn simplify(expr: &Expr) -> Expr {
+    match map_children(expr, simplify) {
+        Expr::BinOp { lhs, op, rhs } => fold(&lhs, &op, &rhs)
+            .or_else(|| identity(&lhs, &op, &rhs))
+            .unwrap_or(Expr::BinOp { lhs, op, rhs }),
+        expr => expr,
+    }
+}
+
+/// The literal `lhs op rhs` evaluates to, if both are numbers and polars
+/// would give the same result without failing.
+fn fold(lhs: &Expr, op: &Op, rhs: &Expr) -> Option<Expr> {
+    let number = |expr: &Expr| match expr {
+        Expr::Int(i) => Some(*i as f64),
+        Expr::Float(f) => Some(*f),
+        _ => None,
+    };
+    match (lhs, rhs) {
+        (Expr::Int(a), Expr::Int(b)) => fold_int(*a, op, *b).map(Expr::Int),
+        _ => fold_float(number(lhs)?, op, number(rhs)?)
+            // Slang has no way to write infinities or NaN
+            .filter(|value| value.is_finite())
+            .map(Expr::Float),
+    }
+}
+
+/// `a op b` computed the way polars does, or `None` if it overflows or
+/// isn't an integer.
+pub(crate) fn fold_int(a: i64, op: &Op, b: i64) -> Option<i64> {
+    match op {
+        Op::Add => a.checked_add(b),
+        Op::Subtract => a.checked_sub(b),
+        Op::Multiply => a.checked_mul(b),
+        // Integer division and modulus round towards negative infinity in polars
+        Op::Divide => {
+            let quotient = a.checked_div(b)?;
+            Some(match a % b != 0 && (a < 0) != (b < 0) {
+                true => quotient - 1,
+                false => quotient,
+            })
+        }
+        Op::Modulus => {
+            let remainder = a.checked_rem(b)?;
+            Some(match remainder != 0 && (remainder < 0) != (b < 0) {
+                true => remainder + b,
+                false => remainder,
+            })
+        }
+        // Negative powers of integers are an error in polars
+        Op::Power => a.checked_pow(u32::try_from(b).ok()?),
+        Op::BitAnd => Some(a & b),
+        Op::BitOr => Some(a | b),
+        Op::BitXor => Some(a ^ b),
+        // Shifts out of range are null
+        Op::ShiftLeft => a.checked_shl(u32::try_from(b).ok()?),
+        Op::ShiftRight => a.checked_shr(u32::try_from(b).ok()?),
+        Op::Equal | Op::NotEqual => None,
+    }
+}
+
+fn fold_float(a: f64, op: &Op, b: f64) -> Option<f64> {
+    match op {
+        Op::Add => Some(a + b),
+        Op::Subtract => Some(a - b),
+        Op::Multiply => Some(a * b),
+        Op::Divide => Some(a / b),
+        Op::Modulus => {
+            let remainder = a % b;
+            Some(match remainder != 0.0 && (remainder < 0.0) != (b < 0.0) {
+                true => remainder + b,
+                false => remainder,
+            })
+        }
+        Op::Power => Some(a.powf(b)),
+        _ => None,
+    }
+}
+
+/// The operand of an operation that leaves it unchanged, e.g. `x` of
+/// `x * 1`. Only integer literals are dropped, as adding a float would
+/// also make an integer `x` a float.
+fn identity(lhs: &Expr, op: &Op, rhs: &Expr) -> Option<Expr> {
+    match (lhs, op, rhs) {
+        (x, Op::Add | Op::Subtract, Expr::Int(0)) | (Expr::Int(0), Op::Add, x) => Some(x.clone()),
+        (x, Op::Multiply | Op::Divide | Op::Power, Expr::Int(1))
+        | (Expr::Int(1), Op::Multiply, x) => Some(x.clone()),
+        _ => None,
+    }
+}
+
+/// Moves operations that appear more than once in `expr` into `let`s of
+/// temporary columns appended to `statements`, returning what is left of
+/// `expr`. Each temporary is numbered from `temporaries`, which counts them.
+fn hoist(
+    expr: Expr,
+    functions: &FunctionRegistry,
+    temporaries: &mut usize,
+    statements: &mut Vec<Statement>,
+) -> Expr {
+    let mut hoister = Hoister {
+        functions,
+        counts: HashMap::new(),
+        names: HashMap::new(),
+        hoisted: vec![],
+        temporaries,
+    };
+
+    // Each round hoists the largest repeated operations, then the next looks
+    // for repeats inside them
+    let mut expr = expr;
+    loop {
+        hoister.counts.clear();
+        hoister.count(&expr);
+        for index in 0..hoister.hoisted.len() {
+            hoister.count(&hoister.hoisted[index].1.clone());
+        }
+
+        let previous = hoister.hoisted.len();
+        expr = hoister.replace(&expr, false);
+        for index in 0..previous {
+            let value = hoister.hoisted[index].1.clone();
+            hoister.hoisted[index].1 = hoister.replace(&value, true);
+        }
+        if hoister.hoisted.len() == previous {
+            break;
+        }
+    }
+
+    // Later rounds can make earlier temporaries refer to later ones
+    let mut pending = hoister.hoisted;
+    while !pending.is_empty() {
+        let ready = pending
+            .iter()
+            .position(|(_, value)| {
+                !any(value, &|expr| {
+                    matches!(expr, Expr::Ident(name) if pending.iter().any(|(pending, _)| pending == name))
+                })
+            })
+            .expect("temporaries can't refer to each other in a cycle");
+        let (name, value) = pending.remove(ready);
+        statements.push(Statement::Let { name, value });
+    }
+    expr
+}
+
+struct Hoister<'a> {
+    functions: &'a FunctionRegistry,
+    /// How often each candidate appears this round, by its source text.
+    counts: HashMap<String, usize>,
+    /// Temporary columns of the candidates hoisted so far, by source text.
+    names: HashMap<String, String>,
+    hoisted: Vec<(String, Expr)>,
+    temporaries: &'a mut usize,
+}
+
+impl Hoister<'_> {
+    /// Whether `expr` is worth computing into a column of its own: an
+    /// operation on columns whose result has a row for each row of the frame.
+    fn candidate(&self, expr: &Expr) -> bool {
+        matches!(
+            expr,
+            Expr::BinOp { .. }
+                | Expr::Call { .. }
+                | Expr::ArrayIndex { .. }
+                | Expr::ArraySlice { .. }
+        ) && any(expr, &|expr| {
+            matches!(expr, Expr::Ident(_) | Expr::Topic(_))
+        }) && !any(expr, &|expr| match expr {
+            Expr::Call { name, .. } => self
+                .functions
+                .get(name)
+                .is_none_or(|function| function.changes_length),
+            _ => false,
+        })
+    }
+
+    fn count(&mut self, expr: &Expr) {
+        if self.candidate(expr) {
+            *self.counts.entry(expr.to_string()).or_default() += 1;
+        }
+        for child in children(expr) {
+            self.count(child);
+        }
+    }
+
+    /// `expr` with candidates that appear more than once replaced by their
+    /// temporary column, other than `expr` itself if it is a `root` that was
+    /// hoisted already.
+    fn replace(&mut self, expr: &Expr, root: bool) -> Expr {
+        if !root && self.candidate(expr) {
+            let key = expr.to_string();
+            if let Some(name) = self.names.get(&key) {
+                return Expr::Ident(name.clone());
+            }
+            if self.counts.get(&key).is_some_and(|count| *count > 1) {
+                let name = format!("{}{}", TEMPORARY_PREFIX, self.temporaries);
+                *self.temporaries += 1;
+                self.names.insert(key, name.clone());
+                self.hoisted.push((name.clone(), expr.clone()));
+                return Expr::Ident(name);
+            }
+        }
+        map_children(expr, |child| self.replace(child, false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::{df, DataType, IntoLazy};
+
+    fn optimize(source: &str) -> String {
+        let program = crate::parse_program(source).unwrap();
+        super::optimize(&program, &FunctionRegistry::with_builtins()).to_string()
+    }
+
+    #[test]
+    fn test_fold() {
+        assert_eq!(optimize("x * (180 / 2.0) + 2 ^ 3"), "x * 90.0 + 8");
+        assert_eq!(optimize("7 / -2 + 7 % -2 + -7.5 % 2.0"), "-4.5");
+        assert_eq!(optimize("x * 1 + 0 - (x ^ 1 / 1)"), "x - x");
+        assert_eq!(optimize("lowpass(x, 10 / 2, t)"), "lowpass(x, 5, t)");
+        // Left as is where polars would fail or differ
+        assert_eq!(
+            optimize("2 ^ -1 + 1 / 0 + 1 << 64"),
+            "2 ^ -1 + 1 / 0 + 1 << 64"
+        );
+        assert_eq!(
+            optimize("x * 1.0 + (9223372036854775807 + 1)"),
+            "x * 1.0 + (9223372036854775807 + 1)"
+        );
+    }
+
+    #[test]
+    fn test_hoist() {
+        assert_eq!(
+            optimize("sin(x * 2) + sin(x * 2) * y"),
+            "let __cse0 = sin(x * 2)\n__cse0 + __cse0 * y"
+        );
+        // Nested repeats are hoisted too, in the order they are needed
+        assert_eq!(
+            optimize("(a * b + c) * (a * b + c) + a * b"),
+            "let __cse1 = a * b\nlet __cse0 = __cse1 + c\n__cse0 * __cse0 + __cse1"
+        );
+        assert_eq!(
+            optimize("def sq(v) = v * v\nsq(x - y)"),
+            "def sq(v) = v * v\nlet __cse0 = x - y\n__cse0 * __cse0"
+        );
+        // Calls that would expand forever are left alone
+        assert_eq!(
+            optimize("def f(v) = g(v)\ndef g(v) = f(v)\nf(x)"),
+            "def f(v) = g(v)\ndef g(v) = f(v)\nf(x)"
+        );
+        // Not worth a column, or can't have one
+        assert_eq!(
+            optimize("p.x + p.x + (1 == 2) + (1 == 2)"),
+            "p.x + p.x + (1 == 2) + (1 == 2)"
+        );
+        assert_eq!(
+            optimize("explode(l) - explode(l)"),
+            "explode(l) - explode(l)"
+        );
+    }
+
+    #[test]
+    fn test_same_result() {
+        let df = df!(
+            "x" => [1.0, -2.5, 4.0],
+            "i" => [7i64, -7, 3],
+        )
+        .unwrap()
+        .lazy();
+        for source in [
+            "i / 2 * 1 + i % (4 - 6) + 3 ^ 2",
+            "def f(a) = (a * 2 + 1) * (a * 2 + 1)\nlet y = f(x) + f(i)\ny - (x * 2 + 1)",
+            "sin(x * 2) + sin(x * 2) * i",
+        ] {
+            let program = crate::parse_program(source).unwrap();
+            let functions = FunctionRegistry::with_builtins();
+            let collect = |program: &Program| {
+                let program = crate::to_polars_program(program, &functions).unwrap();
+                let data = program
+                    .with_bindings(df.clone())
+                    .select([program.result.cast(DataType::Float64)])
+                    .collect()
+                    .unwrap();
+                data.get_columns()[0].f64().unwrap().to_vec()
+            };
+            assert_eq!(
+                collect(&program),
+                collect(&super::optimize(&program, &functions)),
+                "{}",
+                source
+            );
+        }
+    }
+}
@@ -1,6 +1,14 @@
 use anyhow::Result;
-use pest::{iterators::Pair, pratt_parser::PrattParser, Parser};
+use pest::{
+    error::{ErrorVariant, InputLocation},
+    iterators::Pair,
+    pratt_parser::PrattParser,
+    Parser,
+};
 use pest_derive::Parser;
+use std::ops::Range;
+
+use super::diagnostic::Diagnostic;
 
 #[derive(Parser)]
 #[grammar = "slang.pest"]
@@ -10,7 +18,10 @@ struct SlangParser;
 pub enum Expr {
     Int(i64),
     Float(f64),
+    Str(String),
     Ident(String),
+    /// Another topic's data, written `/imu` or `topic("/imu")`.
+    Topic(String),
     Call {
         name: String, // TODO(danny): consider Expr here
         args: Vec<Expr>,
@@ -27,12 +38,81 @@ pub enum Expr {
         obj: Box<Expr>,
         start: Option<Box<Expr>>,
         end: Option<Box<Expr>>,
+        step: Option<Box<Expr>>,
     },
     BinOp {
         lhs: Box<Expr>,
         op: Op,
         rhs: Box<Expr>,
     },
+    /// `obj.*` or `obj[*]`, plotted as one trace per field or element.
+    Wildcard {
+        obj: Box<Expr>,
+        over: Wildcard,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Wildcard {
+    /// `.*`, every field of a struct.
+    Fields,
+    /// `[*]`, every element of a list.
+    Elements,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Statement {
+    /// `let name = value`, binds `value` so later lines can refer to it by `name`.
+    Let { name: String, value: Expr },
+    /// `def name(params) = body`, a function whose parameters are substituted
+    /// into `body` wherever it is called.
+    Def {
+        name: String,
+        params: Vec<String>,
+        body: Expr,
+    },
+}
+
+/// A multi-line slang script: zero or more statements followed by the
+/// expression that produces the result.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    pub statements: Vec<Statement>,
+    pub result: Expr,
+}
+
+/// Where an [`Expr`] is in the source it was parsed from, and where each
+/// of its children is, in the order they are written.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Spans {
+    /// Byte range of the whole expression, including any parentheses.
+    pub span: Range<usize>,
+    /// Byte range of the name the expression is known by: the function of a
+    /// call, the field of an attribute, otherwise the expression itself.
+    pub name: Range<usize>,
+    pub children: Vec<Spans>,
+}
+
+impl Spans {
+    fn new(span: Range<usize>, children: Vec<Spans>) -> Self {
+        Self {
+            name: span.clone(),
+            span,
+            children,
+        }
+    }
+
+    fn leaf(pair: &Pair<'_, Rule>) -> Self {
+        Self::new(pair.as_span().start()..pair.as_span().end(), vec![])
+    }
+}
+
+/// The [`Spans`] of the value or body of each statement of a [`Program`],
+/// and of its result.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProgramSpans {
+    pub statements: Vec<Spans>,
+    pub result: Spans,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -43,6 +123,13 @@ pub enum Op {
     Divide,
     Power,
     Modulus,
+    Equal,
+    NotEqual,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
 }
 
 lazy_static::lazy_static! {
@@ -52,6 +139,13 @@ lazy_static::lazy_static! {
 
         // Precedence is defined lowest to highest
         PrattParser::new()
+            // Comparisons bind loosest, so `a + 1 == b` compares the sum
+            .op(Op::infix(equal, Left) | Op::infix(not_equal, Left))
+            // Bitwise operators bind as in Python, so `x >> 4 & 1` masks the shifted value
+            .op(Op::infix(bit_or, Left))
+            .op(Op::infix(bit_xor, Left))
+            .op(Op::infix(bit_and, Left))
+            .op(Op::infix(shift_left, Left) | Op::infix(shift_right, Left))
             // Addition and subtract have equal precedence
             .op(Op::infix(add, Left) | Op::infix(subtract, Left))
             .op(Op::infix(multiply, Left) | Op::infix(divide, Left) | Op::infix(modulus, Left))
@@ -59,88 +153,182 @@ lazy_static::lazy_static! {
     };
 }
 
-fn parse_basic_val(pair: Pair<'_, Rule>) -> Result<Expr> {
+fn parse_basic_val(pair: Pair<'_, Rule>) -> Result<(Expr, Spans)> {
+    let start = pair.as_span().start();
     let mut inner = pair.into_inner();
     let first = inner.next().unwrap();
+    let spans = Spans::leaf(&first);
 
     match first.as_rule() {
-        Rule::int => Ok(Expr::Int(first.as_str().parse::<i64>().unwrap())),
-        Rule::float => Ok(Expr::Float(first.as_str().parse::<f64>().unwrap())),
-        Rule::ident => {
-            let ident = Expr::Ident(first.as_str().to_string());
-            let mut val = ident;
+        Rule::int => match first.as_str().parse::<i64>() {
+            Ok(value) => Ok((Expr::Int(value), spans)),
+            Err(_) => Err(Diagnostic::new("integer literal out of range", spans.span).into()),
+        },
+        Rule::float => match first.as_str().parse::<f64>() {
+            Ok(value) if value.is_finite() => Ok((Expr::Float(value), spans)),
+            _ => Err(Diagnostic::new("float literal out of range", spans.span).into()),
+        },
+        Rule::string => Ok((
+            Expr::Str(unescape(first.into_inner().next().unwrap().as_str())),
+            spans,
+        )),
+        Rule::ident | Rule::quoted_ident | Rule::topic => {
+            let mut val = match first.as_rule() {
+                Rule::topic => Expr::Topic(first.as_str().to_string()),
+                _ => Expr::Ident(name(first)),
+            };
+            let mut spans = spans;
             for p in inner {
-                val = match p.as_rule() {
+                // Each trailer wraps everything before it
+                let span = start..p.as_span().end();
+                (val, spans) = match p.as_rule() {
                     Rule::call => {
                         let mut args = vec![];
+                        let mut children = vec![];
                         for arg in p.into_inner() {
-                            args.push(parse_basic_expression(arg)?);
+                            let (arg, spans) = parse_basic_expression(arg)?;
+                            args.push(arg);
+                            children.push(spans);
                         }
-                        Expr::Call {
-                            name: first.as_str().to_string(),
-                            args,
+                        let name = match &val {
+                            Expr::Ident(name) => name.as_str(),
+                            Expr::Topic(name) => {
+                                return Err(anyhow::anyhow!("topic `{}` cannot be called", name))
+                            }
+                            _ => return Err(anyhow::anyhow!("only functions can be called")),
+                        };
+                        match (name, args.as_slice()) {
+                            ("topic", [Expr::Str(topic)]) => {
+                                (Expr::Topic(topic.clone()), Spans::new(span, vec![]))
+                            }
+                            ("topic", _) => {
+                                return Err(anyhow::anyhow!(
+                                    "`topic` takes the name of a topic as a string, e.g. topic(\"/imu\")"
+                                ))
+                            }
+                            (name, _) => (
+                                Expr::Call {
+                                    name: name.to_string(),
+                                    args,
+                                },
+                                Spans {
+                                    span,
+                                    name: spans.span,
+                                    children,
+                                },
+                            ),
                         }
                     }
                     Rule::attribute => {
-                        Expr::Attribute {
-                            obj: Box::new(val),
-                            // Cut off the leading dot
-                            attr: p.as_str()[1..].to_string(),
-                        }
+                        let attr = p.into_inner().next().unwrap();
+                        let spans = Spans {
+                            span,
+                            name: Spans::leaf(&attr).span,
+                            children: vec![spans],
+                        };
+                        (
+                            Expr::Attribute {
+                                obj: Box::new(val),
+                                attr: name(attr),
+                            },
+                            spans,
+                        )
                     }
                     Rule::slice => {
-                        let mut start_or_index = None;
-                        let mut has_slice_sep = false;
-                        let mut end = None;
+                        // Python-style `[start:end:step]`, any part may be omitted
+                        let mut parts: [Option<Expr>; 3] = [None, None, None];
+                        let mut slice_seps = 0;
+                        let mut children = vec![spans];
 
                         for part in p.into_inner() {
                             match part.as_rule() {
                                 Rule::basic_expr => {
-                                    if has_slice_sep {
-                                        end = Some(parse_basic_expression(part)?);
-                                    } else {
-                                        start_or_index = Some(parse_basic_expression(part)?);
-                                    }
+                                    let (part, spans) = parse_basic_expression(part)?;
+                                    parts[slice_seps] = Some(part);
+                                    children.push(spans);
                                 }
                                 Rule::slice_sep => {
-                                    has_slice_sep = true;
+                                    slice_seps += 1;
                                 }
                                 _ => unreachable!(),
                             }
                         }
 
-                        if has_slice_sep {
+                        let [start_or_index, end, step] = parts;
+                        let expr = if slice_seps > 0 {
                             Expr::ArraySlice {
                                 obj: Box::new(val),
                                 start: start_or_index.map(Box::new),
                                 end: end.map(Box::new),
+                                step: step.map(Box::new),
                             }
                         } else {
-                            if end.is_some() {
-                                unreachable!("can't have two expressions in an array index");
-                            }
                             Expr::ArrayIndex {
                                 obj: Box::new(val),
-                                index: Box::new(start_or_index.unwrap()),
+                                index: Box::new(start_or_index.ok_or(anyhow::anyhow!(
+                                    "empty array index, expected an index or a slice"
+                                ))?),
                             }
-                        }
+                        };
+                        (expr, Spans::new(span, children))
                     }
+                    Rule::all_fields | Rule::all_elements => (
+                        Expr::Wildcard {
+                            obj: Box::new(val),
+                            over: match p.as_rule() {
+                                Rule::all_fields => Wildcard::Fields,
+                                _ => Wildcard::Elements,
+                            },
+                        },
+                        Spans::new(span, vec![spans]),
+                    ),
                     rule => unreachable!("parse_basic_val expected trailer, found {:?}", rule),
                 };
             }
-            Ok(val)
+            Ok((val, spans))
         }
 
         rule => unreachable!("parse_basic_val expected basic_val, found {:?}", rule),
     }
 }
 
-fn parse_basic_expression(pair: Pair<'_, Rule>) -> Result<Expr> {
-    let primary = parse_basic_expression;
+/// The name an `ident` or `quoted_ident` stands for.
+fn name(pair: Pair<'_, Rule>) -> String {
+    match pair.as_rule() {
+        Rule::quoted_ident => unescape(pair.into_inner().next().unwrap().as_str()),
+        _ => pair.as_str().to_string(),
+    }
+}
+
+/// Resolves backslash escapes, `\"` and `\\` are the only ones that matter
+/// but any escaped character stands for itself.
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn parse_basic_expression(pair: Pair<'_, Rule>) -> Result<(Expr, Spans)> {
+    let primary = |pair: Pair<'_, Rule>| match pair.as_rule() {
+        // Parenthesized, the parentheses are either side of the pair
+        Rule::basic_expr => {
+            let span = pair.as_span().start() - 1..pair.as_span().end() + 1;
+            let (expr, spans) = parse_basic_expression(pair)?;
+            Ok((expr, Spans { span, ..spans }))
+        }
+        _ => parse_basic_expression(pair),
+    };
 
-    let infix = |lhs: Result<Expr>, op: Pair<'_, Rule>, rhs: Result<Expr>| {
-        Ok(Expr::BinOp {
-            lhs: Box::new(lhs?),
+    let infix = |lhs: Result<(Expr, Spans)>, op: Pair<'_, Rule>, rhs: Result<(Expr, Spans)>| {
+        let ((lhs, lhs_spans), (rhs, rhs_spans)) = (lhs?, rhs?);
+        let expr = Expr::BinOp {
+            lhs: Box::new(lhs),
             op: match op.as_rule() {
                 Rule::add => Op::Add,
                 Rule::subtract => Op::Subtract,
@@ -148,39 +336,238 @@ fn parse_basic_expression(pair: Pair<'_, Rule>) -> Result<Expr> {
                 Rule::divide => Op::Divide,
                 Rule::power => Op::Power,
                 Rule::modulus => Op::Modulus,
+                Rule::equal => Op::Equal,
+                Rule::not_equal => Op::NotEqual,
+                Rule::bit_and => Op::BitAnd,
+                Rule::bit_or => Op::BitOr,
+                Rule::bit_xor => Op::BitXor,
+                Rule::shift_left => Op::ShiftLeft,
+                Rule::shift_right => Op::ShiftRight,
                 rule => unreachable!(
                     "parse_basic_expression expected infix operation, found {:?}",
                     rule
                 ),
             },
-            rhs: Box::new(rhs?),
-        })
+            rhs: Box::new(rhs),
+        };
+        let span = lhs_spans.span.start..rhs_spans.span.end;
+        Ok((expr, Spans::new(span, vec![lhs_spans, rhs_spans])))
     };
 
-    let expr = match pair.as_rule() {
-        Rule::basic_val => parse_basic_val(pair)?,
+    match pair.as_rule() {
+        Rule::basic_val => parse_basic_val(pair),
         Rule::basic_expr => PRATT_PARSER
             .map_primary(primary)
             .map_infix(infix)
-            .parse(pair.into_inner())?,
+            .parse(pair.into_inner()),
         rule => unreachable!("parse_basic_expression expected atom, found {:?}", rule),
-    };
-    Ok(expr)
+    }
 }
 
 pub fn parse(input: &str) -> Result<Expr> {
-    let mut pairs = SlangParser::parse(Rule::calculation, input)?;
-    for p in pairs.next().unwrap().into_inner() {
+    Ok(parse_with_spans(input)?.0)
+}
+
+/// Like [`parse`], also returning where each part of the expression is in
+/// `input`.
+pub fn parse_with_spans(input: &str) -> Result<(Expr, Spans)> {
+    let calculation = SlangParser::parse(Rule::calculation, input)
+        .map_err(|error| syntax_error(input, error))?
+        .next()
+        .unwrap();
+    match calculation.into_inner().next() {
+        Some(p) if p.as_rule() == Rule::basic_expr => parse_basic_expression(p),
+        Some(p) if p.as_rule() == Rule::EOI => Err(anyhow::anyhow!("incomplete expression")),
+        Some(p) => unreachable!("parse expected basic_expr, found {:?}", p.as_rule()),
+        None => Err(anyhow::anyhow!("no expression found")),
+    }
+}
+
+/// A pest error as a [`Diagnostic`] pointing at what was found, saying what
+/// was expected instead in words.
+fn syntax_error(input: &str, error: pest::error::Error<Rule>) -> Diagnostic {
+    let start = match error.location {
+        InputLocation::Pos(pos) => pos,
+        InputLocation::Span((start, _)) => start,
+    };
+    let (found, end) = match input[start..].chars().next() {
+        None => ("the end".to_owned(), start),
+        Some('\n') => ("a new line".to_owned(), start + 1),
+        Some(c) => (format!("`{}`", c), start + c.len_utf8()),
+    };
+
+    let message = match error.variant {
+        ErrorVariant::ParsingError { positives, .. } => {
+            let mut expected: Vec<&str> = vec![];
+            for rule in positives {
+                match describe(rule) {
+                    Some(description) if !expected.contains(&description) => {
+                        expected.push(description)
+                    }
+                    _ => {}
+                }
+            }
+            // Read better as the last option
+            expected.sort_by_key(|description| *description == "the end");
+            match expected.split_last() {
+                None if start == input.len() => "expected an expression".to_owned(),
+                None if input[start..].starts_with('`') => {
+                    "expected a name between backticks".to_owned()
+                }
+                None => format!("unexpected {}", found),
+                Some((last, [])) => format!("expected {}, found {}", last, found),
+                Some((last, rest)) => {
+                    format!("expected {} or {}, found {}", rest.join(", "), last, found)
+                }
+            }
+        }
+        ErrorVariant::CustomError { message } => message,
+    };
+    Diagnostic::new(message, start..end)
+}
+
+/// What a rule looks like, for error messages.
+fn describe(rule: Rule) -> Option<&'static str> {
+    Some(match rule {
+        Rule::basic_expr | Rule::basic_val => "an expression",
+        Rule::int | Rule::float | Rule::INTEGER => "a number",
+        Rule::string | Rule::string_inner => "a string",
+        Rule::ident | Rule::quoted_ident | Rule::quoted_inner => "a name",
+        Rule::topic => "a topic",
+        Rule::add
+        | Rule::subtract
+        | Rule::multiply
+        | Rule::divide
+        | Rule::power
+        | Rule::modulus
+        | Rule::equal
+        | Rule::not_equal
+        | Rule::bit_or
+        | Rule::bit_xor
+        | Rule::bit_and
+        | Rule::shift_left
+        | Rule::shift_right => "an operator",
+        Rule::attribute | Rule::slice | Rule::call | Rule::all_fields | Rule::all_elements => {
+            "`.`, `[` or `(`"
+        }
+        Rule::slice_sep => "`:`",
+        Rule::params => "parameters in parentheses",
+        Rule::let_stmt => "`let`",
+        Rule::def_stmt => "`def`",
+        Rule::EOI => "the end",
+        _ => return None,
+    })
+}
+
+fn parse_statement(pair: Pair<'_, Rule>) -> Result<(Statement, Spans)> {
+    match pair.as_rule() {
+        Rule::let_stmt => {
+            let mut inner = pair.into_inner();
+            let name = inner.next().unwrap().as_str().to_string();
+            let (value, spans) = parse_basic_expression(inner.next().unwrap())?;
+            Ok((Statement::Let { name, value }, spans))
+        }
+        Rule::def_stmt => {
+            let mut inner = pair.into_inner();
+            let name = inner.next().unwrap().as_str().to_string();
+            let params: Vec<String> = inner
+                .next()
+                .unwrap()
+                .into_inner()
+                .map(|param| param.as_str().to_string())
+                .collect();
+            for (index, param) in params.iter().enumerate() {
+                if params[..index].contains(param) {
+                    return Err(anyhow::anyhow!(
+                        "duplicate parameter `{}` in definition of `{}`",
+                        param,
+                        name
+                    ));
+                }
+            }
+            let (body, spans) = parse_basic_expression(inner.next().unwrap())?;
+            Ok((Statement::Def { name, params, body }, spans))
+        }
+        rule => unreachable!("parse_statement expected statement, found {:?}", rule),
+    }
+}
+
+/// Parses a script of `let` bindings, `def` functions and `#` comments, one
+/// statement per line, ending with the expression to evaluate.
+pub fn parse_program(input: &str) -> Result<Program> {
+    Ok(parse_program_with_spans(input)?.0)
+}
+
+/// Like [`parse_program`], also returning where each expression is in `input`.
+pub fn parse_program_with_spans(input: &str) -> Result<(Program, ProgramSpans)> {
+    let program = SlangParser::parse(Rule::program, input)
+        .map_err(|error| syntax_error(input, error))?
+        .next()
+        .unwrap();
+
+    let mut statements = vec![];
+    let mut spans = vec![];
+    let mut result: Option<(Expr, Spans)> = None;
+    for p in program.into_inner() {
+        if p.as_rule() == Rule::EOI {
+            break;
+        }
+        if let Some((_, result_spans)) = result.take() {
+            return Err(Diagnostic::new(
+                format!(
+                    "only the last line can be an expression, found `{}` before it",
+                    &input[result_spans.span.clone()]
+                ),
+                result_spans.span,
+            )
+            .into());
+        }
+
         match p.as_rule() {
-            Rule::basic_expr => {
-                return parse_basic_expression(p);
+            Rule::basic_expr => result = Some(parse_basic_expression(p)?),
+            _ => {
+                let (statement, statement_spans) = parse_statement(p)?;
+                statements.push(statement);
+                spans.push(statement_spans);
             }
-            Rule::EOI => return Err(anyhow::anyhow!("incomplete expression")),
-            rule => unreachable!("parse expected basic_expr, found {:?}", rule),
         }
     }
 
-    Err(anyhow::anyhow!("no expression found"))
+    let (result, result_spans) = result.ok_or(anyhow::anyhow!(
+        "script must end with an expression to evaluate"
+    ))?;
+    Ok((
+        Program { statements, result },
+        ProgramSpans {
+            statements: spans,
+            result: result_spans,
+        },
+    ))
+}
+
+/// Parses a library file, which may only contain `def` statements and comments.
+pub fn parse_library(input: &str) -> Result<Vec<Statement>> {
+    let library = SlangParser::parse(Rule::library, input)
+        .map_err(|error| syntax_error(input, error))?
+        .next()
+        .unwrap();
+
+    let mut statements = vec![];
+    for p in library.into_inner() {
+        match p.as_rule() {
+            Rule::def_stmt => statements.push(parse_statement(p)?.0),
+            Rule::EOI => break,
+            Rule::let_stmt | Rule::basic_expr => {
+                return Err(anyhow::anyhow!(
+                    "libraries can only contain `def` statements, found `{}`",
+                    p.as_str().trim()
+                ))
+            }
+            rule => unreachable!("parse_library expected statement, found {:?}", rule),
+        }
+    }
+
+    Ok(statements)
 }
 
 #[cfg(test)]
@@ -291,6 +678,7 @@ mod tests {
                 obj: Box::new(Expr::Ident("data".to_string())),
                 start: None,
                 end: None,
+                step: None,
             }
         );
 
@@ -300,6 +688,7 @@ mod tests {
                 obj: Box::new(Expr::Ident("data".to_string())),
                 start: Some(Box::new(Expr::Int(10))),
                 end: None,
+                step: None,
             }
         );
 
@@ -309,6 +698,7 @@ mod tests {
                 obj: Box::new(Expr::Ident("data".to_string())),
                 start: None,
                 end: Some(Box::new(Expr::Int(10))),
+                step: None,
             }
         );
 
@@ -318,8 +708,52 @@ mod tests {
                 obj: Box::new(Expr::Ident("data".to_string())),
                 start: Some(Box::new(Expr::Int(10))),
                 end: Some(Box::new(Expr::Int(20))),
+                step: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_negative_index_and_step() {
+        assert_eq!(
+            parse("data[-1]").unwrap(),
+            Expr::ArrayIndex {
+                obj: Box::new(Expr::Ident("data".to_string())),
+                index: Box::new(Expr::Int(-1)),
+            }
+        );
+
+        assert_eq!(
+            parse("data[:-1]").unwrap(),
+            Expr::ArraySlice {
+                obj: Box::new(Expr::Ident("data".to_string())),
+                start: None,
+                end: Some(Box::new(Expr::Int(-1))),
+                step: None,
+            }
+        );
+
+        assert_eq!(
+            parse("data[::2]").unwrap(),
+            Expr::ArraySlice {
+                obj: Box::new(Expr::Ident("data".to_string())),
+                start: None,
+                end: None,
+                step: Some(Box::new(Expr::Int(2))),
+            }
+        );
+
+        assert_eq!(
+            parse("data[1:n:2]").unwrap(),
+            Expr::ArraySlice {
+                obj: Box::new(Expr::Ident("data".to_string())),
+                start: Some(Box::new(Expr::Int(1))),
+                end: Some(Box::new(Expr::Ident("n".to_string()))),
+                step: Some(Box::new(Expr::Int(2))),
             }
         );
+
+        assert!(parse("data[]").is_err());
     }
 
     #[test]
@@ -355,4 +789,338 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn test_parse_program() {
+        assert_eq!(
+            parse_program("utime").unwrap(),
+            Program {
+                statements: vec![],
+                result: Expr::Ident("utime".to_string()),
+            }
+        );
+
+        let script = "
+            # seconds since boot
+            let t = utime * 1.0e-6  # trailing comment
+            let scale = 2
+
+            t * scale
+        ";
+        assert_eq!(
+            parse_program(script).unwrap(),
+            Program {
+                statements: vec![
+                    Statement::Let {
+                        name: "t".to_string(),
+                        value: Expr::BinOp {
+                            lhs: Box::new(Expr::Ident("utime".to_string())),
+                            op: Op::Multiply,
+                            rhs: Box::new(Expr::Float(1.0e-6)),
+                        },
+                    },
+                    Statement::Let {
+                        name: "scale".to_string(),
+                        value: Expr::Int(2),
+                    },
+                ],
+                result: Expr::BinOp {
+                    lhs: Box::new(Expr::Ident("t".to_string())),
+                    op: Op::Multiply,
+                    rhs: Box::new(Expr::Ident("scale".to_string())),
+                },
+            }
+        );
+
+        assert_eq!(
+            parse_program("letter").unwrap().result,
+            Expr::Ident("letter".to_string())
+        );
+
+        assert!(parse_program("let a = 1").is_err());
+        assert_eq!(
+            parse_program("foo(x)\nb").err().unwrap().to_string(),
+            "only the last line can be an expression, found `foo(x)` before it"
+        );
+        assert!(parse_program("").is_err());
+        assert!(parse_program("let a = 1 a").is_err());
+    }
+
+    #[test]
+    fn test_parse_def() {
+        let program = parse_program("def mag(x, y) = (x^2 + y^2)^0.5\nmag(a, b)").unwrap();
+        assert_eq!(
+            program.statements,
+            vec![Statement::Def {
+                name: "mag".to_string(),
+                params: vec!["x".to_string(), "y".to_string()],
+                body: parse("(x^2 + y^2)^0.5").unwrap(),
+            }]
+        );
+        assert_eq!(program.result, parse("mag(a, b)").unwrap());
+
+        assert_eq!(
+            parse_program("def zero() = 0\nzero()").unwrap().statements,
+            vec![Statement::Def {
+                name: "zero".to_string(),
+                params: vec![],
+                body: Expr::Int(0),
+            }]
+        );
+
+        assert!(parse_program("def f(x, x) = x\nf(1, 2)").is_err());
+        assert_eq!(
+            parse_program("define").unwrap().result,
+            Expr::Ident("define".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_library() {
+        let library = "
+            # shared helpers
+            def double(x) = x * 2
+
+            def half(x) = x / 2  # halves
+        ";
+        assert_eq!(parse_library(library).unwrap().len(), 2);
+        assert_eq!(parse_library("").unwrap(), vec![]);
+        assert!(parse_library("let a = 1").is_err());
+        assert!(parse_library("def f(x) = x\nf(1)").is_err());
+    }
+
+    #[test]
+    fn test_parse_string() {
+        assert_eq!(parse("\"100ms\"").unwrap(), Expr::Str("100ms".to_string()));
+        assert_eq!(
+            parse(r#""say \"hi\" \\ ""#).unwrap(),
+            Expr::Str(r#"say "hi" \ "#.to_string())
+        );
+        assert_eq!(
+            parse("f(x, \"1s\")").unwrap(),
+            Expr::Call {
+                name: "f".to_string(),
+                args: vec![Expr::Ident("x".to_string()), Expr::Str("1s".to_string())],
+            }
+        );
+        assert!(parse("\"unterminated").is_err());
+    }
+
+    #[test]
+    fn test_parse_topic() {
+        let accel_x = |topic: &str| Expr::Attribute {
+            obj: Box::new(Expr::Attribute {
+                obj: Box::new(Expr::Topic(topic.to_string())),
+                attr: "accel".to_string(),
+            }),
+            attr: "x".to_string(),
+        };
+        assert_eq!(
+            parse("/imu.accel.x - /robot/cmd.accel.x").unwrap(),
+            Expr::BinOp {
+                lhs: Box::new(accel_x("/imu")),
+                op: Op::Subtract,
+                rhs: Box::new(accel_x("/robot/cmd")),
+            }
+        );
+        assert_eq!(parse("topic(\"/imu\").accel.x").unwrap(), accel_x("/imu"));
+        assert_eq!(
+            parse("a / b").unwrap(),
+            Expr::BinOp {
+                lhs: Box::new(Expr::Ident("a".to_string())),
+                op: Op::Divide,
+                rhs: Box::new(Expr::Ident("b".to_string())),
+            }
+        );
+        assert!(parse("topic(imu)").is_err());
+        assert!(parse("/imu(1)").is_err());
+    }
+
+    #[test]
+    fn test_parse_wildcard() {
+        let position = Box::new(Expr::Ident("position".to_string()));
+        assert_eq!(
+            parse("position.*").unwrap(),
+            Expr::Wildcard {
+                obj: position.clone(),
+                over: Wildcard::Fields,
+            }
+        );
+        assert_eq!(
+            parse("position.data[*]").unwrap(),
+            Expr::Wildcard {
+                obj: Box::new(Expr::Attribute {
+                    obj: position,
+                    attr: "data".to_string(),
+                }),
+                over: Wildcard::Elements,
+            }
+        );
+        assert!(parse("position.*.x").is_err());
+        assert!(parse("position[*][0]").is_err());
+        assert!(parse("2.*").is_err());
+    }
+
+    #[test]
+    fn test_parse_quoted_ident() {
+        assert_eq!(
+            parse("`Motor 1 RPM` * 2").unwrap(),
+            Expr::BinOp {
+                lhs: Box::new(Expr::Ident("Motor 1 RPM".to_string())),
+                op: Op::Multiply,
+                rhs: Box::new(Expr::Int(2)),
+            }
+        );
+        assert_eq!(
+            parse("pose.`x.y`").unwrap(),
+            Expr::Attribute {
+                obj: Box::new(Expr::Ident("pose".to_string())),
+                attr: "x.y".to_string(),
+            }
+        );
+        assert_eq!(
+            parse(r"`/vehicle/speed` + `a\`b`").unwrap(),
+            Expr::BinOp {
+                lhs: Box::new(Expr::Ident("/vehicle/speed".to_string())),
+                op: Op::Add,
+                rhs: Box::new(Expr::Ident("a`b".to_string())),
+            }
+        );
+        assert_eq!(
+            parse("`sin`(x)").unwrap(),
+            Expr::Call {
+                name: "sin".to_string(),
+                args: vec![Expr::Ident("x".to_string())],
+            }
+        );
+        assert!(parse("``").is_err());
+        assert!(parse("`unterminated").is_err());
+        assert!(parse("a.b(1)").is_err());
+    }
+
+    #[test]
+    fn test_parse_comparison() {
+        assert_eq!(
+            parse("a + 1 == \"HOVER\"").unwrap(),
+            Expr::BinOp {
+                lhs: Box::new(Expr::BinOp {
+                    lhs: Box::new(Expr::Ident("a".to_string())),
+                    op: Op::Add,
+                    rhs: Box::new(Expr::Int(1)),
+                }),
+                op: Op::Equal,
+                rhs: Box::new(Expr::Str("HOVER".to_string())),
+            }
+        );
+        assert!(matches!(
+            parse("a != b").unwrap(),
+            Expr::BinOp {
+                op: Op::NotEqual,
+                ..
+            }
+        ));
+        assert!(parse("a = b").is_err());
+    }
+
+    #[test]
+    fn test_parse_bitwise() {
+        let ident = |name: &str| Box::new(Expr::Ident(name.to_string()));
+        assert_eq!(
+            parse("x >> 4 & 1 == 1").unwrap(),
+            Expr::BinOp {
+                lhs: Box::new(Expr::BinOp {
+                    lhs: Box::new(Expr::BinOp {
+                        lhs: ident("x"),
+                        op: Op::ShiftRight,
+                        rhs: Box::new(Expr::Int(4)),
+                    }),
+                    op: Op::BitAnd,
+                    rhs: Box::new(Expr::Int(1)),
+                }),
+                op: Op::Equal,
+                rhs: Box::new(Expr::Int(1)),
+            }
+        );
+        assert_eq!(
+            parse("a | b xor c").unwrap(),
+            Expr::BinOp {
+                lhs: ident("a"),
+                op: Op::BitOr,
+                rhs: Box::new(Expr::BinOp {
+                    lhs: ident("b"),
+                    op: Op::BitXor,
+                    rhs: ident("c"),
+                }),
+            }
+        );
+        assert!(parse("a xorb").is_err());
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        let diagnostic = |input: &str| {
+            parse_program(input)
+                .unwrap_err()
+                .downcast::<Diagnostic>()
+                .unwrap()
+        };
+
+        let error = diagnostic("1 +");
+        assert_eq!(error.message, "expected an expression, found the end");
+        assert_eq!(error.span, 3..3);
+
+        let error = diagnostic("let a = 1\na $ b");
+        assert_eq!(error.message, "expected an operator or the end, found `$`");
+        assert_eq!(error.span, 12..13);
+        assert_eq!(
+            error.render("let a = 1\na $ b"),
+            "expected an operator or the end, found `$`\n  |\n2 | a $ b\n  |   ^"
+        );
+
+        assert_eq!(
+            diagnostic("pose.").message,
+            "expected a name, found the end"
+        );
+        assert_eq!(
+            diagnostic("`x").message,
+            "expected a name between backticks"
+        );
+        assert_eq!(diagnostic("").message, "expected an expression");
+
+        let error = diagnostic("x + 99999999999999999999");
+        assert_eq!(error.message, "integer literal out of range");
+        assert_eq!(error.span, 4..24);
+        assert_eq!(
+            diagnostic("1.0e999 * x").message,
+            "float literal out of range"
+        );
+
+        let error = diagnostic("let a = 1\nfoo(x)\nb");
+        assert_eq!(
+            error.message,
+            "only the last line can be an expression, found `foo(x)` before it"
+        );
+        assert_eq!(error.span, 10..16);
+    }
+
+    #[test]
+    fn test_spans() {
+        let source = "let a = 1\n  (a + pose.x) * sin(b[1:])  # doubled\n";
+        let (_, spans) = parse_program_with_spans(source).unwrap();
+        let text = |span: &Range<usize>| &source[span.clone()];
+
+        assert_eq!(text(&spans.statements[0].span), "1");
+        let result = &spans.result;
+        assert_eq!(text(&result.span), "(a + pose.x) * sin(b[1:])");
+
+        let [sum, call] = &result.children[..] else {
+            panic!("expected two operands, found {:?}", result.children);
+        };
+        assert_eq!(text(&sum.span), "(a + pose.x)");
+        assert_eq!(text(&sum.children[1].span), "pose.x");
+        assert_eq!(text(&sum.children[1].name), "x");
+        assert_eq!(text(&call.span), "sin(b[1:])");
+        assert_eq!(text(&call.name), "sin");
+        assert_eq!(text(&call.children[0].children[1].span), "1");
+    }
 }
@@ -1,25 +1,11 @@
 use anyhow::Result;
+use polars::prelude::DataType;
 use polars_lazy::prelude::*;
-use std::{collections::HashMap, f64::consts::PI};
-use trigonometry::TrigonometricFunction;
-
-use super::parser::{Expr, Op};
-
-// TODO(danny): this
-lazy_static::lazy_static! {
-    static ref BUILTINS: HashMap<&'static str, FunctionExpr> = {
-        let mut m = HashMap::new();
-        m.insert("sin", FunctionExpr::Trigonometry(TrigonometricFunction::Sin));
-        m.insert("cos", FunctionExpr::Trigonometry(TrigonometricFunction::Cos));
-        m.insert("tan", FunctionExpr::Trigonometry(TrigonometricFunction::Tan));
-        m.insert("atan2", FunctionExpr::Atan2);
-        // m.insert("explode", polars_lazy::dsl::Expr::Explode);
-        // ("sin", polars_lazy::dsl::Expr::sin),
-        // ("cos", polars_lazy::dsl::Expr::cos),
-
-        m
-    };
-}
+use std::{borrow::Cow, collections::HashMap};
+
+use super::builtins::bits::{shift, Shift};
+use super::functions::{Function, FunctionRegistry, BUILTINS};
+use super::parser::{Expr, Op, Program, Statement};
 
 impl TryInto<polars_lazy::dsl::Expr> for Expr {
     type Error = anyhow::Error;
@@ -29,14 +15,67 @@ impl TryInto<polars_lazy::dsl::Expr> for Expr {
     }
 }
 
+/// A lowered [`Program`]. Each binding is added to the frame with its own
+/// `with_columns`, in order, so it is computed once and later bindings and
+/// the result can refer to it as a column.
+pub struct PolarsProgram {
+    pub bindings: Vec<polars_lazy::dsl::Expr>,
+    pub result: polars_lazy::dsl::Expr,
+}
+
+impl PolarsProgram {
+    /// Adds the bindings to `df`, ready to `select` the result.
+    pub fn with_bindings(&self, df: LazyFrame) -> LazyFrame {
+        self.bindings
+            .iter()
+            .fold(df, |df, binding| df.with_columns([binding.clone()]))
+    }
+}
+
+pub fn to_polars_program(program: &Program, functions: &FunctionRegistry) -> Result<PolarsProgram> {
+    // `def`s are local to the program, so only copy the registry if there are any
+    let mut functions = Cow::Borrowed(functions);
+    let mut bindings = vec![];
+    for statement in &program.statements {
+        match statement {
+            Statement::Let { name, value } => {
+                bindings.push(to_polars_expr_with(value, &functions)?.alias(name));
+            }
+            Statement::Def { name, params, body } => {
+                functions
+                    .to_mut()
+                    .register(Function::define(name, params, body.clone())?);
+            }
+        }
+    }
+
+    Ok(PolarsProgram {
+        bindings,
+        result: to_polars_expr_with(&program.result, &functions)?,
+    })
+}
+
 pub fn to_polars_expr(expr: &Expr) -> Result<polars_lazy::dsl::Expr> {
+    to_polars_expr_with(expr, &BUILTINS)
+}
+
+/// Lowers `expr`, resolving calls against `functions`.
+pub fn to_polars_expr_with(
+    expr: &Expr,
+    functions: &FunctionRegistry,
+) -> Result<polars_lazy::dsl::Expr> {
+    let lower = |expr: &Expr| to_polars_expr_with(expr, functions);
+
     match expr {
         Expr::Int(i) => Ok(lit(*i)),
         Expr::Float(f) => Ok(lit(*f)),
+        Expr::Str(s) => Ok(lit(s.as_str())),
         Expr::BinOp { lhs, op, rhs } => {
-            let lhs = to_polars_expr(lhs)?;
-            let rhs = to_polars_expr(rhs)?;
+            let lhs = lower(lhs)?;
+            let rhs = lower(rhs)?;
 
+            // Arithmetic other than `^` is element-wise on lists and structs,
+            // between two of them or with a number
             Ok(match op {
                 Op::Add => lhs + rhs,
                 Op::Subtract => lhs - rhs,
@@ -44,108 +83,208 @@ pub fn to_polars_expr(expr: &Expr) -> Result<polars_lazy::dsl::Expr> {
                 Op::Divide => lhs / rhs,
                 Op::Power => lhs.pow(rhs),
                 Op::Modulus => lhs % rhs,
+                // Strings compare with string literals, e.g. `mode == "HOVER"`
+                Op::Equal => lhs.eq(rhs),
+                Op::NotEqual => lhs.neq(rhs),
+                // Bitwise on integers, logical on booleans
+                Op::BitAnd => lhs.and(rhs),
+                Op::BitOr => lhs.or(rhs),
+                Op::BitXor => lhs.xor(rhs),
+                Op::ShiftLeft => shift(lhs, rhs, Shift::Left),
+                Op::ShiftRight => shift(lhs, rhs, Shift::Right),
             })
         }
         Expr::Ident(name) => Ok(col(name)),
-        Expr::Call { name, args } => match name.as_str() {
-            // TODO(danny): check for args length
-            "explode" => {
-                let obj = to_polars_expr(args.into_iter().next().unwrap())?;
-                return Ok(obj.explode());
-            }
-            "sin" => {
-                let obj = to_polars_expr(args.into_iter().next().unwrap())?;
-                // let thing = BUILTINS.get("sin").unwrap();
-                // return obj.map_private(thing);
-                return Ok(obj.sin());
-            }
-            "cos" => {
-                let obj = to_polars_expr(args.into_iter().next().unwrap())?;
-                return Ok(obj.cos());
-            }
-            "atan2" => {
-                let a = to_polars_expr(args.into_iter().next().unwrap())?;
-                let b = to_polars_expr(args.into_iter().next().unwrap())?;
-                Ok(polars_lazy::dsl::Expr::arctan2(a, b))
-            }
-            "roll" => {
-                let w = to_polars_expr(args.into_iter().next().unwrap())?;
-                let x = to_polars_expr(args.into_iter().next().unwrap())?;
-                let y = to_polars_expr(args.into_iter().next().unwrap())?;
-                let z = to_polars_expr(args.into_iter().next().unwrap())?;
-
-                // roll (x-axis rotation)
-                let sinr_cosp = lit(2) * (w.clone() * x.clone() + y.clone() * z.clone());
-                let cosr_cosp = lit(1) - lit(2) * (x.clone().pow(2.0) + y.clone().pow(2.0));
-                Ok(polars_lazy::dsl::Expr::arctan2(sinr_cosp, cosr_cosp))
-            }
-            "pitch" => {
-                let w = to_polars_expr(args.into_iter().next().unwrap())?;
-                let x = to_polars_expr(args.into_iter().next().unwrap())?;
-                let y = to_polars_expr(args.into_iter().next().unwrap())?;
-                let z = to_polars_expr(args.into_iter().next().unwrap())?;
-                // pitch (y-axis rotation)
-                let sinp = polars_lazy::dsl::Expr::sqrt(
-                    lit(1) + lit(2) * (w.clone() * y.clone() - x.clone() * z.clone()),
-                );
-                let cosp = polars_lazy::dsl::Expr::sqrt(
-                    lit(1) - lit(2) * (w.clone() * y.clone() - x.clone() * z.clone()),
-                );
-
-                Ok(lit(2) * polars_lazy::dsl::Expr::arctan2(sinp, cosp) - lit(PI) / lit(2.0))
-            }
-            "yaw" => {
-                let w = to_polars_expr(args.into_iter().next().unwrap())?;
-                let x = to_polars_expr(args.into_iter().next().unwrap())?;
-                let y = to_polars_expr(args.into_iter().next().unwrap())?;
-                let z = to_polars_expr(args.into_iter().next().unwrap())?;
-
-                // yaw (z-axis rotation)
-                let siny_cosp = lit(2) * (w.clone() * z.clone() + x.clone() * y.clone());
-                let cosy_cosp = lit(1) - lit(2) * (y.pow(2.0) + z.pow(2.0));
-                Ok(polars_lazy::dsl::Expr::arctan2(siny_cosp, cosy_cosp))
-            }
-            _ => {
-                todo!("Call to {} not implemented yet", name);
+        // Each topic is a struct column of the frame joined by `Topics::join`
+        Expr::Topic(name) => Ok(col(name)),
+        Expr::Call { name, args } => {
+            let function = functions
+                .get(name)
+                .ok_or(anyhow::anyhow!("unknown function `{}`", name))?;
+            function.check_arity(args.len())?;
+
+            if let Some((params, body)) = function.definition() {
+                functions.check_recursion(name)?;
+                let bindings = params.iter().map(String::as_str).zip(args).collect();
+                return lower(&substitute(body, &bindings));
             }
-        },
+
+            let exprs = args.iter().map(lower).collect::<Result<Vec<_>>>()?;
+            function.lower(args, exprs)
+        }
+        // Each field or element is already a trace of its own, see `eval`
+        Expr::Wildcard { obj, .. } => lower(obj),
         Expr::Attribute { obj, attr } => {
-            let obj = to_polars_expr(obj)?;
+            let obj = lower(obj)?;
 
-            Ok(obj.struct_().field_by_name(&attr))
+            Ok(obj.struct_().field_by_name(attr))
         }
         Expr::ArrayIndex { obj, index } => {
-            let obj = to_polars_expr(obj)?;
-            let index = to_polars_expr(index)?;
-            // TODO(danny): Add support for negative indexing
+            let obj = lower(obj)?;
+            let index = lower(index)?;
+            // polars counts negative indices from the end of the list, like python
             Ok(obj.list().get(index, true))
         }
-        Expr::ArraySlice { obj, start, end } => {
-            let obj = to_polars_expr(obj)?;
-            let start = if let Some(start) = start {
-                Some(to_polars_expr(start)?)
-            } else {
-                None
-            };
-            let end = if let Some(end) = end {
-                Some(to_polars_expr(end)?)
-            } else {
-                None
+        Expr::ArraySlice {
+            obj,
+            start,
+            end,
+            step,
+        } => {
+            let obj = lower(obj)?;
+            let start = start.as_deref().map(lower).transpose()?;
+            let end = end.as_deref().map(lower).transpose()?;
+            let step = match step.as_deref() {
+                None => 1,
+                Some(Expr::Int(0)) => return Err(anyhow::anyhow!("slice step cannot be zero")),
+                Some(Expr::Int(step)) => *step,
+                Some(step) => {
+                    return Err(anyhow::anyhow!(
+                        "slice step must be an integer literal, found {:?}",
+                        step
+                    ))
+                }
             };
 
-            if let (None, None) = (start, end) {
-                Ok(obj)
-            } else {
-                // TODO(danny): Also do negative support like python
-                todo!()
-            }
+            list_slice(obj, start, end, step)
         }
     }
 }
 
+/// Replaces identifiers in `expr` that name a parameter with its argument.
+pub(crate) fn substitute(expr: &Expr, bindings: &HashMap<&str, &Expr>) -> Expr {
+    let sub = |expr: &Expr| Box::new(substitute(expr, bindings));
+
+    match expr {
+        Expr::Ident(name) => bindings
+            .get(name.as_str())
+            .map(|arg| (*arg).clone())
+            .unwrap_or_else(|| expr.clone()),
+        Expr::Int(_) | Expr::Float(_) | Expr::Str(_) | Expr::Topic(_) => expr.clone(),
+        Expr::Call { name, args } => Expr::Call {
+            name: name.clone(),
+            args: args.iter().map(|arg| substitute(arg, bindings)).collect(),
+        },
+        Expr::Attribute { obj, attr } => Expr::Attribute {
+            obj: sub(obj),
+            attr: attr.clone(),
+        },
+        Expr::ArrayIndex { obj, index } => Expr::ArrayIndex {
+            obj: sub(obj),
+            index: sub(index),
+        },
+        Expr::ArraySlice {
+            obj,
+            start,
+            end,
+            step,
+        } => Expr::ArraySlice {
+            obj: sub(obj),
+            start: start.as_deref().map(sub),
+            end: end.as_deref().map(sub),
+            step: step.as_deref().map(sub),
+        },
+        Expr::BinOp { lhs, op, rhs } => Expr::BinOp {
+            lhs: sub(lhs),
+            op: op.clone(),
+            rhs: sub(rhs),
+        },
+        Expr::Wildcard { obj, over } => Expr::Wildcard {
+            obj: sub(obj),
+            over: *over,
+        },
+    }
+}
+
+/// Lowers `obj[start:end:step]` with python semantics. `start` and `end` may
+/// be negative or out of range, and may be arbitrary per-row expressions.
+fn list_slice(
+    obj: polars_lazy::dsl::Expr,
+    start: Option<polars_lazy::dsl::Expr>,
+    end: Option<polars_lazy::dsl::Expr>,
+    step: i64,
+) -> Result<polars_lazy::dsl::Expr> {
+    if start.is_none() && end.is_none() && step == 1 {
+        return Ok(obj);
+    }
+
+    let len = obj.clone().list().len().cast(DataType::Int64);
+
+    let (obj, offset, stop) = if step > 0 {
+        let offset = start
+            .map(|start| clamp_slice_bound(start, len.clone(), lit(0i64)))
+            .unwrap_or(lit(0i64));
+        let stop = end
+            .map(|end| clamp_slice_bound(end, len.clone(), lit(0i64)))
+            .unwrap_or(len.clone());
+        (obj, offset, stop)
+    } else {
+        // Walking backwards from `start` to `end` is the same as walking
+        // forwards over the reversed list, with the bounds mirrored.
+        let last = len.clone() - lit(1i64);
+        let offset = start
+            .map(|start| last.clone() - clamp_slice_bound(start, last.clone(), lit(-1i64)))
+            .unwrap_or(lit(0i64));
+        let stop = end
+            .map(|end| last.clone() - clamp_slice_bound(end, last.clone(), lit(-1i64)))
+            .unwrap_or(len.clone());
+        (obj.list().reverse(), offset, stop)
+    };
+
+    let length = when(stop.clone().gt(offset.clone()))
+        .then(stop - offset.clone())
+        .otherwise(lit(0i64));
+    let sliced = obj.list().slice(offset, length);
+
+    Ok(match step.abs() {
+        1 => sliced,
+        step => sliced.list().gather_every(lit(step), lit(0i64)),
+    })
+}
+
+/// Resolves a possibly negative slice bound against the list length, clamped
+/// to `[lower, upper]` like python does for out of range bounds.
+fn clamp_slice_bound(
+    bound: polars_lazy::dsl::Expr,
+    upper: polars_lazy::dsl::Expr,
+    lower: polars_lazy::dsl::Expr,
+) -> polars_lazy::dsl::Expr {
+    let bound = bound.cast(DataType::Int64);
+    let len = upper.clone() - lower.clone();
+    let bound = when(bound.clone().lt(lit(0i64)))
+        .then(bound.clone() + len)
+        .otherwise(bound);
+
+    when(bound.clone().lt(lower.clone()))
+        .then(lower)
+        .when(bound.clone().gt(upper.clone()))
+        .then(upper)
+        .otherwise(bound)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use polars::prelude::{DataFrame, IntoLazy, IntoSeries, NamedFrom, Series};
+
+    fn eval_f64(df: DataFrame, source: &str) -> Vec<f64> {
+        let program = crate::parser::parse_program(source).unwrap();
+        let program = to_polars_program(&program, &BUILTINS).unwrap();
+        let out = program
+            .with_bindings(df.lazy())
+            .select([program.result.clone()])
+            .collect()
+            .unwrap();
+
+        out.get_columns()[0]
+            .as_materialized_series()
+            .cast(&DataType::Float64)
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect()
+    }
     #[test]
     fn test_basic_math() {
         let expr = Expr::BinOp {
@@ -192,4 +331,150 @@ mod tests {
 
         assert_eq!(to_polars_expr(&expr).unwrap(), col("lists").explode());
     }
+
+    #[test]
+    fn test_call_arguments() {
+        let expr = crate::parse("atan2(a, b)").unwrap();
+        assert_eq!(to_polars_expr(&expr).unwrap(), col("a").arctan2(col("b")));
+
+        assert!(to_polars_expr(&crate::parse("atan2(a)").unwrap()).is_err());
+        assert!(to_polars_expr(&crate::parse("sin(a, b)").unwrap()).is_err());
+        assert!(to_polars_expr(&crate::parse("not_a_function(a)").unwrap()).is_err());
+    }
+
+    fn eval_lists(source: &str) -> Vec<Vec<i64>> {
+        let data = Series::new(
+            "data".into(),
+            [
+                Series::new("".into(), [0i64, 1, 2, 3, 4]),
+                Series::new("".into(), [5i64, 6]),
+            ],
+        );
+        let df = DataFrame::new(vec![data.into()]).unwrap();
+        let expr = to_polars_expr(&crate::parse(source).unwrap()).unwrap();
+        let out = df.lazy().select([expr]).collect().unwrap();
+
+        out.get_columns()[0]
+            .as_materialized_series()
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|s| s.unwrap().i64().unwrap().into_no_null_iter().collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_array_negative_index() {
+        let expr = to_polars_expr(&crate::parse("data[-1]").unwrap()).unwrap();
+        let df = DataFrame::new(vec![Series::new(
+            "data".into(),
+            [
+                Series::new("".into(), [0i64, 1, 2, 3, 4]),
+                Series::new("".into(), [5i64, 6]),
+            ],
+        )
+        .into()])
+        .unwrap();
+        let out = df.lazy().select([expr]).collect().unwrap();
+        let values: Vec<i64> = out.get_columns()[0]
+            .as_materialized_series()
+            .i64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(values, vec![4, 6]);
+    }
+
+    #[test]
+    fn test_array_slice() {
+        assert_eq!(eval_lists("data[:]"), vec![vec![0, 1, 2, 3, 4], vec![5, 6]]);
+        assert_eq!(eval_lists("data[2:5]"), vec![vec![2, 3, 4], vec![]]);
+        assert_eq!(eval_lists("data[:-1]"), vec![vec![0, 1, 2, 3], vec![5]]);
+        assert_eq!(eval_lists("data[-2:]"), vec![vec![3, 4], vec![5, 6]]);
+        assert_eq!(eval_lists("data[1:100]"), vec![vec![1, 2, 3, 4], vec![6]]);
+        assert_eq!(eval_lists("data[::2]"), vec![vec![0, 2, 4], vec![5]]);
+        assert_eq!(eval_lists("data[1::2]"), vec![vec![1, 3], vec![6]]);
+        assert_eq!(
+            eval_lists("data[::-1]"),
+            vec![vec![4, 3, 2, 1, 0], vec![6, 5]]
+        );
+        assert_eq!(eval_lists("data[3:0:-1]"), vec![vec![3, 2, 1], vec![6]]);
+        assert_eq!(eval_lists("data[::-2]"), vec![vec![4, 2, 0], vec![6]]);
+        assert_eq!(
+            eval_lists("data[data[0] - 4:]"),
+            vec![vec![1, 2, 3, 4], vec![6]]
+        );
+    }
+
+    #[test]
+    fn test_array_slice_errors() {
+        assert!(to_polars_expr(&crate::parse("data[::0]").unwrap()).is_err());
+        assert!(to_polars_expr(&crate::parse("data[::n]").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_program_bindings() {
+        let df = DataFrame::new(vec![
+            Series::new("utime".into(), [1_000_000i64, 2_000_000]).into()
+        ])
+        .unwrap();
+
+        let script = "
+            let t = utime * 1.0e-6
+            let t2 = t * t  # later bindings see earlier ones
+            t2 + t
+        ";
+        assert_eq!(eval_f64(df.clone(), script), vec![2.0, 6.0]);
+
+        // bindings shadow columns of the same name
+        assert_eq!(eval_f64(df, "let utime = 3\nutime"), vec![3.0, 3.0]);
+    }
+
+    #[test]
+    fn test_program_defs() {
+        let df = DataFrame::new(vec![
+            Series::new("a".into(), [3.0, 6.0]).into(),
+            Series::new("b".into(), [4.0, 8.0]).into(),
+        ])
+        .unwrap();
+
+        let script = "
+            def mag(x, y) = (x^2 + y^2)^0.5
+            def scaled_mag(x, y, k) = mag(x, y) * k
+            scaled_mag(a, b, 2)
+        ";
+        assert_eq!(eval_f64(df.clone(), script), vec![10.0, 20.0]);
+
+        // parameters shadow columns, other names still refer to columns
+        assert_eq!(
+            eval_f64(df.clone(), "def f(a) = a + b\nf(1)"),
+            vec![5.0, 9.0]
+        );
+
+        let program = crate::parser::parse_program("def f(x) = x\nf(1, 2)").unwrap();
+        assert!(to_polars_program(&program, &BUILTINS).is_err());
+    }
+
+    #[test]
+    fn test_quoted_identifiers() {
+        let pose = DataFrame::new(vec![Series::new("x.y".into(), [1.0, 2.0]).into()])
+            .unwrap()
+            .into_struct("pose".into())
+            .into_series();
+        let df = DataFrame::new(vec![
+            Series::new("Motor 1 RPM".into(), [100.0, 200.0]).into(),
+            Series::new("/vehicle/speed".into(), [3.0, 4.0]).into(),
+            pose.into(),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            eval_f64(df.clone(), "`Motor 1 RPM` * 2"),
+            vec![200.0, 400.0]
+        );
+        assert_eq!(
+            eval_f64(df.clone(), "`/vehicle/speed` + pose.`x.y`"),
+            vec![4.0, 6.0]
+        );
+    }
 }
@@ -0,0 +1,255 @@
+//! Data from several topics, each sampled at its own times. Expressions
+//! refer to other topics as `/imu.accel.x` or `topic("/imu").accel.x`, and
+//! are evaluated against the topics they refer to joined on their nearest
+//! timestamps.
+
+use anyhow::Result;
+use polars::prelude::*;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use crate::functions::FunctionRegistry;
+use crate::parser::{Expr, Program, Statement};
+
+/// Column of a joined frame holding the time of each row, taken from the
+/// first topic an expression refers to.
+pub const TIME_COLUMN: &str = "__time";
+
+#[derive(Clone)]
+pub struct Topic {
+    pub frame: LazyFrame,
+    /// Column of `frame` holding the time of each message.
+    pub time: String,
+}
+
+#[derive(Clone, Default)]
+pub struct Topics {
+    topics: BTreeMap<String, Topic>,
+    /// Furthest apart, in units of the time columns, that samples of two
+    /// topics can be and still be joined. Unlimited if `None`.
+    pub tolerance: Option<f64>,
+}
+
+impl fmt::Debug for Topics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Topics")
+            .field("topics", &self.topics.keys().collect::<Vec<_>>())
+            .field("tolerance", &self.tolerance)
+            .finish()
+    }
+}
+
+impl Topics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds topic `name`, e.g. "/imu", replacing any topic with the same name.
+    pub fn insert(&mut self, name: &str, frame: LazyFrame, time: &str) {
+        self.topics.insert(
+            name.to_owned(),
+            Topic {
+                frame,
+                time: time.to_owned(),
+            },
+        );
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Topic> {
+        self.topics.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.topics.keys().map(String::as_str)
+    }
+
+    /// One frame with a struct column per topic in `names`, named after it.
+    /// Rows are the samples of the first topic, each joined with the
+    /// nearest sample of every other topic within the tolerance, or null.
+    pub fn join(&self, names: &[String]) -> Result<LazyFrame> {
+        let mut frames = names.iter().map(|name| {
+            let topic = self.get(name).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "unknown topic `{}`, expected one of: {}",
+                    name,
+                    self.names().collect::<Vec<_>>().join(", ")
+                )
+            })?;
+            Ok(topic
+                .frame
+                .clone()
+                .select([
+                    col(topic.time.as_str())
+                        .cast(DataType::Float64)
+                        .alias(TIME_COLUMN),
+                    as_struct(vec![all()]).alias(name.as_str()),
+                ])
+                .sort([TIME_COLUMN], Default::default()))
+        });
+
+        let first = frames
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("no topics to join"))??;
+        frames.try_fold(first, |joined, frame: Result<LazyFrame>| {
+            let options = AsOfOptions {
+                strategy: AsofStrategy::Nearest,
+                tolerance: self.tolerance.map(AnyValue::Float64),
+                ..Default::default()
+            };
+            Ok(joined
+                .join_builder()
+                .with(frame?)
+                .left_on([col(TIME_COLUMN)])
+                .right_on([col(TIME_COLUMN)])
+                .how(JoinType::AsOf(options))
+                .finish())
+        })
+    }
+}
+
+/// The topics `program` refers to in order of first use, including in the
+/// bodies of slang functions it calls.
+pub fn referenced(program: &Program, functions: &FunctionRegistry) -> Vec<String> {
+    let mut walk = Walk {
+        functions,
+        visited: HashSet::new(),
+        topics: vec![],
+    };
+    for statement in &program.statements {
+        match statement {
+            Statement::Let { value, .. } => walk.expr(value),
+            Statement::Def { body, .. } => walk.expr(body),
+        }
+    }
+    walk.expr(&program.result);
+    walk.topics
+}
+
+struct Walk<'a> {
+    functions: &'a FunctionRegistry,
+    visited: HashSet<String>,
+    topics: Vec<String>,
+}
+
+impl Walk<'_> {
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Int(_) | Expr::Float(_) | Expr::Str(_) | Expr::Ident(_) => {}
+            Expr::Topic(name) => {
+                if !self.topics.contains(name) {
+                    self.topics.push(name.clone());
+                }
+            }
+            Expr::Call { name, args } => {
+                args.iter().for_each(|arg| self.expr(arg));
+                let body = self
+                    .functions
+                    .get(name)
+                    .and_then(|function| function.definition());
+                if let Some((_, body)) = body {
+                    if self.visited.insert(name.clone()) {
+                        self.expr(body);
+                    }
+                }
+            }
+            Expr::Attribute { obj, .. } | Expr::Wildcard { obj, .. } => self.expr(obj),
+            Expr::ArrayIndex { obj, index } => {
+                self.expr(obj);
+                self.expr(index);
+            }
+            Expr::ArraySlice {
+                obj,
+                start,
+                end,
+                step,
+            } => {
+                self.expr(obj);
+                for bound in [start, end, step].into_iter().flatten() {
+                    self.expr(bound);
+                }
+            }
+            Expr::BinOp { lhs, rhs, .. } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Context;
+
+    fn topic(t: &[f64], field: &str, values: &[f64]) -> LazyFrame {
+        let inner = DataFrame::new(vec![Series::new(field.into(), values).into()])
+            .unwrap()
+            .into_struct("accel".into())
+            .into_series();
+        DataFrame::new(vec![Series::new("stamp".into(), t).into(), inner.into()])
+            .unwrap()
+            .lazy()
+    }
+
+    fn context() -> Context {
+        let mut context = Context::new();
+        context.topics.insert(
+            "/imu",
+            topic(&[0.0, 1.0, 2.0, 3.0], "x", &[1.0, 2.0, 3.0, 4.0]),
+            "stamp",
+        );
+        // Sampled just after the imu, and missing the last sample
+        context.topics.insert(
+            "/cmd",
+            topic(&[0.1, 1.1, 2.1], "x", &[10.0, 20.0, 30.0]),
+            "stamp",
+        );
+        context.topics.tolerance = Some(0.5);
+        context
+    }
+
+    #[test]
+    fn test_referenced() {
+        let mut context = context();
+        context
+            .load_library_source("def cmd_x() = /cmd.accel.x")
+            .unwrap();
+        let program = crate::parse_program("let a = /imu.accel.x\na - cmd_x() + /imu").unwrap();
+        assert_eq!(referenced(&program, &context.functions), ["/imu", "/cmd"]);
+    }
+
+    #[test]
+    fn test_eval_topics() {
+        let context = context();
+        let df = DataFrame::empty().lazy();
+
+        let traces = crate::eval_with(&df, "/cmd.accel.x - /imu.accel.x", &context).unwrap();
+        assert_eq!(traces[0].data(), [9.0, 18.0, 27.0]);
+        assert_eq!(traces[0].x, Some(vec![0.1, 1.1, 2.1]));
+
+        let traces =
+            crate::eval_with(&df, "topic(\"/imu\").accel.x * /cmd.accel.x", &context).unwrap();
+        assert_eq!(traces[0].x, Some(vec![0.0, 1.0, 2.0, 3.0]));
+        assert_eq!(&traces[0].data()[..3], [10.0, 40.0, 90.0]);
+        assert!(traces[0].data()[3].is_nan());
+
+        let err = crate::eval_with(&df, "/gps.lat", &context).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "unknown topic `/gps`, expected one of: /cmd, /imu"
+        );
+
+        let time = crate::TimeBase {
+            t: "utime".to_owned(),
+            reference: vec![0.5],
+            method: crate::Interpolation::Linear,
+        };
+        let err = crate::eval_at(&df, "/imu.accel.x", &context, &time)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "`/imu.accel.x` refers to other topics, so can't be interpolated onto a time base"
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! The results of evaluating an expression, one [`Trace`] per line to plot.
+
+use anyhow::Result;
+use polars::prelude::*;
+
+use crate::builtins::strings;
+
+/// The values of a trace, in the type they were computed as so nothing is
+/// lost before they are plotted. Missing values hold a default, see
+/// [`Trace::validity`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Values {
+    Float(Vec<f64>),
+    Int(Vec<i64>),
+    UInt(Vec<u64>),
+    Boolean(Vec<bool>),
+    /// Time since the unix epoch, in `unit`s.
+    Datetime {
+        values: Vec<i64>,
+        unit: TimeUnit,
+        time_zone: Option<String>,
+    },
+}
+
+impl Values {
+    /// The values of `series`, with missing values as defaults. Strings are
+    /// numbered by category, and types without a variant of their own are
+    /// cast to floats.
+    pub fn from_series(series: &Series) -> Result<Self> {
+        let dtype = series.dtype();
+        if strings::is_categorical(dtype) {
+            return Self::from_series(&strings::codes(series)?);
+        }
+        Ok(match dtype {
+            DataType::Boolean => Self::Boolean(
+                series
+                    .bool()?
+                    .iter()
+                    .map(|value| value.unwrap_or_default())
+                    .collect(),
+            ),
+            DataType::Datetime(unit, time_zone) => Self::Datetime {
+                values: defaults(series.to_physical_repr().i64()?),
+                unit: *unit,
+                time_zone: time_zone.as_ref().map(|zone| zone.to_string()),
+            },
+            dtype if dtype.is_signed_integer() => {
+                Self::Int(defaults(series.cast(&DataType::Int64)?.i64()?))
+            }
+            dtype if dtype.is_unsigned_integer() => {
+                Self::UInt(defaults(series.cast(&DataType::UInt64)?.u64()?))
+            }
+            _ => Self::Float(
+                series
+                    .cast(&DataType::Float64)?
+                    .f64()?
+                    .iter()
+                    .map(|value| value.unwrap_or(f64::NAN))
+                    .collect(),
+            ),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Float(values) => values.len(),
+            Self::Int(values) => values.len(),
+            Self::UInt(values) => values.len(),
+            Self::Boolean(values) => values.len(),
+            Self::Datetime { values, .. } => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Each value as a float, datetimes as seconds since the epoch.
+    pub fn to_f64(&self) -> Vec<f64> {
+        match self {
+            Self::Float(values) => values.clone(),
+            Self::Int(values) => values.iter().map(|&value| value as f64).collect(),
+            Self::UInt(values) => values.iter().map(|&value| value as f64).collect(),
+            Self::Boolean(values) => values.iter().map(|&value| value as u8 as f64).collect(),
+            Self::Datetime { values, unit, .. } => {
+                let per_second = match unit {
+                    TimeUnit::Nanoseconds => 1e9,
+                    TimeUnit::Microseconds => 1e6,
+                    TimeUnit::Milliseconds => 1e3,
+                };
+                values
+                    .iter()
+                    .map(|&value| value as f64 / per_second)
+                    .collect()
+            }
+        }
+    }
+}
+
+fn defaults<T>(values: &ChunkedArray<T>) -> Vec<T::Native>
+where
+    T: PolarsNumericType,
+{
+    values
+        .iter()
+        .map(|value| value.unwrap_or_default())
+        .collect()
+}
+
+pub struct Trace {
+    pub name: String,
+    pub values: Values,
+    /// Whether each value is present, or `None` if they all are. Missing
+    /// values, e.g. the first sample of `diff`, are plotted as gaps.
+    pub validity: Option<Vec<bool>>,
+    /// The x axis of the values, when it isn't the x expression being plotted against.
+    pub x: Option<Vec<f64>>,
+}
+
+impl Trace {
+    /// A trace of the values in `series`.
+    pub fn from_series(name: String, series: &Series, x: Option<Vec<f64>>) -> Result<Self> {
+        let validity = (series.null_count() > 0).then(|| {
+            series
+                .is_not_null()
+                .into_iter()
+                .map(|valid| valid == Some(true))
+                .collect()
+        });
+        Ok(Self {
+            name,
+            values: Values::from_series(series)?,
+            validity,
+            x,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Whether the value at `index` is present.
+    pub fn is_valid(&self, index: usize) -> bool {
+        self.validity
+            .as_ref()
+            .is_none_or(|validity| validity[index])
+    }
+
+    /// Each value as a float, see [`Values::to_f64`], with missing values
+    /// as NaN.
+    pub fn data(&self) -> Vec<f64> {
+        let mut data = self.values.to_f64();
+        if let Some(validity) = &self.validity {
+            for (value, valid) in data.iter_mut().zip(validity) {
+                if !valid {
+                    *value = f64::NAN;
+                }
+            }
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_types() {
+        let df = df!(
+            "utime" => [1_700_000_000_000_001i64, 1_700_000_000_000_002],
+            "flag" => [Some(true), None],
+            "count" => [Some(3u32), Some(4)],
+            "value" => [Some(0.5), None],
+        )
+        .unwrap()
+        .lazy()
+        .with_column(
+            col("utime")
+                .cast(DataType::Datetime(TimeUnit::Microseconds, None))
+                .alias("time"),
+        );
+
+        let trace = |expr| crate::eval(&df, expr).unwrap().remove(0);
+
+        let utime = trace("utime");
+        assert_eq!(
+            utime.values,
+            Values::Int(vec![1_700_000_000_000_001, 1_700_000_000_000_002])
+        );
+        assert_eq!(utime.validity, None);
+
+        let flag = trace("flag");
+        assert_eq!(flag.values, Values::Boolean(vec![true, false]));
+        assert_eq!(flag.validity, Some(vec![true, false]));
+        assert!(flag.is_valid(0) && !flag.is_valid(1));
+        assert_eq!(flag.data()[0], 1.0);
+        assert!(flag.data()[1].is_nan());
+
+        assert_eq!(trace("count + count").values, Values::UInt(vec![6, 8]));
+        assert!(trace("value").data()[1].is_nan());
+
+        let time = trace("time");
+        assert_eq!(
+            time.values,
+            Values::Datetime {
+                values: vec![1_700_000_000_000_001, 1_700_000_000_000_002],
+                unit: TimeUnit::Microseconds,
+                time_zone: None,
+            }
+        );
+        assert_eq!(time.data()[0], 1_700_000_000.000_001);
+    }
+}
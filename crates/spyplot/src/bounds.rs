@@ -31,8 +31,8 @@ impl Bounds {
         let new_min = point + by.component_mul(&(self.min - point));
         let new_max = point + by.component_mul(&(self.max - point));
         Self {
-            min: new_min.into(),
-            max: new_max.into(),
+            min: new_min,
+            max: new_max,
         }
     }
 
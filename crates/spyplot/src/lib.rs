@@ -311,7 +311,7 @@ impl egui_wgpu::CallbackTrait for SpyplotCallback {
         resources: &mut egui_wgpu::CallbackResources,
     ) -> Vec<wgpu::CommandBuffer> {
         let resources: &mut SpyplotRenderResources = resources.get_mut().unwrap();
-        resources.prepare(device, queue, &self);
+        resources.prepare(device, queue, self);
         Vec::new()
     }
 
@@ -4,7 +4,7 @@ use slang::LazyFrame;
 use slang::PolarsError;
 use slang::PolarsResult;
 use spyplot::Spyplot;
-use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
 #[derive(serde::Deserialize, serde::Serialize)]
@@ -18,13 +18,34 @@ pub struct TemplateApp {
     x_expr: String,
     y_exprs: Vec<String>,
 
+    /// Path to a `.slang` file of `def`s available to every expression.
+    library_path: String,
+
+    /// Other files expressions can refer to, e.g. `/imu.accel.x`.
+    topics: Vec<TopicFile>,
+
     error: Option<String>,
 
+    /// The expression the last error points into, its source when it was
+    /// run, and where in it the problems are.
+    #[serde(skip)]
+    diagnostics: Option<(ExprId, String, Vec<slang::Diagnostic>)>,
+
     use_spyplot: bool,
 
+    show_spectrogram: bool,
+    /// Samples per spectrogram segment, segments overlap by half.
+    spectrogram_nperseg: usize,
+    /// Slang expression for the time of each sample in seconds, which the
+    /// spectrogram's frequencies are worked out from.
+    spectrogram_t: String,
+
     #[serde(skip)]
     xy_plot: crate::xy_plot::XYPlot,
 
+    #[serde(skip)]
+    spectrogram_plot: crate::spectrogram_plot::SpectrogramPlot,
+
     #[serde(skip)]
     spyplot: Option<spyplot::Spyplot>,
 }
@@ -36,9 +57,16 @@ impl Default for TemplateApp {
             df: Err(PolarsError::NoData("No data".into())),
             x_expr: "utime".to_owned(),
             y_exprs: vec!["position.data[0]".to_owned()],
+            library_path: "".to_owned(),
+            topics: vec![],
             error: None,
+            diagnostics: None,
             use_spyplot: false,
+            show_spectrogram: false,
+            spectrogram_nperseg: 256,
+            spectrogram_t: "utime * 1.0e-6".to_owned(),
             xy_plot: Default::default(),
+            spectrogram_plot: Default::default(),
             spyplot: None,
         }
     }
@@ -65,8 +93,17 @@ impl TemplateApp {
             } else {
                 stored
             };
+            let topics = stored
+                .topics
+                .into_iter()
+                .map(|topic| TopicFile {
+                    frame: slang::read_data(topic.path.clone()).ok(),
+                    ..topic
+                })
+                .collect();
             return Self {
                 spyplot: Spyplot::new(cc),
+                topics,
                 ..stored
             };
         }
@@ -99,8 +136,12 @@ impl eframe::App for TemplateApp {
                 let is_web = cfg!(target_arch = "wasm32");
                 if !is_web {
                     // maybe get new dataframe
-                    if let Some(df) = self.file_io.ui(ui, ctx) {
-                        self.df = df;
+                    match self.file_io.ui(ui, ctx) {
+                        Some(crate::file_io::Opened::Data(df)) => self.df = df,
+                        Some(crate::file_io::Opened::Topic(path)) => {
+                            self.topics.push(TopicFile::load(path));
+                        }
+                        None => {}
                     }
                 }
 
@@ -126,10 +167,27 @@ impl eframe::App for TemplateApp {
             });
             ui.separator();
 
-            ui.checkbox(&mut self.use_spyplot, "Use spyplot viewer");
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.use_spyplot, "Use spyplot viewer");
+                ui.checkbox(&mut self.show_spectrogram, "Show spectrogram")
+                    .on_hover_text("Spectrogram of the first y trace");
+                if self.show_spectrogram {
+                    ui.label("Time:");
+                    ui.text_edit_singleline(&mut self.spectrogram_t)
+                        .on_hover_text("Time of each sample in seconds");
+                    ui.label("Segment:");
+                    ui.add(
+                        egui::DragValue::new(&mut self.spectrogram_nperseg)
+                            .range(2..=65536)
+                            .suffix(" samples"),
+                    );
+                }
+            });
 
             if self.use_spyplot {
                 self.spyplot.as_mut().unwrap().ui(ui);
+            } else if self.show_spectrogram {
+                self.spectrogram_plot.ui(ui);
             } else {
                 self.xy_plot.ui(ui);
             }
@@ -143,7 +201,49 @@ impl eframe::App for TemplateApp {
 
 impl TemplateApp {
     fn editor_ui(&mut self, ui: &mut egui::Ui) {
-        ui.text_edit_singleline(&mut self.x_expr);
+        ui.horizontal(|ui| {
+            ui.label("Library:");
+            ui.text_edit_singleline(&mut self.library_path)
+                .on_hover_text("Path to a .slang file of `def`s, reloaded on every run");
+        });
+        if !self.topics.is_empty() {
+            ui.collapsing("Topics", |ui| {
+                let mut removed = None;
+                for (index, topic) in self.topics.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.text_edit_singleline(&mut topic.name)
+                            .on_hover_text("Name expressions refer to the topic by, e.g. /imu");
+                        ui.label("time:");
+                        ui.text_edit_singleline(&mut topic.time)
+                            .on_hover_text("Column holding the time of each message");
+                        if topic.frame.is_none() {
+                            ui.colored_label(egui::Color32::RED, "failed to load")
+                                .on_hover_text(topic.path.display().to_string());
+                        }
+                        if ui.small_button("x").on_hover_text("Remove").clicked() {
+                            removed = Some(index);
+                        }
+                    });
+                }
+                if let Some(index) = removed {
+                    self.topics.remove(index);
+                }
+            });
+        }
+        // Completions are offered from the columns of the open file
+        let schema = self
+            .df
+            .as_ref()
+            .ok()
+            .and_then(|df| df.clone().collect_schema().ok());
+        let diagnostics = self.diagnostics_for(ExprId::X);
+        expr_editor(
+            ui,
+            ExprId::X,
+            &mut self.x_expr,
+            diagnostics,
+            schema.as_deref(),
+        );
         ui.horizontal(|ui| {
             if ui.small_button("-").clicked() {
                 self.y_exprs.pop();
@@ -156,8 +256,15 @@ impl TemplateApp {
                 }
             }
         });
-        for y_expr in &mut self.y_exprs {
-            ui.text_edit_singleline(y_expr);
+        for index in 0..self.y_exprs.len() {
+            let diagnostics = self.diagnostics_for(ExprId::Y(index));
+            expr_editor(
+                ui,
+                ExprId::Y(index),
+                &mut self.y_exprs[index],
+                diagnostics,
+                schema.as_deref(),
+            );
         }
 
         if ui
@@ -165,7 +272,30 @@ impl TemplateApp {
             .on_hover_text("Run the expression")
             .clicked()
         {
-            self.error = self.eval_and_plot().err().map(|e| e.to_string());
+            let result = self.eval_and_plot();
+            self.error = None;
+            self.diagnostics = None;
+            if let Err(error) = result {
+                // Errors that point into an expression are shown under it
+                let diagnostics = match (
+                    error.downcast_ref::<slang::Diagnostics>(),
+                    error.downcast_ref::<slang::Diagnostic>(),
+                ) {
+                    (Some(all), _) => Some(all.0.clone()),
+                    (None, Some(one)) => Some(vec![one.clone()]),
+                    (None, None) => None,
+                };
+                match (error.downcast_ref::<ExprId>(), diagnostics) {
+                    (Some(id), Some(diagnostics)) => {
+                        let source = match id {
+                            ExprId::X => self.x_expr.clone(),
+                            ExprId::Y(index) => self.y_exprs[*index].clone(),
+                        };
+                        self.diagnostics = Some((*id, source, diagnostics));
+                    }
+                    _ => self.error = Some(format!("{:#}", error)),
+                }
+            }
         }
 
         if let Some(error) = &self.error {
@@ -173,26 +303,79 @@ impl TemplateApp {
         }
     }
 
+    /// The diagnostics for expression `id` and its source when it was run,
+    /// if the last run failed there.
+    fn diagnostics_for(&self, id: ExprId) -> Option<(String, Vec<slang::Diagnostic>)> {
+        match &self.diagnostics {
+            Some((at, source, diagnostics)) if *at == id => {
+                Some((source.clone(), diagnostics.clone()))
+            }
+            _ => None,
+        }
+    }
+
     fn eval_and_plot(&mut self) -> Result<()> {
         if let PolarsResult::Ok(df) = &self.df {
-            let mut y_series: HashMap<String, Vec<f64>> = HashMap::new();
-            for y_expr in self.y_exprs.iter() {
-                for y_trace in slang::eval(df, &y_expr)?.into_iter() {
-                    y_series.insert(y_trace.name, y_trace.data);
+            let mut context = slang::Context::new();
+            if !self.library_path.is_empty() {
+                context.load_library(&self.library_path)?;
+            }
+            for topic in &self.topics {
+                if let Some(frame) = &topic.frame {
+                    context
+                        .topics
+                        .insert(&topic.name, frame.clone(), &topic.time);
                 }
             }
 
-            let x_data = slang::eval(df, &self.x_expr)?
-                .into_iter()
+            // x first, then each y, all in one query
+            let exprs: Vec<&str> = std::iter::once(&self.x_expr)
+                .chain(&self.y_exprs)
+                .map(String::as_str)
+                .collect();
+            let mut traces = slang::eval_many_with(df, &exprs, &context)
+                .map_err(|error| match error.downcast::<slang::ExprError>() {
+                    Ok(slang::ExprError { index: 0, error }) => error.context(ExprId::X),
+                    Ok(slang::ExprError { index, error }) => error.context(ExprId::Y(index - 1)),
+                    Err(error) => error,
+                })?
+                .into_iter();
+
+            let x_trace = traces
                 .next()
-                .ok_or(anyhow::anyhow!("No x_expr trace"))?
-                .data;
-            self.xy_plot.set_data(&x_data, &y_series);
-            if y_series.len() != 0 {
-                let points: Vec<[f64; 2]> = x_data
+                .and_then(|traces| traces.into_iter().next())
+                .ok_or(anyhow::anyhow!("No x_expr trace"))?;
+            let y_traces: Vec<slang::Trace> = traces.flatten().collect();
+            self.xy_plot.set_data(&x_trace, &y_traces);
+            let x_data = x_trace.data();
+
+            let spectrogram = match (self.show_spectrogram, y_traces.first()) {
+                (true, Some(trace)) if trace.x.is_none() => {
+                    let t = slang::eval_with(df, &self.spectrogram_t, &context)?
+                        .into_iter()
+                        .next()
+                        .ok_or(anyhow::anyhow!("No spectrogram time trace"))?
+                        .data();
+                    Some(slang::spectrogram(
+                        &trace.data(),
+                        &t,
+                        self.spectrogram_nperseg,
+                    )?)
+                }
+                _ => None,
+            };
+            self.spectrogram_plot.set_data(spectrogram);
+
+            if let Some(trace) = y_traces.first() {
+                let points: Vec<[f64; 2]> = trace
+                    .x
+                    .as_deref()
+                    .unwrap_or(&x_data)
                     .iter()
-                    .zip(y_series.iter().next().unwrap().1)
-                    .map(|(x, y)| [*x, *y])
+                    .zip(trace.data())
+                    .map(|(x, y)| [*x, y])
+                    // No gaps in the line, but at least no NaNs in the vertices
+                    .filter(|point| point.iter().all(|value| value.is_finite()))
                     .collect();
 
                 let spyplot = self.spyplot.as_mut().expect("Spyplot not initialized!");
@@ -205,6 +388,291 @@ impl TemplateApp {
     }
 }
 
+/// A file loaded as a topic.
+#[derive(serde::Deserialize, serde::Serialize)]
+struct TopicFile {
+    /// What expressions call the topic, e.g. `/imu`.
+    name: String,
+    path: PathBuf,
+    /// Column holding the time of each message.
+    time: String,
+    #[serde(skip)]
+    frame: Option<LazyFrame>,
+}
+
+impl TopicFile {
+    /// Loads `path` as a topic named after the file, with the first column
+    /// that looks like a time as its time.
+    fn load(path: PathBuf) -> Self {
+        let frame = slang::read_data(path.clone()).ok();
+        let columns: Vec<String> = frame
+            .as_ref()
+            .and_then(|frame| frame.clone().collect_schema().ok())
+            .map(|schema| schema.iter_names().map(|name| name.to_string()).collect())
+            .unwrap_or_default();
+        let time = ["utime", "timestamp", "time", "t"]
+            .into_iter()
+            .find(|time| columns.iter().any(|column| column == time))
+            .or(columns.first().map(String::as_str))
+            .unwrap_or("utime")
+            .to_owned();
+        Self {
+            name: topic_name(&path),
+            path,
+            time,
+            frame,
+        }
+    }
+}
+
+/// `/` and the file name of `path` without its extension, with anything
+/// that can't be in a topic name replaced, e.g. `/imu_2` for `imu-2.parquet`.
+fn topic_name(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let stem: String = stem
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    format!("/{}", stem)
+}
+
+/// One of the expressions in the editor.
+#[derive(Clone, Copy, Debug, Hash, PartialEq)]
+enum ExprId {
+    X,
+    Y(usize),
+}
+
+impl std::fmt::Display for ExprId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExprId::X => write!(f, "x expression"),
+            ExprId::Y(index) => write!(f, "y expression {}", index + 1),
+        }
+    }
+}
+
+/// Adds an editor for expression `id`, and if the last run failed in it
+/// underlines each problem and shows the diagnostics below. The underlines
+/// are dropped once the expression is edited. While typing, names from
+/// `schema` and the builtin functions are offered in a popup.
+fn expr_editor(
+    ui: &mut egui::Ui,
+    id: ExprId,
+    text: &mut String,
+    diagnostics: Option<(String, Vec<slang::Diagnostic>)>,
+    schema: Option<&slang::Schema>,
+) {
+    // Multiline so scripts with `let` bindings can be written out
+    let multiline = matches!(id, ExprId::Y(_));
+    let id = ui.make_persistent_id(id);
+    let popup_id = id.with("completions");
+    let mut popup: CompletionPopup = ui
+        .data_mut(|data| data.get_temp(popup_id))
+        .unwrap_or_default();
+
+    // Keys for the popup are taken before the editor sees them
+    let open = !popup.completions.is_empty() && ui.memory(|memory| memory.has_focus(id));
+    if open {
+        let count = popup.completions.len();
+        let mut accepted = None;
+        ui.input_mut(|input| {
+            if input.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown) {
+                popup.selected = (popup.selected + 1) % count;
+            }
+            if input.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp) {
+                popup.selected = (popup.selected + count - 1) % count;
+            }
+            if input.consume_key(egui::Modifiers::NONE, egui::Key::Tab)
+                || input.consume_key(egui::Modifiers::NONE, egui::Key::Enter)
+            {
+                accepted = popup.completions.get(popup.selected).cloned();
+            }
+            if input.consume_key(egui::Modifiers::NONE, egui::Key::Escape) {
+                popup.dismissed = true;
+            }
+        });
+        if let Some(completion) = accepted {
+            accept_completion(ui.ctx(), id, text, &completion);
+            // Until the next edit, or the completed name is offered again
+            popup = CompletionPopup {
+                dismissed: true,
+                ..Default::default()
+            };
+        }
+    }
+
+    let style = match multiline {
+        true => egui::TextStyle::Monospace,
+        false => egui::TextStyle::Body,
+    };
+    let mut layouter = |ui: &egui::Ui, text: &str, wrap_width: f32| {
+        let plain = egui::TextFormat::simple(style.resolve(ui.style()), ui.visuals().text_color());
+        let mut job = egui::text::LayoutJob::default();
+        let marked = egui::TextFormat {
+            underline: egui::Stroke::new(1.5, egui::Color32::RED),
+            ..plain.clone()
+        };
+        let mut spans: Vec<_> = match &diagnostics {
+            Some((source, diagnostics)) if text == source.as_str() => diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.span.clone())
+                .filter(|span| text.is_char_boundary(span.start) && text.is_char_boundary(span.end))
+                .collect(),
+            _ => vec![],
+        };
+        spans.sort_by_key(|span| span.start);
+        let mut end = 0;
+        for span in spans {
+            // Overlapping spans are underlined as far as the first one goes
+            if span.start < end {
+                continue;
+            }
+            job.append(&text[end..span.start], 0.0, plain.clone());
+            job.append(&text[span.clone()], 0.0, marked.clone());
+            end = span.end;
+        }
+        job.append(&text[end..], 0.0, plain);
+        job.wrap.max_width = wrap_width;
+        ui.fonts(|fonts| fonts.layout_job(job))
+    };
+
+    let editor = match multiline {
+        true => egui::TextEdit::multiline(text)
+            .code_editor()
+            .desired_rows(1),
+        false => egui::TextEdit::singleline(text),
+    };
+    let output = editor.id(id).layouter(&mut layouter).show(ui);
+
+    let cursor = output
+        .cursor_range
+        .filter(|_| output.response.has_focus())
+        .map(|range| range.primary);
+    match (cursor, schema) {
+        (Some(cursor), Some(schema)) => {
+            if output.response.changed() {
+                popup.dismissed = false;
+            }
+            let offset = byte_offset(text, cursor.ccursor.index);
+            let completions = slang::complete(text, offset, schema);
+            // Only offered once something is typed, or after a `.` or `[`
+            let typing = completions
+                .first()
+                .is_some_and(|completion| !completion.replace.is_empty())
+                || text[..offset].ends_with(['.', '[']);
+            let completions = match typing && !popup.dismissed {
+                true => completions,
+                false => vec![],
+            };
+            if completions != popup.completions {
+                popup.selected = 0;
+                popup.completions = completions;
+            }
+
+            if !popup.completions.is_empty() {
+                let below_cursor = output.galley.pos_from_cursor(&cursor).left_bottom();
+                let mut clicked = None;
+                egui::Area::new(popup_id)
+                    .order(egui::Order::Foreground)
+                    .fixed_pos(output.galley_pos + below_cursor.to_vec2())
+                    .show(ui.ctx(), |ui| {
+                        egui::Frame::popup(ui.style()).show(ui, |ui| {
+                            egui::ScrollArea::vertical()
+                                .max_height(200.0)
+                                .show(ui, |ui| {
+                                    for (index, completion) in popup.completions.iter().enumerate()
+                                    {
+                                        let label = ui.selectable_label(
+                                            index == popup.selected,
+                                            egui::RichText::new(format!(
+                                                "{}  {}",
+                                                completion.text, completion.detail
+                                            ))
+                                            .monospace(),
+                                        );
+                                        // On press, the editor loses focus before a click
+                                        if label.is_pointer_button_down_on() {
+                                            clicked = Some(completion.clone());
+                                        }
+                                    }
+                                });
+                        });
+                    });
+                if let Some(completion) = clicked {
+                    accept_completion(ui.ctx(), id, text, &completion);
+                    ui.memory_mut(|memory| memory.request_focus(id));
+                    popup = CompletionPopup {
+                        dismissed: true,
+                        ..Default::default()
+                    };
+                }
+            }
+        }
+        _ => popup = CompletionPopup::default(),
+    }
+    ui.data_mut(|data| data.insert_temp(popup_id, popup));
+
+    if let Some((source, diagnostics)) = &diagnostics {
+        for diagnostic in diagnostics {
+            ui.label(
+                egui::RichText::new(diagnostic.render(source))
+                    .monospace()
+                    .color(egui::Color32::RED),
+            );
+        }
+    }
+}
+
+/// Completions offered in an expression editor, kept between frames.
+#[derive(Clone, Default)]
+struct CompletionPopup {
+    completions: Vec<slang::Completion>,
+    selected: usize,
+    /// Hidden with escape until the expression is next edited.
+    dismissed: bool,
+}
+
+/// Replaces the word `completion` completes in `text` and puts the cursor of
+/// editor `id` after it.
+fn accept_completion(
+    ctx: &egui::Context,
+    id: egui::Id,
+    text: &mut String,
+    completion: &slang::Completion,
+) {
+    let range = completion.replace.clone();
+    if range.end > text.len()
+        || !text.is_char_boundary(range.start)
+        || !text.is_char_boundary(range.end)
+    {
+        return;
+    }
+    text.replace_range(range.clone(), &completion.text);
+    let cursor = text[..range.start + completion.text.len()].chars().count();
+    if let Some(mut state) = egui::TextEdit::load_state(ctx, id) {
+        state
+            .cursor
+            .set_char_range(Some(egui::text::CCursorRange::one(
+                egui::text::CCursor::new(cursor),
+            )));
+        state.store(ctx, id);
+    }
+}
+
+/// Byte offset of character `index` of `text`, egui cursors count characters.
+fn byte_offset(text: &str, index: usize) -> usize {
+    text.char_indices()
+        .nth(index)
+        .map_or(text.len(), |(offset, _)| offset)
+}
+
 fn describe_data_type(data_type: &DataType) -> String {
     match data_type {
         DataType::Struct(_inner) => "struct".to_string(),
@@ -220,6 +688,9 @@ fn describe_data_type(data_type: &DataType) -> String {
         DataType::UInt64 => "uint64".to_string(),
         DataType::Float32 => "float32".to_string(),
         DataType::Float64 => "float64".to_string(),
+        DataType::String => "string".to_string(),
+        DataType::Categorical(..) => "categorical".to_string(),
+        DataType::Enum(..) => "enum".to_string(),
         DataType::Date => "Date".to_string(),
         DataType::Datetime(time_unit, time_zone) => format!(
             "DateTime({}{})",
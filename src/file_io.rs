@@ -17,12 +17,23 @@ lazy_static! {
     };
 }
 
+/// A file picked in the dialog.
+pub(crate) enum Opened {
+    /// The data to plot.
+    Data(PolarsResult<LazyFrame>),
+    /// Another file, for expressions to refer to as a topic.
+    Topic(PathBuf),
+}
+
 #[derive(Default, serde::Deserialize, serde::Serialize)]
 #[serde(default)] // if we add new fields, give them default values when deserializing old state
 pub(crate) struct SpyglassFileDialog {
     pub(crate) opened_file: Option<PathBuf>,
     #[serde(skip)]
     open_file_dialog: Option<FileDialog>,
+    /// Whether the open dialog is adding a topic rather than loading data.
+    #[serde(skip)]
+    adding_topic: bool,
 }
 
 impl SpyglassFileDialog {
@@ -30,20 +41,22 @@ impl SpyglassFileDialog {
         Self {
             opened_file: self.opened_file.clone(),
             open_file_dialog: None,
+            adding_topic: false,
         }
     }
 
     #[must_use]
-    pub(crate) fn ui(
-        &mut self,
-        ui: &mut egui::Ui,
-        ctx: &egui::Context,
-    ) -> Option<PolarsResult<LazyFrame>> {
+    pub(crate) fn ui(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) -> Option<Opened> {
         ui.menu_button("File", |ui| {
             if ui.button("Quit").clicked() {
                 ctx.send_viewport_cmd(egui::ViewportCommand::Close);
             }
-            if ui.button("Load").clicked() {
+            let load = ui.button("Load").clicked();
+            let add_topic = ui
+                .button("Add topic")
+                .on_hover_text("Load another file for expressions to refer to, e.g. /imu.accel.x")
+                .clicked();
+            if load || add_topic {
                 let filter = Box::new({
                     move |path: &Path| -> bool {
                         path.extension()
@@ -54,6 +67,7 @@ impl SpyglassFileDialog {
                     FileDialog::open_file(self.opened_file.clone()).show_files_filter(filter);
                 dialog.open();
                 self.open_file_dialog = Some(dialog);
+                self.adding_topic = add_topic;
 
                 ui.close_menu();
             }
@@ -62,8 +76,13 @@ impl SpyglassFileDialog {
         if let Some(dialog) = &mut self.open_file_dialog {
             if dialog.show(ctx).selected() {
                 if let Some(file) = dialog.path() {
+                    if self.adding_topic {
+                        return Some(Opened::Topic(file.to_path_buf()));
+                    }
                     self.opened_file = Some(file.to_path_buf());
-                    return Some(slang::read_data(self.opened_file.clone().unwrap()));
+                    return Some(Opened::Data(slang::read_data(
+                        self.opened_file.clone().unwrap(),
+                    )));
                 }
             }
         }
@@ -2,5 +2,6 @@
 
 mod app;
 mod file_io;
+mod spectrogram_plot;
 mod xy_plot;
 pub use app::TemplateApp;
@@ -0,0 +1,134 @@
+use egui::{Color32, ColorImage, Response, TextureHandle, TextureOptions};
+use egui_plot::{Legend, Plot, PlotImage, PlotPoint};
+use slang::Spectrogram;
+
+/// Power below the peak by more than this is drawn as the floor color.
+const DYNAMIC_RANGE_DB: f64 = 80.0;
+
+/// Stops of the colormap from the floor to the peak.
+const COLORMAP: [[u8; 3]; 5] = [
+    [68, 1, 84],
+    [59, 82, 139],
+    [33, 145, 140],
+    [94, 201, 98],
+    [253, 231, 37],
+];
+
+#[derive(Default)]
+pub struct SpectrogramPlot {
+    spectrogram: Option<Spectrogram>,
+    texture: Option<TextureHandle>,
+}
+
+impl SpectrogramPlot {
+    pub fn set_data(&mut self, spectrogram: Option<Spectrogram>) {
+        self.spectrogram = spectrogram;
+        self.texture = None;
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) -> Response {
+        let plot = Plot::new("spectrogram_plot")
+            .legend(Legend::default())
+            .show_grid(true)
+            .x_axis_label("time (s)")
+            .y_axis_label("frequency (Hz)");
+
+        let spectrogram = self
+            .spectrogram
+            .as_ref()
+            .filter(|spectrogram| !spectrogram.times.is_empty());
+        let image = spectrogram.and_then(|spectrogram| {
+            let texture = self.texture.get_or_insert_with(|| {
+                ui.ctx().load_texture(
+                    "spectrogram",
+                    to_image(spectrogram),
+                    TextureOptions::NEAREST,
+                )
+            });
+            to_plot_image(spectrogram, texture)
+        });
+
+        plot.show(ui, |plot_ui| {
+            if let Some(image) = image {
+                plot_ui.image(image.name("power (dB)"));
+            }
+        })
+        .response
+    }
+}
+
+/// Power in dB as an image with time along x and frequency increasing up y.
+fn to_image(spectrogram: &Spectrogram) -> ColorImage {
+    let db: Vec<Vec<f64>> = spectrogram
+        .power
+        .iter()
+        .map(|column| {
+            column
+                .iter()
+                .map(|p| 10.0 * p.max(f64::MIN_POSITIVE).log10())
+                .collect()
+        })
+        .collect();
+    let peak = db
+        .iter()
+        .flatten()
+        .copied()
+        .fold(f64::NEG_INFINITY, f64::max);
+
+    let width = db.len();
+    let height = spectrogram.frequencies.len();
+    let mut pixels = Vec::with_capacity(width * height);
+    for row in (0..height).rev() {
+        for column in &db {
+            let level = 1.0 - (peak - column[row]) / DYNAMIC_RANGE_DB;
+            pixels.push(colormap(level.clamp(0.0, 1.0)));
+        }
+    }
+
+    ColorImage {
+        size: [width, height],
+        pixels,
+    }
+}
+
+fn colormap(level: f64) -> Color32 {
+    let position = level * (COLORMAP.len() - 1) as f64;
+    let index = (position as usize).min(COLORMAP.len() - 2);
+    let fraction = position - index as f64;
+    let [r, g, b] = [0, 1, 2].map(|channel| {
+        let low = COLORMAP[index][channel] as f64;
+        let high = COLORMAP[index + 1][channel] as f64;
+        (low + (high - low) * fraction).round() as u8
+    });
+    Color32::from_rgb(r, g, b)
+}
+
+/// Places the image so each pixel is centered on its segment time and
+/// frequency bin.
+fn to_plot_image(spectrogram: &Spectrogram, texture: &TextureHandle) -> Option<PlotImage> {
+    let times = &spectrogram.times;
+    let frequencies = &spectrogram.frequencies;
+    let (first_time, last_time) = (*times.first()?, *times.last()?);
+    let (first_frequency, last_frequency) = (*frequencies.first()?, *frequencies.last()?);
+
+    let time_step = match times.len() {
+        1 => 1.0,
+        n => (last_time - first_time) / (n - 1) as f64,
+    };
+    let frequency_step = match frequencies.len() {
+        1 => 1.0,
+        n => (last_frequency - first_frequency) / (n - 1) as f64,
+    };
+
+    Some(PlotImage::new(
+        texture.id(),
+        PlotPoint::new(
+            (first_time + last_time) / 2.0,
+            (first_frequency + last_frequency) / 2.0,
+        ),
+        [
+            (last_time - first_time + time_step) as f32,
+            (last_frequency - first_frequency + frequency_step) as f32,
+        ],
+    ))
+}
@@ -1,3 +1,5 @@
+use chrono::DateTime;
+use chrono_tz::Tz;
 use egui::epaint::Hsva;
 use egui::{Color32, ComboBox, Response, TextWrapMode};
 use egui_plot::{
@@ -15,7 +17,12 @@ pub struct XYPlot {
     scatter_plot: bool,
     line_style: LineStyle,
 
-    plot_points: HashMap<String, Vec<PlotPoint>>,
+    /// The segments of each trace between missing values.
+    plot_points: HashMap<String, Vec<Vec<PlotPoint>>>,
+    /// Whether x is a datetime, in seconds since the epoch.
+    x_datetime: bool,
+    /// The time zone of x, if it is a datetime with one.
+    x_time_zone: Option<Tz>,
 }
 
 impl Default for XYPlot {
@@ -28,6 +35,8 @@ impl Default for XYPlot {
             scatter_plot: false,
 
             plot_points: HashMap::new(),
+            x_datetime: false,
+            x_time_zone: None,
         }
     }
 }
@@ -42,6 +51,8 @@ impl XYPlot {
             scatter_plot,
 
             plot_points: _,
+            x_datetime: _,
+            x_time_zone: _,
         } = self;
 
         ui.menu_button("View", |ui| {
@@ -82,11 +93,30 @@ impl XYPlot {
         if self.proportional {
             plot = plot.data_aspect(1.0);
         }
+        let zone = self.x_time_zone;
+        if self.x_datetime {
+            plot = plot
+                .x_axis_formatter(move |mark, _range| format_datetime(mark.value, zone))
+                .label_formatter(move |name, point| {
+                    format!(
+                        "{}\nx = {}\ny = {:.3}",
+                        name,
+                        format_datetime(point.x, zone),
+                        point.y
+                    )
+                });
+        }
         if self.coordinates {
-            plot = plot.coordinates_formatter(Corner::LeftBottom, CoordinatesFormatter::default());
+            let formatter = match self.x_datetime {
+                true => CoordinatesFormatter::new(move |point, _bounds| {
+                    format!("x: {}\ny: {:.3}", format_datetime(point.x, zone), point.y)
+                }),
+                false => CoordinatesFormatter::default(),
+            };
+            plot = plot.coordinates_formatter(Corner::LeftBottom, formatter);
         }
         plot.show(ui, |plot_ui| {
-            for (label, y_data) in self.plot_points.iter() {
+            for (label, segments) in self.plot_points.iter() {
                 let mut h = DefaultHasher::new();
                 h.write(label.as_bytes());
                 let hash = h.finish();
@@ -96,35 +126,111 @@ impl XYPlot {
                 let hue = rng.sample(Uniform::new(0.0, 1.0));
                 let color: Color32 = Hsva::new(hue, 0.8, 0.8, 1.0).into();
 
-                if self.scatter_plot {
-                    plot_ui.points(
-                        Points::new(PlotPoints::Borrowed(y_data))
-                            .color(color)
-                            .name(label),
-                    );
-                } else {
-                    plot_ui.line(
-                        Line::new(PlotPoints::Borrowed(y_data))
-                            .color(color)
-                            .style(self.line_style)
-                            .name(label),
-                    );
+                // Segments share a name, so a trace is one entry in the legend
+                for y_data in segments {
+                    if self.scatter_plot {
+                        plot_ui.points(
+                            Points::new(PlotPoints::Borrowed(y_data))
+                                .color(color)
+                                .name(label),
+                        );
+                    } else {
+                        plot_ui.line(
+                            Line::new(PlotPoints::Borrowed(y_data))
+                                .color(color)
+                                .style(self.line_style)
+                                .name(label),
+                        );
+                    }
                 }
             }
         })
         .response
     }
 
-    pub fn set_data(&mut self, x_data: &Vec<f64>, y_series: &HashMap<String, Vec<f64>>) {
+    /// Plots each trace against its own x axis if it has one, else `x`.
+    /// Missing values in either are left as gaps.
+    pub fn set_data(&mut self, x: &slang::Trace, traces: &[slang::Trace]) {
         self.plot_points.clear();
-        for (label, y_data) in y_series.iter() {
-            let points: Vec<PlotPoint> = x_data
+        (self.x_datetime, self.x_time_zone) = match &x.values {
+            slang::Values::Datetime { time_zone, .. } => (
+                true,
+                // Zones chrono doesn't know are shown as UTC
+                time_zone.as_deref().and_then(|zone| zone.parse().ok()),
+            ),
+            _ => (false, None),
+        };
+        let x_data = x.data();
+        for trace in traces {
+            let mut segments = vec![vec![]];
+            for (x, y) in trace
+                .x
+                .as_deref()
+                .unwrap_or(&x_data)
                 .iter()
-                .zip(y_data.iter())
-                .map(|(x, y)| PlotPoint { x: *x, y: *y })
-                .collect();
+                .zip(trace.data())
+            {
+                if x.is_nan() || y.is_nan() {
+                    segments.push(vec![]);
+                } else {
+                    segments.last_mut().unwrap().push(PlotPoint { x: *x, y });
+                }
+            }
+            segments.retain(|segment| !segment.is_empty());
 
-            self.plot_points.insert(label.clone(), points);
+            self.plot_points.insert(trace.name.clone(), segments);
         }
     }
 }
+
+/// `seconds` since the unix epoch as a date and time in `zone`, or UTC if
+/// there is none, with milliseconds if there are any, e.g.
+/// `2024-02-29 12:30:00.250`.
+fn format_datetime(seconds: f64, zone: Option<Tz>) -> String {
+    let millis = (seconds * 1000.0).round() as i64;
+    let Some(datetime) = DateTime::from_timestamp_millis(millis) else {
+        return seconds.to_string();
+    };
+    let format = match millis % 1000 {
+        0 => "%Y-%m-%d %H:%M:%S",
+        _ => "%Y-%m-%d %H:%M:%S%.3f",
+    };
+    match zone {
+        Some(zone) => {
+            let datetime = datetime.with_timezone(&zone);
+            format!("{} {}", datetime.format(format), datetime.format("%Z"))
+        }
+        None => datetime.format(format).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_datetime() {
+        assert_eq!(format_datetime(0.0, None), "1970-01-01 00:00:00");
+        assert_eq!(
+            format_datetime(1_709_209_800.25, None),
+            "2024-02-29 12:30:00.250"
+        );
+        // Before the epoch, counting back from it
+        assert_eq!(format_datetime(-0.75, None), "1969-12-31 23:59:59.250");
+        assert_eq!(format_datetime(-58_060_800.0, None), "1968-02-29 00:00:00");
+        assert_eq!(
+            format_datetime(-2_208_988_800.0, None),
+            "1900-01-01 00:00:00"
+        );
+
+        let los_angeles = Some(chrono_tz::America::Los_Angeles);
+        assert_eq!(
+            format_datetime(1_709_209_800.25, los_angeles),
+            "2024-02-29 04:30:00.250 PST"
+        );
+        assert_eq!(
+            format_datetime(1_720_000_000.0, los_angeles),
+            "2024-07-03 02:46:40 PDT"
+        );
+    }
+}